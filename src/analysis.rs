@@ -0,0 +1,550 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    array2d::Array2D,
+    draw::Symmetry,
+    sim::{calc_block_width, Block, Kernel, Simulation},
+};
+
+/// Classification of where a run is headed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fate {
+    /// No repetition detected (yet)
+    Evolving,
+    /// No live cells remain
+    Extinct,
+    /// The frame is identical to the previous one
+    Static,
+    /// The frame repeats with the given period (> 1)
+    Periodic(usize),
+    /// The frame repeats shifted by `offset` cells every `period` generations, as a spaceship
+    /// does. Only detected while the pattern doesn't straddle the seam of a torus.
+    Translating { period: usize, offset: (i32, i32) },
+}
+
+impl Fate {
+    pub fn is_spaceship(&self) -> bool {
+        matches!(self, Self::Translating { .. })
+    }
+}
+
+/// Classifies a run by remembering hashes of recent frames
+pub struct FateDetector {
+    /// Most recent frame first
+    history: VecDeque<FrameKey>,
+    max_period: usize,
+}
+
+/// A frame as the detector remembers it
+struct FrameKey {
+    hash: u64,
+    /// Hash of the live cells relative to `origin`, equal for translated copies
+    shape: u64,
+    /// Least x and least y of the live cells
+    origin: (i32, i32),
+}
+
+impl FateDetector {
+    /// Detect periods up to and including `max_period`
+    pub fn new(max_period: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(max_period),
+            max_period,
+        }
+    }
+
+    /// Forget past frames, e.g. after the grid was edited
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    /// Record the current frame (call once per step) and classify the run
+    pub fn observe(&mut self, sim: &dyn Simulation) -> Fate {
+        let Some(origin) = sim
+            .live_cells()
+            .reduce(|(x0, y0), (x, y)| (x0.min(x), y0.min(y)))
+        else {
+            self.reset();
+            return Fate::Extinct;
+        };
+
+        let (mut hasher, mut shape) = (DefaultHasher::new(), DefaultHasher::new());
+        for (x, y) in sim.live_cells() {
+            (x, y).hash(&mut hasher);
+            (x - origin.0, y - origin.1).hash(&mut shape);
+        }
+        let key = FrameKey {
+            hash: hasher.finish(),
+            shape: shape.finish(),
+            origin,
+        };
+
+        // An exact repeat takes precedence over a shifted one
+        let repeat = self.history.iter().position(|past| past.hash == key.hash);
+        let shifted = self
+            .history
+            .iter()
+            .enumerate()
+            .find(|(_, past)| past.shape == key.shape);
+        let fate = match (repeat, shifted) {
+            (Some(0), _) => Fate::Static,
+            (Some(idx), _) => Fate::Periodic(idx + 1),
+            (None, Some((idx, past))) => Fate::Translating {
+                period: idx + 1,
+                offset: (origin.0 - past.origin.0, origin.1 - past.origin.1),
+            },
+            (None, None) => Fate::Evolving,
+        };
+
+        self.history.push_front(key);
+        self.history.truncate(self.max_period);
+        fate
+    }
+}
+
+/// Count the 8-connected groups of live cells. On a torus, groups touching across the seam are
+/// one group.
+pub fn count_components(sim: &dyn Simulation) -> usize {
+    let (w, h) = sim.pixel_dims();
+    let wrap = |(x, y): (i32, i32)| match sim.wraps() {
+        true => (x.rem_euclid(w as i32), y.rem_euclid(h as i32)),
+        false => (x, y),
+    };
+    let mut unvisited: HashSet<(i32, i32)> = sim.live_cells().collect();
+    let mut components = 0;
+    let mut stack = vec![];
+
+    while let Some(&start) = unvisited.iter().next() {
+        components += 1;
+        unvisited.remove(&start);
+        stack.push(start);
+
+        while let Some((x, y)) = stack.pop() {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let neighbor = wrap((x + dx, y + dy));
+                    if unvisited.remove(&neighbor) {
+                        stack.push(neighbor);
+                    }
+                }
+            }
+        }
+    }
+
+    components
+}
+
+/// Mean position of the live cells, or None if there are none.
+///
+/// On a torus each axis is averaged as an angle around the circle (the circular mean), so a
+/// pattern straddling the seam gets a centroid inside it rather than halfway across the grid.
+/// This is not the arithmetic mean, but it moves exactly with the pattern under translation,
+/// which is what velocity measurements need.
+pub fn centroid(sim: &dyn Simulation) -> Option<(f64, f64)> {
+    let (w, h) = sim.pixel_dims();
+    let (w, h) = (w as f64, h as f64);
+    let mut count = 0;
+    if sim.wraps() {
+        let tau = std::f64::consts::TAU;
+        let mut sums = [0.0; 4];
+        for (x, y) in sim.live_cells() {
+            let (ax, ay) = (tau * x as f64 / w, tau * y as f64 / h);
+            sums[0] += ax.cos();
+            sums[1] += ax.sin();
+            sums[2] += ay.cos();
+            sums[3] += ay.sin();
+            count += 1;
+        }
+        let mean = |cos: f64, sin: f64, size: f64| (sin.atan2(cos) / tau * size).rem_euclid(size);
+        (count > 0).then(|| (mean(sums[0], sums[1], w), mean(sums[2], sums[3], h)))
+    } else {
+        let (mut sx, mut sy) = (0.0, 0.0);
+        for (x, y) in sim.live_cells() {
+            sx += x as f64;
+            sy += y as f64;
+            count += 1;
+        }
+        (count > 0).then(|| (sx / count as f64, sy / count as f64))
+    }
+}
+
+/// Offset from `from` to `to`. On a torus of the given (width, height), the shortest of the
+/// offsets around each axis.
+pub fn displacement(
+    from: (f64, f64),
+    to: (f64, f64),
+    dims: (usize, usize),
+    wrap: bool,
+) -> (f64, f64) {
+    let shortest = |d: f64, size: usize| {
+        let size = size as f64;
+        match wrap {
+            true => (d + size / 2.).rem_euclid(size) - size / 2.,
+            false => d,
+        }
+    };
+    (
+        shortest(to.0 - from.0, dims.0),
+        shortest(to.1 - from.1, dims.1),
+    )
+}
+
+/// Measures how fast the live cells move, by following their centroid
+pub struct VelocityTracker {
+    /// Centroids of recent frames, most recent first
+    history: VecDeque<(f64, f64)>,
+    period: usize,
+}
+
+impl VelocityTracker {
+    /// Average over `period` generations. Use a multiple of a spaceship's period, so that its
+    /// shape (and therefore its centroid's offset within it) is the same at both ends.
+    pub fn new(period: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(period + 1),
+            period: period.max(1),
+        }
+    }
+
+    /// Forget past frames, e.g. after the grid was edited
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    /// Record the current frame (call once per step). Returns the velocity in cells per
+    /// generation over the last `period` generations, once that many have been seen.
+    pub fn observe(&mut self, sim: &dyn Simulation) -> Option<(f64, f64)> {
+        let Some(current) = centroid(sim) else {
+            self.reset();
+            return None;
+        };
+        self.history.push_front(current);
+        self.history.truncate(self.period + 1);
+
+        let &oldest = self.history.get(self.period)?;
+        let (dx, dy) = displacement(oldest, current, sim.pixel_dims(), sim.wraps());
+        Some((dx / self.period as f64, dy / self.period as f64))
+    }
+}
+
+/// A non-trivial symmetry of the square
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Mirror across the vertical axis
+    MirrorX,
+    /// Mirror across the horizontal axis
+    MirrorY,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror across the main diagonal
+    Transpose,
+    /// Mirror across the other diagonal
+    AntiTranspose,
+}
+
+impl Transform {
+    pub const ALL: [Self; 7] = [
+        Self::MirrorX,
+        Self::MirrorY,
+        Self::Rotate90,
+        Self::Rotate180,
+        Self::Rotate270,
+        Self::Transpose,
+        Self::AntiTranspose,
+    ];
+
+    /// Transforms which a kernel must commute with for drawing under `symmetry` to stay symmetric
+    pub fn required_by(symmetry: Symmetry) -> &'static [Self] {
+        match symmetry {
+            Symmetry::None => &[],
+            Symmetry::Vertical => &[Self::MirrorX],
+            Symmetry::Horizontal => &[Self::MirrorY],
+            Symmetry::Quad => &[Self::MirrorX, Self::MirrorY, Self::Rotate180],
+            Symmetry::Octo => &Self::ALL,
+        }
+    }
+
+    /// Apply to a square array
+    pub fn apply(self, arr: &Array2D<bool>) -> Array2D<bool> {
+        let w = arr.width();
+        let mut out = Array2D::new(w, w);
+        for y in 0..w {
+            for x in 0..w {
+                let (u, v) = match self {
+                    Self::MirrorX => (w - 1 - x, y),
+                    Self::MirrorY => (x, w - 1 - y),
+                    Self::Rotate90 => (w - 1 - y, x),
+                    Self::Rotate180 => (w - 1 - x, w - 1 - y),
+                    Self::Rotate270 => (y, w - 1 - x),
+                    Self::Transpose => (y, x),
+                    Self::AntiTranspose => (w - 1 - y, w - 1 - x),
+                };
+                out[(u, v)] = arr[(x, y)];
+            }
+        }
+        out
+    }
+}
+
+/// Result of `check_isotropy`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IsotropyReport {
+    pub samples: usize,
+    /// Seed the samples were drawn from; checking again with it reproduces the report
+    pub seed: u64,
+    /// For each transform, the number of samples whose output wasn't transformed like the input
+    pub mismatches: Vec<(Transform, usize)>,
+}
+
+impl IsotropyReport {
+    /// True if the kernel commuted with every transform on every sample
+    pub fn is_isotropic(&self) -> bool {
+        self.mismatches.iter().all(|&(_, n)| n == 0)
+    }
+
+    /// True if no sample contradicted the transforms `symmetry` relies on
+    pub fn supports(&self, symmetry: Symmetry) -> bool {
+        Transform::required_by(symmetry).iter().all(|t| {
+            self.mismatches
+                .iter()
+                .any(|&(checked, n)| checked == *t && n == 0)
+        })
+    }
+}
+
+/// Check that a kernel treats all directions alike, by stepping random neighborhoods along with
+/// their rotations and reflections and comparing the outputs. Count-based rules with symmetric
+/// masks pass; rules reading the raw neighborhood (`GeneralKernel`) may not.
+///
+/// This is a statistical check: a kernel which passes could still be anisotropic on inputs that
+/// weren't sampled. The samples are drawn from `seed`, so a failure can be reproduced. Logs a
+/// warning for each transform that fails.
+pub fn check_isotropy(kernel: &dyn Kernel, samples: usize, seed: u64) -> IsotropyReport {
+    let w = calc_block_width(kernel);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut mismatches: Vec<(Transform, usize)> = Transform::ALL.iter().map(|&t| (t, 0)).collect();
+    for _ in 0..samples {
+        let density = rng.gen_range(0.1..0.9);
+        let data = (0..4 * w * w).map(|_| rng.gen_bool(density)).collect();
+        let window = Array2D::from_array(2 * w, data);
+        let output = kernel.step_block(split_window(&window)).0;
+
+        // The output is the center of the window, so both transform about the same point
+        for (transform, count) in &mut mismatches {
+            let transformed = kernel.step_block(split_window(&transform.apply(&window))).0;
+            if transformed != Block::from(&transform.apply(&output.to_array())) {
+                *count += 1;
+            }
+        }
+    }
+
+    for &(transform, n) in &mismatches {
+        if n > 0 {
+            log::warn!(
+                "Kernel is not invariant under {transform:?} ({n}/{samples} samples, seed {seed})"
+            );
+        }
+    }
+
+    IsotropyReport {
+        samples,
+        seed,
+        mismatches,
+    }
+}
+
+/// Split a 2w x 2w window into the four input blocks of `Kernel::step_block`
+fn split_window(window: &Array2D<bool>) -> [Block; 4] {
+    let w = window.width() / 2;
+    [(0, 0), (1, 0), (0, 1), (1, 1)]
+        .map(|(i, j)| Block::from_fn(w, w, |x, y| window[(i * w + x, j * w + y)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kernels::{basic_mnca, GeneralKernel, Life},
+        sim::Dense,
+    };
+
+    fn run(cells: &[(usize, usize)], steps: usize) -> Fate {
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        for &xy in cells {
+            sim.set_pixel(xy, true);
+        }
+
+        let mut detector = FateDetector::new(8);
+        let mut fate = Fate::Evolving;
+        for _ in 0..steps {
+            sim.step();
+            fate = detector.observe(&sim);
+        }
+        fate
+    }
+
+    #[test]
+    fn test_extinct() {
+        assert_eq!(run(&[(3, 3)], 1), Fate::Extinct);
+    }
+
+    #[test]
+    fn test_static() {
+        assert_eq!(run(&[(3, 3), (4, 3), (3, 4), (4, 4)], 3), Fate::Static);
+    }
+
+    #[test]
+    fn test_periodic() {
+        assert_eq!(run(&[(2, 3), (3, 3), (4, 3)], 4), Fate::Periodic(2));
+    }
+
+    #[test]
+    fn test_glider_is_translating() {
+        let mut sim = Dense::new(Box::new(Life), 16, 16);
+        for xy in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            sim.set_pixel((xy.0 + 4, xy.1 + 4), true);
+        }
+
+        let mut detector = FateDetector::new(8);
+        let fates: Vec<Fate> = (0..8)
+            .map(|_| {
+                sim.step();
+                detector.observe(&sim)
+            })
+            .collect();
+        // Gliders return to their shape every 4 generations, one cell along each diagonal
+        assert_eq!(fates[3], Fate::Evolving);
+        let Fate::Translating { period, offset } = fates[7] else {
+            panic!("{:?}", fates[7]);
+        };
+        assert_eq!(period, 4);
+        assert_eq!((offset.0.abs(), offset.1.abs()), (1, 1));
+        assert!(fates[7].is_spaceship());
+        assert!(!Fate::Periodic(2).is_spaceship());
+    }
+
+    #[test]
+    fn test_count_components() {
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        // Two diagonally connected cells, and a separate pair
+        for xy in [(0, 0), (1, 1), (5, 5), (5, 6)] {
+            sim.set_pixel(xy, true);
+        }
+        assert_eq!(count_components(&sim), 2);
+    }
+
+    #[test]
+    fn test_components_join_across_seam() {
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        for xy in [(0, 3), (7, 3), (7, 4)] {
+            sim.set_pixel(xy, true);
+        }
+        assert_eq!(count_components(&sim), 2);
+        sim.set_wrap(true);
+        assert_eq!(count_components(&sim), 1);
+    }
+
+    #[test]
+    fn test_centroid_across_seam() {
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        sim.set_wrap(true);
+        for xy in [(0, 2), (7, 2)] {
+            sim.set_pixel(xy, true);
+        }
+        let (x, y) = centroid(&sim).unwrap();
+        // Halfway between the two cells, across the seam
+        assert!((x - 7.5).abs() < 1e-9, "{x}");
+        assert!((y - 2.).abs() < 1e-9, "{y}");
+    }
+
+    #[test]
+    fn test_glider_velocity_across_seam() {
+        // Moving one cell in +x and -y every 4 generations, crossing both seams of the 16x16
+        // torus within 64 generations
+        let mut sim = Dense::new(Box::new(Life), 8, 8);
+        sim.set_wrap(true);
+        for xy in [(12, 13), (13, 12), (11, 11), (12, 11), (13, 11)] {
+            sim.set_pixel(xy, true);
+        }
+
+        let mut tracker = VelocityTracker::new(4);
+        let mut readings = vec![];
+        for _ in 0..64 {
+            readings.extend(tracker.observe(&sim));
+            sim.step();
+        }
+        assert_eq!(readings.len(), 60);
+        for (vx, vy) in readings {
+            assert!(
+                (vx - 0.25).abs() < 1e-9 && (vy + 0.25).abs() < 1e-9,
+                "{vx}, {vy}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_displacement() {
+        assert_eq!(
+            displacement((1., 1.), (15., 2.), (16, 16), false),
+            (14., 1.)
+        );
+        assert_eq!(displacement((1., 1.), (15., 2.), (16, 16), true), (-2., 1.));
+        assert_eq!(displacement((15., 0.), (1., 0.), (16, 16), true), (2., 0.));
+    }
+
+    #[test]
+    fn test_life_is_isotropic() {
+        let report = Life.check_isotropy(64, 376);
+        assert_eq!(report.samples, 64);
+        assert_eq!(report.seed, 376);
+        assert!(report.is_isotropic(), "{report:?}");
+        assert!(report.supports(Symmetry::Octo));
+    }
+
+    #[test]
+    fn test_layered_is_isotropic() {
+        let report = check_isotropy(&basic_mnca(), 4, 376);
+        assert!(report.is_isotropic(), "{report:?}");
+    }
+
+    #[test]
+    fn test_directional_kernel() {
+        // Every cell copies its left neighbor, which only survives a vertical flip
+        let kernel = GeneralKernel::new(|window| window[(0, 1)], 3).unwrap();
+        let report = kernel.check_isotropy(16, 376);
+        assert!(!report.is_isotropic());
+        // The same seed gives the same report
+        assert_eq!(kernel.check_isotropy(16, 376), report);
+        assert!(report.supports(Symmetry::Horizontal));
+        assert!(!report.supports(Symmetry::Vertical));
+        assert!(!report.supports(Symmetry::Quad));
+    }
+
+    #[test]
+    fn test_transforms() {
+        let arr = Array2D::from_array(2, vec![true, false, false, false]);
+        let corner = |t: Transform| {
+            let out = t.apply(&arr);
+            (0..4).find(|&i| out.data()[i]).unwrap()
+        };
+        assert_eq!(corner(Transform::MirrorX), 1);
+        assert_eq!(corner(Transform::MirrorY), 2);
+        assert_eq!(corner(Transform::Rotate180), 3);
+        assert_eq!(corner(Transform::Transpose), 0);
+        assert_eq!(corner(Transform::AntiTranspose), 3);
+
+        // Rotations compose
+        let nonsym = Array2D::from_array(3, (0..9).map(|i| i % 4 == 1).collect());
+        let twice = Transform::Rotate90.apply(&Transform::Rotate90.apply(&nonsym));
+        assert_eq!(twice, Transform::Rotate180.apply(&nonsym));
+        let back = Transform::Rotate270.apply(&Transform::Rotate90.apply(&nonsym));
+        assert_eq!(back, nonsym);
+    }
+}
@@ -1,53 +1,1036 @@
-use egui::{Frame, Rect, Rgba, Rounding, Sense, Ui, Vec2};
+use std::{sync::Arc, time::Duration};
+
+use egui::{Frame, Key, Rect, Rgba, Rounding, Sense, Stroke, Ui, Vec2};
 use rand::Rng;
 
 use crate::{
-    kernels::{basic_mnca, larger_than_life_layered_kernel, life_layered_kernel, Life, KernelCache},
-    sim::Dense,
+    analysis::{Fate, FateDetector},
+    array2d::Array2D,
+    capture::{save_screenshot, screenshot_file_name, CaptureRange, FrameRecorder},
+    draw::{line, Brush, BrushShape, Lattice, Symmetry},
+    events::{Event, EventLog},
+    kernels::{life_layered_kernel, CountHistogram, KernelCache, LayeredKernel, Param},
+    patterns::InitialPattern,
+    registry::{BuiltKernel, KernelRegistry, RegisteredKernel},
+    sim::{Dense, Kernel, Simulation},
+    sparse::Sparse,
 };
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// Fixed-size grid, best for soups filling the whole area
+    Dense,
+    /// Unbounded grid storing only live blocks, best for growing seeds
+    Sparse,
+}
+
+/// Number of generations over which neighbor counts are accumulated before being displayed
+const HISTOGRAM_WINDOW: u64 = 32;
+
+/// Size at which the kernel cache is emptied
+const CACHE_CAPACITY: usize = 1 << 30;
+
+/// Upper end of the steps per frame slider
+const MAX_STEPS_PER_FRAME: usize = 100;
+
+/// Time spent stepping towards a "go to generation" target each frame, so that long jumps
+/// don't freeze the UI
+const GOTO_BUDGET: Duration = Duration::from_millis(50);
+
+/// Largest brush radius, in cells
+const MAX_BRUSH_RADIUS: u32 = 64;
+
+/// Scroll distance which changes the brush radius by one, in points
+const SCROLL_PER_RADIUS: f32 = 40.;
+
+/// Registry name of the rule entered as an expression
+const EXPRESSION_RULE: &str = "Expression";
+
+/// Number of simulations which may be shown beside the main one
+const MAX_COMPARISONS: usize = 3;
+
+/// How the grid is sized on screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RenderScale {
+    /// Fill the available area, with fractional cell sizes
+    Stretch,
+    /// Each cell is exactly N x N device pixels, centered and scrolling if it doesn't fit
+    Integer(u32),
+}
+
+/// How live cells are drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CellStyle {
+    Filled,
+    /// Stroked rectangle, keeping individual cells legible when zoomed in
+    Outline,
+    /// Rounded dot
+    Dot,
+}
+
 pub struct TemplateApp {
-    sim: Dense,
+    sim: Box<dyn Simulation>,
+    /// Rules on offer, each named by its entry in the registry
+    registry: KernelRegistry,
+    rule: String,
+    /// Wrap the kernel in a `KernelCache`; off runs the bare kernel, to check the cache agrees
+    cached: bool,
+    backend: Backend,
+    /// Requested grid size in cells; the actual size is rounded up to whole blocks
+    grid_size: (usize, usize),
+    cell_style: CellStyle,
+    scale: RenderScale,
+    symmetry: Symmetry,
+    brush: Brush,
+    /// Scroll over the grid not yet applied to the brush radius, in points
+    brush_scroll: f32,
+    /// Size in cells of the blobs seeded by the noise pattern
+    noise_scale: u32,
+    /// Overlay the kernel's neighborhood on the hovered cell
+    show_neighborhood: bool,
+    /// Draw the normalized count of the first layer as grayscale, instead of the cells
+    show_counts: bool,
+    /// Tint the cells whose blocks the kernel only approximated in the last step
+    show_approximate: bool,
+    /// Crossfade between successive generations instead of showing discrete frames, stepping at
+    /// most `smooth_rate` times per second so that there is time to fade
+    interpolate: bool,
+    smooth_rate: f32,
+    blend: Option<Blend>,
+    /// Time (as given by egui) of the last step
+    last_step_time: f64,
+    comparisons: Vec<Comparison>,
     pause: bool,
     single_step: bool,
+    /// Step in pairs, one in each block partition, so that every frame shown is in phase A; see
+    /// `Simulation::zero_borders`. Generations are still counted in single steps.
+    whole_generations: bool,
+    /// Generation entered in the "go to" field, and the target being fast-forwarded to, if any
+    goto_generation: u64,
+    goto: Option<u64>,
+    /// Number of steps per frame when running without a frame budget
+    steps_per_frame: usize,
+    /// Keep stepping each frame until this much time has passed
+    frame_budget: Option<Duration>,
+    recording: bool,
+    log: EventLog,
+    /// Log being replayed, and the index of the next event to apply
+    replay: Option<(EventLog, usize)>,
+    /// RON of a log to load, as copied with "Copy log"
+    log_text: String,
+    log_error: Option<String>,
+    /// Directory and generations to write PNG frames for, when recording frames
+    frame_dir: String,
+    frame_range: CaptureRange,
+    frame_recorder: Option<FrameRecorder>,
+    /// Outcome of the last frame recording
+    frame_status: Option<String>,
+    #[cfg(feature = "video")]
+    video: VideoCapture,
+    generation: u64,
+    fate_detector: FateDetector,
+    /// Current classification of the run, and the generation at which it was first reached
+    fate: (Fate, u64),
+    pause_on_extinction: bool,
+    /// Stop stepping and repainting while the window is in the background
+    pause_when_unfocused: bool,
+    kernel: KernelHandles,
+    /// Source of the rule expression being edited, and why it last failed to apply
+    rule_expr: String,
+    rule_expr_error: Option<String>,
+    /// Bins from the last complete window
+    histogram_bins: Vec<Vec<u64>>,
 }
 
 impl Default for TemplateApp {
     fn default() -> Self {
-        //let kernel = Box::new(basic_mnca());
-        let kernel = Box::new(KernelCache::new(Box::new(basic_mnca())));
-        let mut sim = Dense::new(kernel, 17*3, 10*3);
+        Self::with_registry(KernelRegistry::with_builtins())
+    }
+}
 
-        let mut rng = rand::thread_rng();
-        for block in sim.data_mut().data_mut() {
-            for pixel in block.data_mut() {
-                *pixel = rng.gen_bool(0.5);
-            }
-        }
+/// A simulation shown beside the main one, which receives the same events under its own rule.
+/// Fate, histogram and parameter controls only cover the main simulation.
+struct Comparison {
+    rule: String,
+    cached: bool,
+    sim: Box<dyn Simulation>,
+    /// Also keeps the kernel's cache alive alongside the simulation
+    kernel: KernelHandles,
+    generation: u64,
+}
 
+impl Comparison {
+    /// Start from the current state of `from`
+    fn new(
+        rule: &RegisteredKernel,
+        cached: bool,
+        backend: Backend,
+        size: (usize, usize),
+        from: &dyn Simulation,
+    ) -> Self {
+        let (mut sim, kernel) = new_sim(rule, cached, backend, size);
+        let state = from.snapshot();
+        sim.for_each_pixel_mut(&mut |(x, y), cell| {
+            *cell = x < state.width() && y < state.height() && state[(x, y)];
+        });
         Self {
+            rule: rule.name().to_string(),
+            cached,
             sim,
-            pause: true,
-            single_step: false,
+            kernel,
+            generation: 0,
+        }
+    }
+
+    fn apply(&mut self, event: &Event) {
+        event.apply(&mut *self.sim);
+        match event {
+            Event::Step => self.generation += 1,
+            Event::Clear | Event::Seed(_) | Event::Pattern(_) => self.generation = 0,
+            Event::SetPixel { .. } | Event::FlipPhase => (),
         }
     }
 }
 
+/// The frame shown before the last step, and when to have faded from it to the current frame
+struct Blend {
+    previous: Array2D<bool>,
+    start: f64,
+    duration: f64,
+}
+
+impl Blend {
+    /// Progress from the previous frame (0) to the current one (1)
+    fn progress(&self, now: f64) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        ((now - self.start) / self.duration).clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Parts of the current kernel the UI inspects while the simulation owns it. Kernels which
+/// aren't layered have no histogram, parameters, fingerprint or layers to show.
+struct KernelHandles {
+    lattice: Lattice,
+    /// Neighbor counts collected by the kernel
+    histogram: Option<Arc<CountHistogram>>,
+    /// None when running the bare kernel
+    cache: Option<Arc<KernelCache>>,
+    fingerprint: Option<u64>,
+    params: Option<Arc<[Param]>>,
+    /// The kernel itself, behind the cache
+    layered: Option<Arc<LayeredKernel>>,
+}
+
+fn new_sim(
+    rule: &RegisteredKernel,
+    cached: bool,
+    backend: Backend,
+    (width, height): (usize, usize),
+) -> (Box<dyn Simulation>, KernelHandles) {
+    let (kernel, mut handles): (Box<dyn Kernel>, _) = match rule.build() {
+        BuiltKernel::Layered(mut kernel) => {
+            let histogram = kernel.count_histogram();
+            let (fingerprint, params) = (kernel.fingerprint(), kernel.params());
+            let layered = Arc::new(kernel);
+            let handles = KernelHandles {
+                lattice: rule.lattice,
+                histogram: Some(histogram),
+                cache: None,
+                fingerprint,
+                params,
+                layered: Some(layered.clone()),
+            };
+            (Box::new(layered), handles)
+        }
+        BuiltKernel::Opaque(kernel) => {
+            let handles = KernelHandles {
+                lattice: rule.lattice,
+                histogram: None,
+                cache: None,
+                fingerprint: None,
+                params: None,
+                layered: None,
+            };
+            (kernel, handles)
+        }
+    };
+    let kernel: Box<dyn Kernel> = if cached {
+        let cache = Arc::new(KernelCache::with_capacity(kernel, CACHE_CAPACITY));
+        handles.cache = Some(cache.clone());
+        Box::new(cache)
+    } else {
+        kernel
+    };
+
+    let sim: Box<dyn Simulation> = match backend {
+        Backend::Dense => {
+            let mut sim = Dense::with_pixel_dims(kernel, width, height);
+            sim.set_threads(
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(1),
+            );
+            Box::new(sim)
+        }
+        Backend::Sparse => Box::new(Sparse::with_pixel_dims(kernel, width, height)),
+    };
+    (sim, handles)
+}
+
 impl TemplateApp {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         Self::default()
     }
+
+    /// Offer the rules in `registry`, starting with the first. Panics if it is empty.
+    pub fn with_registry(registry: KernelRegistry) -> Self {
+        let rule = registry
+            .names()
+            .next()
+            .expect("the registry offers at least one rule")
+            .to_string();
+        let backend = Backend::Dense;
+        let grid_size = (816, 480);
+
+        let (sim, kernel) = new_sim(
+            registry.get(&rule).expect("just listed"),
+            true,
+            backend,
+            grid_size,
+        );
+
+        let mut inst = Self {
+            sim,
+            registry,
+            rule,
+            cached: true,
+            backend,
+            grid_size,
+            cell_style: CellStyle::Filled,
+            scale: RenderScale::Stretch,
+            symmetry: Symmetry::None,
+            brush: Brush::default(),
+            brush_scroll: 0.,
+            noise_scale: 16,
+            show_neighborhood: false,
+            show_counts: false,
+            show_approximate: false,
+            interpolate: false,
+            smooth_rate: 10.0,
+            blend: None,
+            last_step_time: 0.0,
+            comparisons: vec![],
+            pause: true,
+            single_step: false,
+            whole_generations: false,
+            goto_generation: 0,
+            goto: None,
+            steps_per_frame: 1,
+            frame_budget: None,
+            recording: false,
+            log: EventLog::new(),
+            replay: None,
+            log_text: String::new(),
+            log_error: None,
+            frame_dir: "frames".into(),
+            frame_range: CaptureRange {
+                generations: 0..1000,
+                every: 1,
+            },
+            frame_recorder: None,
+            frame_status: None,
+            #[cfg(feature = "video")]
+            video: VideoCapture::default(),
+            generation: 0,
+            fate_detector: FateDetector::new(64),
+            fate: (Fate::Evolving, 0),
+            pause_on_extinction: true,
+            pause_when_unfocused: true,
+            kernel,
+            rule_expr: "center ? n0 in 0.25..0.375 : n0 == 0.375".into(),
+            rule_expr_error: None,
+            histogram_bins: vec![],
+        };
+        inst.apply(Event::Seed(rand::thread_rng().gen()));
+
+        inst
+    }
+
+    /// Apply an event to the simulation, recording it if enabled
+    fn apply(&mut self, event: Event) {
+        event.apply(&mut *self.sim);
+        self.observe(&event);
+        for comparison in &mut self.comparisons {
+            comparison.apply(&event);
+        }
+        if self.recording {
+            self.log.push(event);
+        }
+    }
+
+    /// Step once, or with `whole_generations` for as many steps (at most two) as it takes to
+    /// return to phase A
+    fn step(&mut self) {
+        self.apply(Event::Step);
+        if self.whole_generations && !self.sim.zero_borders() {
+            self.apply(Event::Step);
+        }
+    }
+
+    /// Step for one frame, according to the frame budget or fixed steps per frame
+    fn run_steps(&mut self) {
+        match self.frame_budget {
+            Some(budget) => {
+                // At least one step is always taken, so a step longer than the budget still
+                // yields a repaint after every step
+                let stopwatch = Stopwatch::start();
+                loop {
+                    self.step();
+                    if self.pause || stopwatch.elapsed_exceeds(budget) {
+                        break;
+                    }
+                }
+            }
+            None => {
+                for _ in 0..self.steps_per_frame {
+                    self.step();
+                    if self.pause {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Step without rendering until the target generation or the frame's time budget is reached,
+    /// pausing on arrival
+    fn fast_forward(&mut self, target: u64) {
+        let stopwatch = Stopwatch::start();
+        while self.generation < target {
+            self.step();
+            if stopwatch.elapsed_exceeds(GOTO_BUDGET) {
+                break;
+            }
+        }
+        if self.generation >= target {
+            self.goto = None;
+            self.pause = true;
+        }
+    }
+
+    /// Resize the brush with the scroll wheel, taking the scroll so that it doesn't also move a
+    /// scrolling view. The scroll bars still work.
+    fn scroll_brush(&mut self, ui: &mut Ui) {
+        let delta = ui.input_mut(|i| std::mem::take(&mut i.scroll_delta)).y;
+        self.brush_scroll += delta;
+        let steps = (self.brush_scroll / SCROLL_PER_RADIUS).trunc();
+        self.brush_scroll -= steps * SCROLL_PER_RADIUS;
+        let radius = self.brush.radius as i64 + steps as i64;
+        self.brush.radius = radius.clamp(0, MAX_BRUSH_RADIUS.into()) as u32;
+    }
+
+    fn goto_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| match self.goto {
+            Some(target) => {
+                ui.label(format!("Going to {target}..."));
+                if ui.button("Cancel").clicked() {
+                    self.goto = None;
+                }
+            }
+            None => {
+                ui.add(egui::DragValue::new(&mut self.goto_generation));
+                // There's no rewinding, so only later generations can be reached
+                let ahead = self.goto_generation > self.generation;
+                if ui
+                    .add_enabled(ahead, egui::Button::new("Go to gen N"))
+                    .on_disabled_hover_text("Only later generations can be reached")
+                    .clicked()
+                {
+                    self.goto = Some(self.goto_generation);
+                }
+            }
+        });
+    }
+
+    fn speed_ui(&mut self, ui: &mut Ui) {
+        let mut use_budget = self.frame_budget.is_some();
+        ui.checkbox(&mut use_budget, "Frame budget");
+
+        if use_budget {
+            let budget = self
+                .frame_budget
+                .unwrap_or(Duration::from_secs_f32(1. / 30.));
+            let mut fps = 1. / budget.as_secs_f32();
+            ui.add(egui::Slider::new(&mut fps, 1.0..=120.0).text("Target FPS"));
+            self.frame_budget = Some(Duration::from_secs_f32(1. / fps));
+        } else {
+            self.frame_budget = None;
+            ui.add(
+                egui::Slider::new(&mut self.steps_per_frame, 1..=MAX_STEPS_PER_FRAME)
+                    .text("Steps per frame"),
+            );
+        }
+    }
+
+    /// Replace the simulation with an empty one of the current backend and size
+    fn rebuild_sim(&mut self) {
+        let (sim, kernel) = new_sim(
+            self.registry
+                .get(&self.rule)
+                .expect("rules come from the registry"),
+            self.cached,
+            self.backend,
+            self.grid_size,
+        );
+        if let (Some(new), Some(old)) = (&kernel.histogram, &self.kernel.histogram) {
+            new.set_enabled(old.is_enabled());
+        }
+        self.sim = sim;
+        self.kernel = kernel;
+        self.histogram_bins.clear();
+        self.blend = None;
+        for comparison in &mut self.comparisons {
+            *comparison = Comparison::new(
+                self.registry
+                    .get(&comparison.rule)
+                    .expect("rules come from the registry"),
+                comparison.cached,
+                self.backend,
+                self.grid_size,
+                &*self.sim,
+            );
+        }
+    }
+
+    /// Controls for the simulations shown beside the main one
+    fn comparisons_ui(&mut self, ui: &mut Ui) {
+        let mut remove = None;
+        let mut rebuild = None;
+        for (i, comparison) in self.comparisons.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("View {}:", i + 2));
+                let rule_changed = rule_picker(ui, i + 1, &self.registry, &mut comparison.rule);
+                if ui.checkbox(&mut comparison.cached, "Cached").changed() || rule_changed {
+                    rebuild = Some(i);
+                }
+                if ui.small_button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+            ui.label(format!("Generation: {}", comparison.generation));
+        }
+
+        if let Some(i) = rebuild {
+            let Comparison { rule, cached, .. } = &self.comparisons[i];
+            let rule = self
+                .registry
+                .get(rule)
+                .expect("rules come from the registry");
+            self.comparisons[i] =
+                Comparison::new(rule, *cached, self.backend, self.grid_size, &*self.sim);
+        }
+        if let Some(i) = remove {
+            self.comparisons.remove(i);
+        }
+
+        let can_add = self.comparisons.len() < MAX_COMPARISONS;
+        if ui
+            .add_enabled(can_add, egui::Button::new("Add view"))
+            .on_hover_text("Copy the current state into a new view, to step under another rule")
+            .clicked()
+        {
+            let rule = self
+                .registry
+                .next_after(&self.rule)
+                .expect("rules come from the registry");
+            let comparison =
+                Comparison::new(rule, self.cached, self.backend, self.grid_size, &*self.sim);
+            self.comparisons.push(comparison);
+        }
+    }
+
+    /// Update the generation count and classification after an event was applied
+    fn observe(&mut self, event: &Event) {
+        match event {
+            Event::Step => {
+                self.generation += 1;
+                self.record_frame();
+                #[cfg(feature = "video")]
+                self.video.record(&*self.sim);
+                if let Some(histogram) = &self.kernel.histogram {
+                    if histogram.is_enabled() && self.generation.is_multiple_of(HISTOGRAM_WINDOW) {
+                        self.histogram_bins = histogram.bins();
+                        histogram.clear();
+                    }
+                }
+                let fate = self.fate_detector.observe(&*self.sim);
+                if fate != self.fate.0 {
+                    self.fate = (fate, self.generation);
+                    if fate == Fate::Extinct && self.pause_on_extinction {
+                        self.pause = true;
+                    }
+                }
+            }
+            Event::SetPixel { .. } | Event::FlipPhase => self.fate_detector.reset(),
+            Event::Clear | Event::Seed(_) | Event::Pattern(_) => {
+                self.blend = None;
+                self.generation = 0;
+                self.fate_detector.reset();
+                self.fate = (Fate::Evolving, 0);
+            }
+        }
+    }
+
+    /// Pass the current state to the frame recorder, stopping it once the range is covered
+    fn record_frame(&mut self) {
+        if let Some(recorder) = &mut self.frame_recorder {
+            recorder.record(self.generation, &*self.sim);
+            if recorder.is_complete(self.generation) {
+                self.stop_frames();
+            }
+        }
+    }
+
+    /// Wait for the frame writer to catch up and report how it went
+    fn stop_frames(&mut self) {
+        if let Some(recorder) = self.frame_recorder.take() {
+            self.frame_status = Some(match recorder.finish() {
+                Ok(n) => format!("Wrote {n} frames to {}", self.frame_dir),
+                Err(e) => format!("Frame recording failed: {e}"),
+            });
+        }
+    }
+
+    fn frames_ui(&mut self, ui: &mut Ui) {
+        let mut record = self.frame_recorder.is_some();
+        if ui.checkbox(&mut record, "Record frames").changed() {
+            if record {
+                match FrameRecorder::start(
+                    &self.frame_dir,
+                    self.frame_range.clone(),
+                    self.kernel.fingerprint,
+                ) {
+                    Ok(recorder) => {
+                        self.frame_recorder = Some(recorder);
+                        self.frame_status = None;
+                        self.record_frame();
+                    }
+                    Err(e) => self.frame_status = Some(format!("Frame recording failed: {e}")),
+                }
+            } else {
+                self.stop_frames();
+            }
+        }
+
+        ui.add_enabled_ui(self.frame_recorder.is_none(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Directory:");
+                ui.text_edit_singleline(&mut self.frame_dir);
+            });
+            let range = &mut self.frame_range;
+            ui.horizontal(|ui| {
+                ui.label("Generations:");
+                ui.add(egui::DragValue::new(&mut range.generations.start));
+                ui.label("to");
+                ui.add(
+                    egui::DragValue::new(&mut range.generations.end)
+                        .clamp_range(range.generations.start + 1..=u64::MAX),
+                );
+            });
+            ui.add(
+                egui::DragValue::new(&mut range.every)
+                    .clamp_range(1..=u64::MAX)
+                    .prefix("Every "),
+            );
+        });
+
+        if let Some(recorder) = &self.frame_recorder {
+            ui.label(format!("Captured {} frames", recorder.captured()));
+        } else if let Some(status) = &self.frame_status {
+            ui.label(status);
+        }
+    }
+
+    /// How the requested size was laid out in blocks
+    fn grid_info_ui(&self, ui: &mut Ui) {
+        let order = self.sim.order();
+        let block = 1 << order;
+        let (w, h) = self.sim.pixel_dims();
+        egui::Grid::new("grid_info").show(ui, |ui| {
+            for (name, value) in [
+                ("Kernel order", order.to_string()),
+                ("Block width", format!("{block} px")),
+                ("Blocks", format!("{} x {}", w / block, h / block)),
+                ("Pixels", format!("{w} x {h}")),
+                ("Cells", (w * h).to_string()),
+            ] {
+                ui.label(name);
+                ui.label(value);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn fate_ui(&mut self, ui: &mut Ui) {
+        if self.whole_generations {
+            let half = if self.sim.zero_borders() {
+                ""
+            } else {
+                " + half"
+            };
+            ui.label(format!(
+                "Generation: {} (whole: {}{half})",
+                self.generation,
+                self.generation / 2
+            ));
+        } else {
+            ui.label(format!("Generation: {}", self.generation));
+        }
+        ui.label(format!("Population: {}", self.sim.population()));
+
+        let (fate, generation) = self.fate;
+        let text = match fate {
+            Fate::Evolving => "Evolving".to_string(),
+            Fate::Extinct => format!("Extinct at generation {generation}"),
+            Fate::Static => format!("Static since generation {generation}"),
+            Fate::Periodic(period) => {
+                format!("Period {period} since generation {generation}")
+            }
+            Fate::Translating { period, offset } => format!(
+                "Moving {offset:?} every {period} generations since generation {generation}"
+            ),
+        };
+        let color = match fate {
+            Fate::Evolving => ui.visuals().text_color(),
+            Fate::Extinct => ui.visuals().error_fg_color,
+            Fate::Static | Fate::Periodic(_) | Fate::Translating { .. } => {
+                ui.visuals().warn_fg_color
+            }
+        };
+        ui.heading(egui::RichText::new(text).color(color));
+
+        ui.checkbox(&mut self.pause_on_extinction, "Pause on extinction");
+    }
+
+    /// Write the current state to a timestamped PNG in the frame directory, at the cell size of
+    /// the view if it has one
+    fn screenshot(&mut self) {
+        let scale = match self.scale {
+            RenderScale::Stretch => 1,
+            RenderScale::Integer(n) => n as usize,
+        };
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let dir = std::path::Path::new(&self.frame_dir);
+        let path = dir.join(screenshot_file_name(self.kernel.fingerprint, secs));
+        let saved = std::fs::create_dir_all(dir)
+            .map_err(Into::into)
+            .and_then(|()| save_screenshot(&*self.sim, &path, scale));
+        self.frame_status = Some(match saved {
+            Ok(()) => format!("Saved {}", path.display()),
+            Err(e) => format!("Screenshot failed: {e}"),
+        });
+    }
+
+    /// Space: pause, right arrow or `.`: step, C: clear, R: randomize, +/-: steps per frame,
+    /// P: screenshot. Ignored while a text field has focus.
+    fn shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let (pause, step, clear, seed, faster, slower, screenshot) = ctx.input(|i| {
+            let period = i
+                .events
+                .iter()
+                .any(|e| matches!(e, egui::Event::Text(t) if t == "."));
+            (
+                i.key_pressed(Key::Space),
+                i.key_pressed(Key::ArrowRight) || period,
+                i.key_pressed(Key::C),
+                i.key_pressed(Key::R),
+                i.key_pressed(Key::PlusEquals),
+                i.key_pressed(Key::Minus),
+                i.key_pressed(Key::P),
+            )
+        });
+
+        self.pause ^= pause;
+        self.single_step |= step;
+        if clear {
+            self.apply(Event::Clear);
+        }
+        if seed {
+            self.apply(Event::Seed(rand::thread_rng().gen()));
+        }
+        if faster {
+            self.steps_per_frame = (self.steps_per_frame + 1).min(MAX_STEPS_PER_FRAME);
+        }
+        if slower {
+            self.steps_per_frame = self.steps_per_frame.saturating_sub(1).max(1);
+        }
+        if screenshot {
+            self.screenshot();
+        }
+    }
+
+    /// Sliders for the live parameters of the rule, if it has any
+    fn params_ui(&mut self, ui: &mut Ui) {
+        let Some(params) = &self.kernel.params else {
+            return;
+        };
+
+        let mut changed = false;
+        for param in params.iter() {
+            let mut value = param.get();
+            let slider = egui::Slider::new(&mut value, param.range())
+                .text(param.name())
+                .fixed_decimals(3);
+            if ui.add(slider).changed() {
+                param.set(value);
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return;
+        }
+        // Memoized blocks were computed under the old values, and skipped blocks would keep them
+        if let Some(cache) = &self.kernel.cache {
+            cache.clear();
+        }
+        self.sim.invalidate();
+    }
+
+    /// Text box for a rule expression over the current rule's layers (or Life's, if it has
+    /// none), registered as the "Expression" rule. Returns whether that rule was just selected.
+    fn rule_expr_ui(&mut self, ui: &mut Ui) -> bool {
+        let apply = ui
+            .horizontal(|ui| {
+                ui.label("Expression:");
+                let edit = ui.text_edit_singleline(&mut self.rule_expr).on_hover_text(
+                    "center: the cell's state; n0, n1, ...: each layer's normalized count.\n\
+                        e.g. center ? n0 in 0.25..0.375 : n0 == 0.375",
+                );
+                let enter = edit.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                ui.button("Apply").clicked() || enter
+            })
+            .inner;
+        if let Some(error) = &self.rule_expr_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        if !apply {
+            return false;
+        }
+
+        let layers = match &self.kernel.layered {
+            Some(layered) => layered.layers().to_vec(),
+            None => life_layered_kernel().layers().to_vec(),
+        };
+        if let Err(e) = LayeredKernel::with_expr(&self.rule_expr, layers.clone()) {
+            self.rule_expr_error = Some(e.to_string());
+            return false;
+        }
+        self.rule_expr_error = None;
+        let source = self.rule_expr.clone();
+        self.registry.register_layered(
+            EXPRESSION_RULE,
+            Box::new(move || {
+                LayeredKernel::with_expr(&source, layers.clone()).expect("checked when applied")
+            }),
+        );
+        self.rule = EXPRESSION_RULE.to_string();
+        true
+    }
+
+    /// Key to the colors of the overlays being shown
+    fn legend_ui(&self, ui: &mut Ui) {
+        if self.show_counts && self.kernel.layered.is_some() {
+            ui.horizontal(|ui| {
+                ui.label("0");
+                let (rect, _) = ui.allocate_exact_size(Vec2::new(96., 12.), Sense::hover());
+                let step = rect.width() / LEGEND_STEPS as f32;
+                for i in 0..LEGEND_STEPS {
+                    let min = rect.min + Vec2::new(i as f32 * step, 0.);
+                    let value = i as f32 / (LEGEND_STEPS - 1) as f32;
+                    ui.painter().rect_filled(
+                        Rect::from_min_size(min, Vec2::new(step, rect.height())),
+                        Rounding::none(),
+                        Rgba::from_gray(value),
+                    );
+                }
+                ui.label("1+");
+                ui.label("normalized count");
+            });
+        }
+        if self.show_approximate {
+            legend_swatch(ui, APPROXIMATE_TINT, "Approximated");
+        }
+        if let (true, Some(layered)) = (self.show_neighborhood, &self.kernel.layered) {
+            ui.horizontal_wrapped(|ui| {
+                for i in 0..layered.layers().len() {
+                    legend_swatch(ui, layer_color(i), &format!("Layer {i}"));
+                }
+            });
+        }
+    }
+
+    fn histogram_ui(&mut self, ui: &mut Ui) {
+        let Some(histogram) = &self.kernel.histogram else {
+            return;
+        };
+        let mut enabled = histogram.is_enabled();
+        ui.checkbox(&mut enabled, "Count histogram");
+        if enabled != histogram.is_enabled() {
+            histogram.set_enabled(enabled);
+            histogram.clear();
+            self.histogram_bins.clear();
+        }
+        if !enabled {
+            return;
+        }
+
+        if self.histogram_bins.is_empty() {
+            ui.label(format!("Collecting over {HISTOGRAM_WINDOW} generations..."));
+        }
+
+        for (layer, bins) in self.histogram_bins.iter().enumerate() {
+            ui.label(format!("Layer {layer}, normalized count 0 to 1"));
+            let (rect, _) =
+                ui.allocate_exact_size(Vec2::new(ui.available_width(), 48.), Sense::hover());
+            let max = bins.iter().copied().max().unwrap_or(0).max(1) as f32;
+            let bar_width = rect.width() / bins.len() as f32;
+            for (i, &n) in bins.iter().enumerate() {
+                let height = rect.height() * n as f32 / max;
+                let bar = Rect::from_min_max(
+                    rect.left_bottom() + Vec2::new(i as f32 * bar_width, -height),
+                    rect.left_bottom() + Vec2::new((i + 1) as f32 * bar_width - 1., 0.),
+                );
+                ui.painter()
+                    .rect_filled(bar, Rounding::none(), ui.visuals().text_color());
+            }
+        }
+    }
+
+    /// Play `log` back onto a fresh simulation, one event per frame
+    fn start_replay(&mut self, log: EventLog) {
+        self.rebuild_sim();
+        self.replay = Some((log, 0));
+        self.pause = true;
+    }
+
+    /// Parse the pasted log and replay it, or report why it can't be
+    fn load_log(&mut self) {
+        match EventLog::from_ron(&self.log_text) {
+            Ok(log) => {
+                self.log_error = None;
+                self.log = log.clone();
+                self.start_replay(log);
+            }
+            Err(e) => self.log_error = Some(format!("Couldn't load log: {e}")),
+        }
+    }
+
+    fn record_replay_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if self.recording {
+                if ui.button("Stop recording").clicked() {
+                    self.recording = false;
+                }
+            } else if ui.button("Record").clicked() {
+                // Start from a deterministic state, so that the log can reconstruct the session
+                self.log = EventLog::new();
+                self.recording = true;
+                self.apply(Event::Seed(rand::thread_rng().gen()));
+            }
+
+            let can_replay = !self.recording && !self.log.events().is_empty();
+            if ui
+                .add_enabled(can_replay, egui::Button::new("Replay"))
+                .clicked()
+            {
+                self.start_replay(self.log.clone());
+            }
+
+            if ui.button("Copy log").clicked() {
+                ui.output_mut(|o| o.copied_text = self.log.to_ron());
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Log:");
+            ui.text_edit_singleline(&mut self.log_text)
+                .on_hover_text("Paste a log copied with \"Copy log\" to replay it");
+            let can_load = !self.recording && !self.log_text.is_empty();
+            if ui
+                .add_enabled(can_load, egui::Button::new("Load"))
+                .clicked()
+            {
+                self.load_log();
+            }
+        });
+        if let Some(error) = &self.log_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+
+        if let Some((log, idx)) = &self.replay {
+            ui.label(format!("Replaying {}/{}", idx, log.events().len()));
+        } else if self.recording {
+            ui.label(format!("Recorded {} events", self.log.events().len()));
+        }
+    }
 }
 
 impl eframe::App for TemplateApp {
     /// Called each time the UI needs repainting, which may be many times per second.
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.request_repaint();
+        // Leaves `pause` untouched, so the previous state resumes when focus returns
+        let background = self.pause_when_unfocused && !ctx.input(|i| i.focused);
+        self.shortcuts(ctx);
+        if !background {
+            ctx.request_repaint();
+            let now = ctx.input(|i| i.time);
+            let period = 1.0 / self.smooth_rate as f64;
+            let due = !self.interpolate || now - self.last_step_time >= period;
+            let stepping = self.replay.is_some() || self.single_step || (!self.pause && due);
+            let previous = (self.interpolate && stepping).then(|| self.sim.snapshot());
+            let generation = self.generation;
 
-        if !self.pause || self.single_step {
-            self.sim.step();
-            self.single_step = false;
+            if let Some((log, idx)) = &mut self.replay {
+                // Play back one event per frame
+                let next = log.events().get(*idx).cloned();
+                *idx += 1;
+                match next {
+                    Some(event) => {
+                        event.apply(&mut *self.sim);
+                        self.observe(&event);
+                        for comparison in &mut self.comparisons {
+                            comparison.apply(&event);
+                        }
+                    }
+                    None => self.replay = None,
+                }
+            } else if self.single_step {
+                self.step();
+                self.single_step = false;
+            } else if let Some(target) = self.goto {
+                self.fast_forward(target);
+            } else if !self.pause && due {
+                self.run_steps();
+            }
+
+            if self.generation != generation {
+                self.blend = previous.map(|previous| Blend {
+                    previous,
+                    start: now,
+                    duration: period,
+                });
+                self.last_step_time = now;
+            }
         }
 
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
@@ -55,36 +1038,445 @@ impl eframe::App for TemplateApp {
                 ui.checkbox(&mut self.pause, "Pause");
                 self.single_step |= ui.button("Step").clicked();
             });
+            self.goto_ui(ui);
+            ui.horizontal(|ui| {
+                // Blocks are offset by half a block on alternate generations
+                let phase = if self.sim.zero_borders() { "A" } else { "B" };
+                ui.label(format!("Phase: {phase}"));
+                if ui
+                    .button("Flip phase")
+                    .on_hover_text("Re-align blocks without advancing a generation")
+                    .clicked()
+                {
+                    self.apply(Event::FlipPhase);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.whole_generations, "Whole generations")
+                    .on_hover_text(
+                        "Step in pairs, once in each block partition, as Margolus rules like \
+                        billiard balls and sand require",
+                    );
+                if self.whole_generations
+                    && ui
+                        .button("Half step")
+                        .on_hover_text("Step once, to see the other partition")
+                        .clicked()
+                {
+                    self.apply(Event::Step);
+                }
+            });
+            ui.checkbox(&mut self.pause_when_unfocused, "Pause in background");
+
+            ui.horizontal(|ui| {
+                if ui.button("Clear").clicked() {
+                    self.apply(Event::Clear);
+                }
+                if ui.button("Randomize").clicked() {
+                    self.apply(Event::Seed(rand::thread_rng().gen()));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let mut pattern = None;
+                egui::ComboBox::from_id_source("pattern")
+                    .selected_text("Load pattern")
+                    .show_ui(ui, |ui| {
+                        for p in InitialPattern::ALL {
+                            if ui.selectable_label(false, p.name()).clicked() {
+                                pattern = Some(p);
+                            }
+                        }
+                    });
+                if let Some(pattern) = pattern {
+                    let pattern = match pattern {
+                        InitialPattern::Noise { .. } => InitialPattern::Noise {
+                            seed: rand::thread_rng().gen(),
+                            scale: self.noise_scale,
+                        },
+                        pattern => pattern,
+                    };
+                    self.apply(Event::Pattern(pattern));
+                }
+                ui.add(
+                    egui::DragValue::new(&mut self.noise_scale)
+                        .clamp_range(1..=256)
+                        .prefix("Noise scale "),
+                );
+            });
+
+            self.speed_ui(ui);
+            self.record_replay_ui(ui);
+            self.frames_ui(ui);
+            #[cfg(feature = "video")]
+            self.video.ui(&*self.sim, ui);
+
+            ui.separator();
+            self.fate_ui(ui);
+            ui.separator();
+            self.histogram_ui(ui);
+            ui.separator();
+            self.comparisons_ui(ui);
+            ui.separator();
+
+            let mut rule_changed = ui
+                .horizontal(|ui| {
+                    ui.label("Rule:");
+                    rule_picker(ui, 0, &self.registry, &mut self.rule)
+                })
+                .inner;
+            self.params_ui(ui);
+            rule_changed |= self.rule_expr_ui(ui);
+
+            let prev_backend = self.backend;
+            ui.horizontal(|ui| {
+                ui.label("Backend:");
+                ui.selectable_value(&mut self.backend, Backend::Dense, "Dense");
+                ui.selectable_value(&mut self.backend, Backend::Sparse, "Sparse");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Cells:");
+                ui.selectable_value(&mut self.cell_style, CellStyle::Filled, "Filled");
+                ui.selectable_value(&mut self.cell_style, CellStyle::Outline, "Outline");
+                ui.selectable_value(&mut self.cell_style, CellStyle::Dot, "Dot");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Scale:");
+                ui.selectable_value(&mut self.scale, RenderScale::Stretch, "Fit");
+                for n in [1, 2, 4, 8] {
+                    ui.selectable_value(&mut self.scale, RenderScale::Integer(n), format!("{n}x"));
+                }
+            });
+
+            // Only layered kernels have neighborhoods to show
+            ui.add_enabled_ui(self.kernel.layered.is_some(), |ui| {
+                ui.checkbox(&mut self.show_neighborhood, "Show neighborhood");
+                ui.checkbox(&mut self.show_counts, "Show layer 0 counts");
+            });
+            ui.checkbox(&mut self.show_approximate, "Approximation heatmap")
+                .on_hover_text("Tint blocks which the kernel approximated rather than computed");
+            self.legend_ui(ui);
+            if ui
+                .checkbox(&mut self.interpolate, "Smooth frames")
+                .changed()
+            {
+                self.blend = None;
+            }
+            if self.interpolate {
+                ui.add(egui::Slider::new(&mut self.smooth_rate, 1.0..=60.0).text("Generations/s"));
+            }
+
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Symmetry:");
+                for (symmetry, name) in [
+                    (Symmetry::None, "None"),
+                    (Symmetry::Vertical, "Vertical"),
+                    (Symmetry::Horizontal, "Horizontal"),
+                    (Symmetry::Quad, "4-fold"),
+                    (Symmetry::Octo, "8-fold"),
+                ] {
+                    ui.selectable_value(&mut self.symmetry, symmetry, name);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Brush:").on_hover_text(
+                    "Click to toggle a cell, or drag to paint. Right drag or shift erases.",
+                );
+                ui.selectable_value(&mut self.brush.shape, BrushShape::Disk, "Disk");
+                ui.selectable_value(&mut self.brush.shape, BrushShape::Ring, "Ring");
+                ui.add(egui::Slider::new(
+                    &mut self.brush.radius,
+                    0..=MAX_BRUSH_RADIUS,
+                ))
+                .on_hover_text("Radius in cells. Scroll over the grid to change it.");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Size:");
+                ui.add(egui::DragValue::new(&mut self.grid_size.0).clamp_range(1..=4096));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut self.grid_size.1).clamp_range(1..=4096));
+            });
+            self.grid_info_ui(ui);
+            if let Some(fingerprint) = self.kernel.fingerprint {
+                ui.label(format!("Rule: {fingerprint:016x}"));
+            }
+            let mib = |bytes: usize| bytes as f32 / (1 << 20) as f32;
+            let toggle_cache = ui
+                .checkbox(&mut self.cached, "Kernel cache")
+                .on_hover_text("Add a view with the other setting to compare the two")
+                .changed();
+            if let Some(cache) = &self.kernel.cache {
+                let stats = cache.stats();
+                ui.label(format!(
+                    "Cache: {:.1} / {:.0} MiB",
+                    mib(stats.bytes),
+                    mib(CACHE_CAPACITY)
+                ));
+                let rate = stats
+                    .hit_rate()
+                    .map_or("-".into(), |r| format!("{:.1}%", r * 100.));
+                ui.label(format!("Hit rate: {rate} ({} misses)", stats.misses))
+                    .on_hover_text(format!(
+                        "{} solutions, {} jumps, {} stored blocks, {} keys",
+                        stats.solutions_len, stats.jumps_len, stats.values_len, stats.cache_len
+                    ));
+            }
+            let resize = ui.button("Resize").clicked();
+
+            if rule_changed || self.backend != prev_backend || resize || toggle_cache {
+                self.rebuild_sim();
+                self.apply(Event::Seed(rand::thread_rng().gen()));
+            }
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            Frame::canvas(ui.style()).show(ui, |ui| {
-                sim_widget(&mut self.sim, ui);
+            let tiles = tiles(ui.available_rect_before_wrap(), 1 + self.comparisons.len());
+            if ui.rect_contains_pointer(tiles[0]) {
+                self.scroll_brush(ui);
+            }
+
+            let counts = self
+                .kernel
+                .layered
+                .as_ref()
+                .filter(|_| self.show_counts)
+                .map(|layered| layered.count_field(&self.sim.snapshot(), 0));
+            let approximate = self
+                .show_approximate
+                .then(|| self.sim.approximate_cells())
+                .flatten();
+            let overlays = Overlays {
+                layers: self
+                    .kernel
+                    .layered
+                    .as_ref()
+                    .filter(|_| self.show_neighborhood)
+                    .map(|layered| layered.layers()),
+                counts: counts.as_ref(),
+                approximate: approximate.as_ref(),
+                brush: Some(self.brush),
+                previous: self.blend.as_ref().and_then(|blend| {
+                    let fresh =
+                        (blend.previous.width(), blend.previous.height()) == self.sim.pixel_dims();
+                    fresh.then(|| (&blend.previous, blend.progress(ctx.input(|i| i.time))))
+                }),
+            };
+            let (style, scale) = (self.cell_style, self.scale);
+            let view = |ui: &mut Ui,
+                        id: usize,
+                        sim: &dyn Simulation,
+                        lattice: Lattice,
+                        overlays: &Overlays<'_>| {
+                let widget = |ui: &mut Ui| {
+                    Frame::canvas(ui.style())
+                        .show(ui, |ui| {
+                            sim_widget(sim, lattice, style, scale, overlays, ui)
+                        })
+                        .inner
+                };
+                match scale {
+                    RenderScale::Stretch => widget(ui),
+                    RenderScale::Integer(_) => {
+                        egui::ScrollArea::both()
+                            .id_source(id)
+                            .auto_shrink([false; 2])
+                            .show(ui, widget)
+                            .inner
+                    }
+                }
+            };
+
+            let paint = ui
+                .allocate_ui_at_rect(tiles[0], |ui| {
+                    view(ui, 0, &*self.sim, self.kernel.lattice, &overlays)
+                })
+                .inner;
+            // Painting on the other views isn't supported, but edits to the main view are mirrored
+            let plain = Overlays {
+                layers: None,
+                counts: None,
+                approximate: None,
+                brush: None,
+                previous: None,
+            };
+            for (i, (comparison, &tile)) in self.comparisons.iter().zip(&tiles[1..]).enumerate() {
+                ui.allocate_ui_at_rect(tile, |ui| {
+                    view(
+                        ui,
+                        i + 1,
+                        &*comparison.sim,
+                        comparison.kernel.lattice,
+                        &plain,
+                    )
+                });
+            }
+
+            if let Some((path, value)) = paint {
+                let dims = self.sim.pixel_dims();
+                for pos in path {
+                    for cell in self.brush.cells(pos, dims) {
+                        for (x, y) in self.symmetry.images(cell, dims) {
+                            if self.sim.get_pixel((x, y)) != value {
+                                self.apply(Event::SetPixel { x, y, value });
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// A square of `color` over the canvas background, then a label
+fn legend_swatch(ui: &mut Ui, color: Rgba, label: &str) {
+    ui.horizontal(|ui| {
+        let (rect, _) = ui.allocate_exact_size(Vec2::splat(12.), Sense::hover());
+        let visuals = ui.visuals();
+        ui.painter()
+            .rect_filled(rect, Rounding::none(), visuals.extreme_bg_color);
+        ui.painter().rect_filled(rect, Rounding::none(), color);
+        ui.label(label);
+    });
+}
+
+/// Streaming every generation to a video file while enabled
+#[cfg(feature = "video")]
+#[derive(Default)]
+struct VideoCapture {
+    settings: crate::video::VideoSettings,
+    writer: Option<crate::video::VideoWriter>,
+    /// Outcome of the last recording
+    status: Option<String>,
+}
+
+#[cfg(feature = "video")]
+impl VideoCapture {
+    fn record(&mut self, sim: &dyn Simulation) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.record(sim) {
+                self.stop();
+                self.status = Some(format!("Video recording stopped: {e}"));
+            }
+        }
+    }
+
+    /// Wait for ffmpeg to finish the file and report how it went
+    fn stop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            self.status = Some(match writer.finish() {
+                Ok(n) => format!("Wrote {n} frames to {}", self.settings.path.display()),
+                Err(e) => e.to_string(),
+            });
+        }
+    }
+
+    fn ui(&mut self, sim: &dyn Simulation, ui: &mut Ui) {
+        let mut record = self.writer.is_some();
+        if ui.checkbox(&mut record, "Record video").changed() {
+            if record {
+                match crate::video::VideoWriter::start(sim.pixel_dims(), &self.settings) {
+                    Ok(writer) => {
+                        self.writer = Some(writer);
+                        self.status = None;
+                        self.record(sim);
+                    }
+                    Err(e) => self.status = Some(e.to_string()),
+                }
+            } else {
+                self.stop();
+            }
+        }
+
+        ui.add_enabled_ui(self.writer.is_none(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                let mut path = self.settings.path.display().to_string();
+                if ui.text_edit_singleline(&mut path).changed() {
+                    self.settings.path = path.into();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut self.settings.fps)
+                        .clamp_range(1..=240)
+                        .suffix(" fps"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut self.settings.scale)
+                        .clamp_range(1..=16)
+                        .prefix("Pixels per cell: "),
+                );
             });
         });
+
+        if let Some(writer) = &self.writer {
+            ui.label(format!("Sent {} frames", writer.captured()));
+        } else if let Some(status) = &self.status {
+            ui.label(status);
+        }
     }
 }
 
+/// Dropdown of the registered rules, returning whether the selection changed
+fn rule_picker(ui: &mut Ui, id: usize, registry: &KernelRegistry, rule: &mut String) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_id_source(("rule", id))
+        .selected_text(rule.as_str())
+        .show_ui(ui, |ui| {
+            for name in registry.names() {
+                changed |= ui.selectable_value(rule, name.to_string(), name).changed();
+            }
+        });
+    changed
+}
+
+/// Split an area into `n` equal tiles, in rows of up to two
+fn tiles(area: Rect, n: usize) -> Vec<Rect> {
+    let cols = n.min(2);
+    let rows = n.div_ceil(cols);
+    let size = Vec2::new(area.width() / cols as f32, area.height() / rows as f32);
+    (0..n)
+        .map(|i| {
+            let offset = Vec2::new((i % cols) as f32, (i / cols) as f32) * size;
+            Rect::from_min_size(area.min + offset, size)
+        })
+        .collect()
+}
+
 /// Maps sim coordinates to/from egui coordinates
 struct CoordinateMapping {
     width: f32,
     height: f32,
+    /// Horizontal offset of each row, in cells: 0.5 for a hex lattice in axial coordinates, where
+    /// the grid is drawn as a parallelogram
+    shear: f32,
     area: Rect,
 }
 
 impl CoordinateMapping {
-    pub fn new(width: usize, height: usize, area: Rect) -> Self {
+    pub fn new(width: usize, height: usize, lattice: Lattice, area: Rect) -> Self {
         Self {
             width: width as f32,
             height: height as f32,
+            shear: lattice.shear(),
             area,
         }
     }
 
+    /// Number of cell widths spanned by the area
+    fn columns(&self) -> f32 {
+        self.width + self.shear * (self.height - 1.).max(0.)
+    }
+
     pub fn sim_to_egui(&self, pt: (usize, usize)) -> egui::Pos2 {
         let (x, y) = pt;
+        let x = x as f32 + self.shear * y as f32;
         egui::Pos2::new(
-            (x as f32 / self.width) * self.area.width(),
+            (x / self.columns()) * self.area.width(),
             (1. - y as f32 / self.height) * self.area.height(),
         ) + self.area.min.to_vec2()
     }
@@ -92,40 +1484,399 @@ impl CoordinateMapping {
     pub fn sim_to_egui_vect(&self, pt: (usize, usize)) -> egui::Vec2 {
         let (x, y) = pt;
         Vec2::new(
-            (x as f32 / self.width) * self.area.width(),
+            (x as f32 / self.columns()) * self.area.width(),
             (y as f32 / self.height) * self.area.height(),
         )
     }
 
-    /*
-    pub fn egui_to_sim(&self, pt: egui::Pos2) -> (usize, usize) {
-        glam::Vec2::new(
-            (pt.x / self.area.width()) * self.width,
-            (1. - pt.y / self.area.height()) * self.height,
+    /// Screen area covered by a cell. Y is flipped, so the top-left corner of the cell is at the
+    /// next row up.
+    pub fn cell_rect(&self, (i, j): (usize, usize)) -> Rect {
+        let shift = Vec2::new(-self.shear * self.sim_to_egui_vect((1, 0)).x, 0.);
+        Rect::from_min_size(
+            self.sim_to_egui((i, j + 1)) + shift,
+            self.sim_to_egui_vect((1, 1)),
         )
     }
-    */
+
+    /// Returns the cell under the given point, which may be outside the grid
+    pub fn egui_to_sim(&self, pt: egui::Pos2) -> (i32, i32) {
+        let pt = pt - self.area.min.to_vec2();
+        let y = ((1. - pt.y / self.area.height()) * self.height).floor();
+        let x = (pt.x / self.area.width()) * self.columns() - self.shear * y;
+        (x.floor() as i32, y as i32)
+    }
+
+    /// Returns the cell under the given point, if it lies within the grid
+    pub fn egui_to_sim_checked(&self, pt: egui::Pos2) -> Option<(usize, usize)> {
+        let (x, y) = self.egui_to_sim(pt);
+        let in_bounds = (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y);
+        in_bounds.then_some((x as usize, y as usize))
+    }
 }
 
-fn sim_widget(sim: &mut Dense, ui: &mut Ui) {
-    let (widget_area, _response) =
-        ui.allocate_exact_size(ui.available_size(), Sense::click_and_drag());
+/// Colors of successive neighborhood layers in the hover overlay
+const LAYER_COLORS: [Rgba; 4] = [
+    Rgba::from_rgb(1.0, 0.3, 0.3),
+    Rgba::from_rgb(0.3, 0.6, 1.0),
+    Rgba::from_rgb(0.3, 1.0, 0.4),
+    Rgba::from_rgb(1.0, 0.8, 0.2),
+];
 
+/// Translucent color of the given neighborhood layer in the hover overlay
+fn layer_color(layer: usize) -> Rgba {
+    LAYER_COLORS[layer % LAYER_COLORS.len()].multiply(0.4)
+}
+
+/// Number of steps in the legend's gradient bar
+const LEGEND_STEPS: usize = 32;
+
+/// Tint of the cells under the brush
+const BRUSH_PREVIEW: Rgba = Rgba::from_rgba_premultiplied(0.15, 0.15, 0.15, 0.15);
+
+/// Tint of cells in the approximation heatmap
+const APPROXIMATE_TINT: Rgba = Rgba::from_rgba_premultiplied(0.3, 0.1, 0.0, 0.3);
+
+/// Optional extras drawn by `sim_widget`
+struct Overlays<'a> {
+    /// Neighborhood layers drawn around the hovered cell
+    layers: Option<&'a [Array2D<bool>]>,
+    /// Normalized counts drawn as grayscale in place of the cells
+    counts: Option<&'a Array2D<f32>>,
+    /// Cells to tint as approximated
+    approximate: Option<&'a Array2D<bool>>,
+    /// Brush previewed around the hovered cell
+    brush: Option<Brush>,
+    /// Previous frame and how far to fade from it to the current one, in 0..=1
+    previous: Option<(&'a Array2D<bool>, f32)>,
+}
+
+/// Draws the simulation and any overlays. Returns the cells stroked this frame, from the one under
+/// the pointer last frame to the one under it now, and the value to paint them with. A primary
+/// button stroke toggles the cell it starts on and paints the rest the same, so a click flips one
+/// cell; the secondary button, or the primary with shift held, always paints dead.
+fn sim_widget(
+    sim: &dyn Simulation,
+    lattice: Lattice,
+    style: CellStyle,
+    scale: RenderScale,
+    overlays: &Overlays<'_>,
+    ui: &mut Ui,
+) -> Option<(Vec<(usize, usize)>, bool)> {
     let (w, h) = sim.pixel_dims();
-    let coords = CoordinateMapping::new(w, h, widget_area);
+
+    let (widget_area, response) = match scale {
+        RenderScale::Stretch => {
+            ui.allocate_exact_size(ui.available_size(), Sense::click_and_drag())
+        }
+        RenderScale::Integer(n) => {
+            let columns = w as f32 + lattice.shear() * h.saturating_sub(1) as f32;
+            let size = Vec2::new(columns, h as f32) * n as f32 / ui.ctx().pixels_per_point();
+            let (outer, response) =
+                ui.allocate_exact_size(ui.available_size().max(size), Sense::click_and_drag());
+            // Snap to the device pixel grid, so that every cell edge lands on a pixel boundary
+            let min = ui.painter().round_pos_to_pixels(outer.center() - size / 2.);
+            (Rect::from_min_size(min, size), response)
+        }
+    };
+
+    let coords = CoordinateMapping::new(w, h, lattice, widget_area);
 
     let rect_size = coords.sim_to_egui_vect((1, 1));
 
+    let rounding = match style {
+        CellStyle::Dot => Rounding::same(rect_size.min_elem() / 2.),
+        CellStyle::Filled | CellStyle::Outline => Rounding::none(),
+    };
+    let stroke = Stroke::new(1.0_f32, Rgba::WHITE);
+
     // Draw particles
     let painter = ui.painter_at(widget_area);
+    let cells = overlays.counts.is_none().then(|| sim.snapshot());
     for j in 0..h {
         for i in 0..w {
-            if sim.get_pixel((i, j)) {
-                let pt = coords.sim_to_egui((i, j));
-                //dbg!(pt);
-                let rect = Rect::from_min_size(pt, rect_size);
-                painter.rect_filled(rect, Rounding::none(), Rgba::WHITE);
+            if let Some(counts) = overlays.counts {
+                let value = counts[(i, j)].min(1.0);
+                if value > 0.0 {
+                    let rect = coords.cell_rect((i, j));
+                    painter.rect_filled(rect, Rounding::none(), Rgba::from_gray(value));
+                }
+            } else if let Some(cells) = &cells {
+                let live = f32::from(u8::from(cells[(i, j)]));
+                let opacity = match overlays.previous {
+                    Some((previous, t)) => {
+                        let before = f32::from(u8::from(previous[(i, j)]));
+                        before + (live - before) * t
+                    }
+                    None => live,
+                };
+                if opacity <= 0.0 {
+                    continue;
+                }
+
+                let rect = coords.cell_rect((i, j));
+                let color = Rgba::WHITE.multiply(opacity);
+                if lattice == Lattice::Hex && style != CellStyle::Dot {
+                    let (fill, outline) = match style {
+                        CellStyle::Outline => (Rgba::TRANSPARENT, Stroke::new(stroke.width, color)),
+                        _ => (color, Stroke::NONE),
+                    };
+                    painter.add(egui::Shape::convex_polygon(hexagon(rect), fill, outline));
+                    continue;
+                }
+                match style {
+                    CellStyle::Filled | CellStyle::Dot => {
+                        painter.rect_filled(rect, rounding, color)
+                    }
+                    // Shrink slightly so that neighboring outlines don't overlap
+                    CellStyle::Outline => {
+                        let stroke = Stroke::new(stroke.width, color);
+                        painter.rect_stroke(rect.shrink(0.5), rounding, stroke)
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(approximate) = overlays.approximate {
+        for j in 0..h {
+            for i in 0..w {
+                if approximate[(i, j)] {
+                    painter.rect_filled(
+                        coords.cell_rect((i, j)),
+                        Rounding::none(),
+                        APPROXIMATE_TINT,
+                    );
+                }
             }
         }
     }
+
+    let hovered = response
+        .hover_pos()
+        .and_then(|pos| coords.egui_to_sim_checked(pos));
+    if let (Some(pos), Some(brush)) = (hovered, overlays.brush) {
+        for cell in brush.cells(pos, (w, h)) {
+            painter.rect_filled(coords.cell_rect(cell), Rounding::none(), BRUSH_PREVIEW);
+        }
+    }
+    if let (Some((cx, cy)), Some(layers)) = (hovered, overlays.layers) {
+        for (i, layer) in layers.iter().enumerate() {
+            let color = layer_color(i);
+            let (rx, ry) = (layer.width() / 2, layer.height() / 2);
+            for y in 0..layer.height() {
+                for x in 0..layer.width() {
+                    // Mask rows run along increasing sim y, like the blocks the kernel reads
+                    let (i, j) = ((cx + x).wrapping_sub(rx), (cy + y).wrapping_sub(ry));
+                    if layer[(x, y)] && i < w && j < h {
+                        painter.rect_filled(coords.cell_rect((i, j)), Rounding::none(), color);
+                    }
+                }
+            }
+        }
+    }
+
+    let painting = response.is_pointer_button_down_on();
+    let pos = response.interact_pointer_pos()?;
+    let cell = painting.then(|| coords.egui_to_sim_checked(pos)).flatten();
+
+    // The stroke's value, decided when it starts, and the cell it was over last frame
+    let stroke_id = response.id.with("stroke");
+    let (value, last) = if ui.input(|i| i.pointer.any_pressed()) {
+        let erase = ui.input(|i| i.pointer.secondary_down() || i.modifiers.shift);
+        let value = match cell {
+            _ if erase => false,
+            Some(cell) => !sim.get_pixel(cell),
+            None => true,
+        };
+        (value, None)
+    } else {
+        ui.data(|d| d.get_temp(stroke_id)).unwrap_or((true, None))
+    };
+    ui.data_mut(|d| d.insert_temp(stroke_id, (value, cell)));
+
+    // The pointer can cross several cells between frames
+    let cell = cell?;
+    let path = match last {
+        Some(last) => line(last, cell),
+        None => vec![cell],
+    };
+    Some((path, value))
+}
+
+/// Corners of a pointy-top hexagon about the center of a cell. Hexagons overlap the rows above
+/// and below by a third of the cell height, which tiles the sheared rows.
+fn hexagon(rect: Rect) -> Vec<egui::Pos2> {
+    let (c, w, h) = (rect.center(), rect.width() / 2., rect.height() / 3.);
+    [
+        (0., -2. * h),
+        (w, -h),
+        (w, h),
+        (0., 2. * h),
+        (-w, h),
+        (-w, -h),
+    ]
+    .into_iter()
+    .map(|(x, y)| c + Vec2::new(x, y))
+    .collect()
+}
+
+/// Measures wall-clock time for the frame budget.
+/// `std::time::Instant` is unavailable on the web, where the budget is always considered spent.
+struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    fn elapsed_exceeds(&self, budget: Duration) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed() >= budget;
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = budget;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernels::Life;
+
+    fn mapping(sim: &dyn Simulation) -> CoordinateMapping {
+        lattice_mapping(sim, Lattice::Square)
+    }
+
+    fn lattice_mapping(sim: &dyn Simulation, lattice: Lattice) -> CoordinateMapping {
+        let (w, h) = sim.pixel_dims();
+        let area = Rect::from_min_size(egui::Pos2::new(10.0, 20.0), Vec2::new(300.0, 200.0));
+        CoordinateMapping::new(w, h, lattice, area)
+    }
+
+    #[test]
+    fn test_cell_rect_round_trips() {
+        let sim = Dense::new(Box::new(Life), 3, 2);
+        for lattice in [Lattice::Square, Lattice::Hex] {
+            let coords = lattice_mapping(&sim, lattice);
+            let (w, h) = sim.pixel_dims();
+            for y in 0..h {
+                for x in 0..w {
+                    let rect = coords.cell_rect((x, y));
+                    assert_eq!(
+                        coords.egui_to_sim_checked(rect.center()),
+                        Some((x, y)),
+                        "{lattice:?}"
+                    );
+                    assert!(coords.area.contains_rect(rect), "{lattice:?} {rect:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_egui_to_sim_picks_cell_under_point() {
+        let sim = Dense::new(Box::new(Life), 3, 2);
+        let coords = mapping(&sim);
+        let (w, h) = sim.pixel_dims();
+        let inset = Vec2::splat(0.01);
+        for y in 0..h {
+            for x in 0..w {
+                // Corners just inside the cell, rather than the neighbors they touch
+                let rect = coords.cell_rect((x, y)).shrink2(inset);
+                let corners = [
+                    rect.left_top(),
+                    rect.right_top(),
+                    rect.left_bottom(),
+                    rect.right_bottom(),
+                ];
+                for corner in corners {
+                    let cell = coords.egui_to_sim_checked(corner);
+                    assert_eq!(cell, Some((x, y)), "{corner:?}");
+                }
+            }
+        }
+
+        let area = coords.area;
+        for outside in [
+            area.left_top() - inset,
+            area.right_bottom() + inset,
+            egui::Pos2::new(area.center().x, area.top() - 1.),
+            egui::Pos2::new(area.left() - 1., area.center().y),
+        ] {
+            assert_eq!(coords.egui_to_sim_checked(outside), None, "{outside:?}");
+        }
+    }
+
+    #[test]
+    fn test_every_rule_builds_at_the_requested_size() {
+        let registry = KernelRegistry::with_builtins();
+        for name in registry.names() {
+            for cached in [false, true] {
+                let rule = registry.get(name).unwrap();
+                let (sim, handles) = new_sim(rule, cached, Backend::Dense, (100, 60));
+                let (w, h) = sim.pixel_dims();
+                assert!(w >= 100 && h >= 60, "{name}: {:?}", (w, h));
+                assert_eq!(handles.cache.is_some(), cached, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_log_replays_or_reports() {
+        let mut app = TemplateApp {
+            log_text: "(events:[])".into(),
+            ..Default::default()
+        };
+        app.load_log();
+        assert!(app.log_error.is_some() && app.replay.is_none());
+
+        let mut log = EventLog::new();
+        log.push(Event::Seed(343));
+        app.log_text = log.to_ron();
+        app.load_log();
+        assert!(app.log_error.is_none());
+        assert_eq!(app.log, log);
+        assert!(matches!(&app.replay, Some((replaying, 0)) if *replaying == log));
+    }
+
+    #[test]
+    fn test_hex_rows_shift_right() {
+        let sim = Dense::new(Box::new(Life), 3, 2);
+        let coords = lattice_mapping(&sim, Lattice::Hex);
+        let (below, above) = (coords.cell_rect((0, 0)), coords.cell_rect((0, 1)));
+        assert!((above.min.x - below.min.x - below.width() / 2.).abs() < 1e-3);
+        assert!(above.max.y <= below.min.y + 1e-3);
+    }
+
+    #[test]
+    fn test_still_life_stays_put_on_screen() {
+        // A 2x2 block straddling block boundaries in the zero borders phase, but not the other
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        for pos in [(3, 3), (4, 3), (3, 4), (4, 4)] {
+            sim.set_pixel(pos, true);
+        }
+        let coords = mapping(&sim);
+        let rects = |sim: &Dense| -> Vec<Rect> {
+            sim.live_cells()
+                .map(|(x, y)| coords.cell_rect((x as usize, y as usize)))
+                .collect()
+        };
+
+        let first = rects(&sim);
+        assert_eq!(first.len(), 4);
+        for _ in 0..4 {
+            sim.step();
+            assert_eq!(rects(&sim), first, "zero borders {}", sim.zero_borders());
+        }
+    }
 }
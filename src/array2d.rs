@@ -1,16 +1,44 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{error::Error, format::PACKED_GRID};
+
 pub type GridPos = (usize, usize);
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+/// Rows and columns beyond which `Debug` and `Display` elide the rest of a grid
+const MAX_FORMATTED: usize = 64;
+
+#[derive(Clone, Hash, PartialEq, Eq)]
 pub struct Array2D<T> {
     width: usize,
+    /// Stored rather than derived from the length, so that it stays truthful for zero widths
+    height: usize,
     data: Vec<T>,
 }
 
 impl<T> Array2D<T> {
+    /// Panics if the data can't be split into rows of `width`; see `try_from_array`
     #[track_caller]
     pub fn from_array(width: usize, data: Vec<T>) -> Self {
-        assert_eq!(data.len() % width, 0, "Width does not match data length");
-        Self { width, data }
+        Self::try_from_array(width, data).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Wrap row-major data, failing unless it splits into whole non-empty rows of `width`
+    pub fn try_from_array(width: usize, data: Vec<T>) -> Result<Self, Error> {
+        if width == 0 || !data.len().is_multiple_of(width) {
+            return Err(Error::DataLength {
+                width,
+                len: data.len(),
+            });
+        }
+        let height = data.len() / width;
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
     }
 
     pub fn new(width: usize, height: usize) -> Self
@@ -19,10 +47,26 @@ impl<T> Array2D<T> {
     {
         Self {
             width,
+            height,
             data: vec![T::default(); width * height],
         }
     }
 
+    /// Build an array from a function of each (x, y), called in row-major order like `data`
+    pub fn from_fn(width: usize, height: usize, mut f: impl FnMut(usize, usize) -> T) -> Self {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
     pub fn data(&self) -> &[T] {
         &self.data
     }
@@ -31,9 +75,33 @@ impl<T> Array2D<T> {
         &mut self.data
     }
 
+    /// Swap the axes, so that each column becomes a contiguous row of the result
+    pub fn transpose(&self) -> Self
+    where
+        T: Clone,
+    {
+        Self::from_fn(self.height, self.width, |x, y| self[(y, x)].clone())
+    }
+
+    /// Each column from left to right, top to bottom. Columns are strided through the row-major
+    /// data; to walk many long columns, `transpose` first so that they are contiguous.
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T>> + '_ {
+        (0..self.width).map(move |x| self.data[x..].iter().step_by(self.width))
+    }
+
+    /// Row-major: x varies fastest
+    #[track_caller]
     fn calc_index(&self, (x, y): GridPos) -> usize {
-        debug_assert!(x < self.width);
-        debug_assert!(y < self.width);
+        debug_assert!(
+            x < self.width,
+            "x = {x} out of bounds for width {}",
+            self.width
+        );
+        debug_assert!(
+            y < self.height(),
+            "y = {y} out of bounds for height {}",
+            self.height()
+        );
         x + y * self.width
     }
 
@@ -42,20 +110,431 @@ impl<T> Array2D<T> {
     }
 
     pub fn height(&self) -> usize {
-        self.data.len() / self.width
+        self.height
+    }
+}
+
+/// How an element is drawn by `Array2D`'s `Debug` output
+pub trait DebugElement {
+    /// Elements are drawn as single characters, so rows need no separators
+    const COMPACT: bool = false;
+
+    fn fmt_element(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl DebugElement for bool {
+    const COMPACT: bool = true;
+
+    fn fmt_element(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if *self { "#" } else { "-" })
+    }
+}
+
+macro_rules! debug_element {
+    ($($t:ty),*) => {
+        $(impl DebugElement for $t {
+            fn fmt_element(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(self, f)
+            }
+        })*
+    };
+}
+
+debug_element!(u8, u16, u32, u64, usize, i32, f32, (usize, usize));
+
+impl<T: DebugElement> Array2D<T> {
+    /// Write the elements row by row, eliding rows and columns past `MAX_FORMATTED`
+    fn fmt_rows(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.width == 0 {
+            return Ok(());
+        }
+        for (y, row) in self.data.chunks_exact(self.width).enumerate() {
+            if y == MAX_FORMATTED {
+                return write!(f, "\n... ({} more rows)", self.height - y);
+            }
+            if y > 0 {
+                writeln!(f)?;
+            }
+            for (x, elem) in row.iter().take(MAX_FORMATTED).enumerate() {
+                if x > 0 && !T::COMPACT {
+                    f.write_str(" ")?;
+                }
+                elem.fmt_element(f)?;
+            }
+            if self.width > MAX_FORMATTED {
+                write!(f, " ... ({} more)", self.width - MAX_FORMATTED)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The dimensions followed by the grid itself, with booleans drawn as `#` (live) and `-` (dead)
+impl<T: DebugElement> fmt::Debug for Array2D<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Array2D {}x{}", self.width, self.height)?;
+        self.fmt_rows(f)
+    }
+}
+
+/// Rows of `#` (live) and `-` (dead)
+impl fmt::Display for Array2D<bool> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_rows(f)
     }
 }
 
 impl<T> std::ops::Index<GridPos> for Array2D<T> {
     type Output = T;
+    #[track_caller]
     fn index(&self, pos: GridPos) -> &T {
         &self.data[self.calc_index(pos)]
     }
 }
 
 impl<T> std::ops::IndexMut<GridPos> for Array2D<T> {
+    #[track_caller]
     fn index_mut(&mut self, pos: GridPos) -> &mut T {
         let idx = self.calc_index(pos);
         &mut self.data[idx]
     }
 }
+
+impl Array2D<bool> {
+    /// Panics unless the rows form a grid; see `try_from_rows`
+    #[track_caller]
+    pub fn from_rows(rows: &[&str]) -> Self {
+        Self::try_from_rows(rows).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Parse rows of `#` (live) and `-` (dead), top to bottom, as written by `Display`. For
+    /// legible grids in tests. Fails unless there is at least one row and all rows are the same
+    /// non-zero length.
+    pub fn try_from_rows(rows: &[&str]) -> Result<Self, Error> {
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        let mut data = Vec::with_capacity(width * rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(Error::GridRows(format!(
+                    "row {y} is {} cells wide, expected {width}",
+                    row.chars().count()
+                )));
+            }
+            for (x, c) in row.chars().enumerate() {
+                data.push(match c {
+                    '#' => true,
+                    '-' => false,
+                    _ => return Err(Error::GridRows(format!("unexpected {c:?} at ({x}, {y})"))),
+                });
+            }
+        }
+        Self::try_from_array(width, data)
+    }
+
+    /// Returns true if no cell is live
+    pub fn is_empty(&self) -> bool {
+        !self.data.iter().any(|&x| x)
+    }
+
+    /// Returns true if every cell is live
+    pub fn is_full(&self) -> bool {
+        self.data.iter().all(|&x| x)
+    }
+
+    /// Indices into `data` of the cells which differ from `other`, which must be the same size
+    pub fn diff(&self, other: &Self) -> Vec<usize> {
+        assert_eq!(
+            self.data.len(),
+            other.data.len(),
+            "Diffed arrays differ in size"
+        );
+        self.data
+            .iter()
+            .zip(&other.data)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Flip the cells at the given indices, as produced by `diff`
+    pub fn apply_diff(&mut self, diff: &[usize]) {
+        for &i in diff {
+            self.data[i] = !self.data[i];
+        }
+    }
+
+    /// Write a compact binary encoding: the `PACKED_GRID` magic and version, width and height as
+    /// little-endian u32s, then each row packed least significant bit first and padded to a
+    /// whole number of bytes
+    pub fn write_packed(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(PACKED_GRID.magic.as_bytes())?;
+        writer.write_all(&PACKED_GRID.version.to_le_bytes())?;
+        writer.write_all(&(self.width() as u32).to_le_bytes())?;
+        writer.write_all(&(self.height() as u32).to_le_bytes())?;
+
+        let mut row_bytes = vec![0_u8; self.width.div_ceil(8)];
+        for row in self.data.chunks_exact(self.width) {
+            row_bytes.iter_mut().for_each(|b| *b = 0);
+            for (x, &cell) in row.iter().enumerate() {
+                row_bytes[x / 8] |= u8::from(cell) << (x % 8);
+            }
+            writer.write_all(&row_bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the encoding produced by `write_packed`
+    pub fn read_packed(mut reader: impl Read) -> Result<Self, Error> {
+        let mut word = [0_u8; 4];
+        reader.read_exact(&mut word)?;
+        let magic = String::from_utf8_lossy(&word).into_owned();
+        reader.read_exact(&mut word)?;
+        let version = PACKED_GRID.check(&magic, u32::from_le_bytes(word))?;
+
+        match version {
+            1 => {
+                reader.read_exact(&mut word)?;
+                let width = u32::from_le_bytes(word) as usize;
+                reader.read_exact(&mut word)?;
+                let height = u32::from_le_bytes(word) as usize;
+
+                if width == 0 {
+                    return Err(Error::DataLength { width, len: 0 });
+                }
+
+                let mut row_bytes = vec![0_u8; width.div_ceil(8)];
+                let mut data = Vec::with_capacity(width * height);
+                for _ in 0..height {
+                    reader.read_exact(&mut row_bytes)?;
+                    data.extend((0..width).map(|x| row_bytes[x / 8] >> (x % 8) & 1 == 1));
+                }
+
+                Ok(Self::from_array(width, data))
+            }
+            _ => unreachable!("check rejects version {version}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 grid whose elements are their own (x, y) coordinates
+    fn coords() -> Array2D<(usize, usize)> {
+        Array2D::from_array(3, vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)])
+    }
+
+    #[test]
+    fn test_index_is_row_major() {
+        let arr = coords();
+        assert_eq!((arr.width(), arr.height()), (3, 2));
+        assert_eq!(arr.width() * arr.height(), arr.data().len());
+
+        for y in 0..arr.height() {
+            for x in 0..arr.width() {
+                assert_eq!(arr[(x, y)], (x, y));
+                assert_eq!(arr.data()[x + arr.width() * y], (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_mut() {
+        let mut arr: Array2D<u8> = Array2D::new(3, 2);
+        assert_eq!(arr.data(), [0; 6]);
+        arr[(2, 1)] = 7;
+        arr[(1, 0)] = 3;
+        assert_eq!(arr.data(), [0, 3, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_from_array_bad_length() {
+        Array2D::from_array(4, vec![0; 6]);
+    }
+
+    #[test]
+    fn test_zero_width_keeps_height() {
+        let arr: Array2D<u8> = Array2D::new(0, 3);
+        assert_eq!((arr.width(), arr.height()), (0, 3));
+        assert!(arr.data().is_empty());
+    }
+
+    #[test]
+    fn test_try_from_array() {
+        assert!(Array2D::try_from_array(3, vec![0; 6]).is_ok());
+        assert!(matches!(
+            Array2D::try_from_array(4, vec![0; 6]),
+            Err(Error::DataLength { width: 4, len: 6 })
+        ));
+        assert!(Array2D::<u8>::try_from_array(0, vec![]).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_index_y_out_of_bounds() {
+        let _ = coords()[(0, 2)];
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_index_x_out_of_bounds() {
+        // Would otherwise alias (0, 1)
+        let _ = coords()[(3, 0)];
+    }
+
+    #[test]
+    fn test_format() {
+        let arr = Array2D::from_array(3, vec![true, false, false, false, true, true]);
+        assert_eq!(arr.to_string(), "#--\n-##");
+        assert_eq!(format!("{arr:?}"), "Array2D 3x2\n#--\n-##");
+        assert_eq!(
+            format!("{:?}", coords()),
+            "Array2D 3x2\n(0, 0) (1, 0) (2, 0)\n(0, 1) (1, 1) (2, 1)"
+        );
+
+        let huge: Array2D<bool> = Array2D::new(MAX_FORMATTED + 2, MAX_FORMATTED + 5);
+        let text = huge.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), MAX_FORMATTED + 1);
+        assert!(lines[0].ends_with("--- ... (2 more)"));
+        assert_eq!(lines[MAX_FORMATTED], "... (5 more rows)");
+    }
+
+    #[test]
+    fn test_from_fn() {
+        let mut calls = vec![];
+        let arr = Array2D::from_fn(3, 2, |x, y| {
+            calls.push((x, y));
+            (x, y)
+        });
+        assert_eq!(arr, coords());
+        assert_eq!(calls, coords().data());
+        for (x, y) in calls {
+            assert_eq!(arr[(x, y)], (x, y));
+        }
+
+        let empty: Array2D<u8> = Array2D::from_fn(0, 3, |_, _| unreachable!());
+        assert_eq!((empty.width(), empty.height()), (0, 3));
+    }
+
+    #[test]
+    fn test_transpose() {
+        let arr = coords();
+        let transposed = arr.transpose();
+        assert_eq!((transposed.width(), transposed.height()), (2, 3));
+        for (x, y) in coords().data().iter().copied() {
+            assert_eq!(transposed[(y, x)], arr[(x, y)]);
+        }
+        assert_eq!(transposed.transpose(), arr);
+
+        let empty: Array2D<u8> = Array2D::new(0, 3);
+        let transposed = empty.transpose();
+        assert_eq!((transposed.width(), transposed.height()), (3, 0));
+    }
+
+    #[test]
+    fn test_columns() {
+        let arr = coords();
+        let columns: Vec<Vec<_>> = arr.columns().map(|c| c.copied().collect()).collect();
+        assert_eq!(
+            columns,
+            [[(0, 0), (0, 1)], [(1, 0), (1, 1)], [(2, 0), (2, 1)]]
+        );
+        // Likewise the rows of the transpose
+        for (column, row) in columns.iter().zip(arr.transpose().data().chunks(2)) {
+            assert_eq!(column, row);
+        }
+    }
+
+    #[test]
+    fn test_from_rows() {
+        let arr = Array2D::from_rows(&["#--", "-##"]);
+        assert_eq!(
+            arr,
+            Array2D::from_array(3, vec![true, false, false, false, true, true])
+        );
+        let text = arr.to_string();
+        assert_eq!(Array2D::from_rows(&text.lines().collect::<Vec<_>>()), arr);
+
+        assert!(matches!(
+            Array2D::try_from_rows(&["#-", "#"]),
+            Err(Error::GridRows(_))
+        ));
+        assert!(matches!(
+            Array2D::try_from_rows(&["#o"]),
+            Err(Error::GridRows(_))
+        ));
+        assert!(Array2D::try_from_rows(&[]).is_err());
+        assert!(Array2D::try_from_rows(&[""]).is_err());
+    }
+
+    #[test]
+    fn test_empty_full() {
+        let zero: Array2D<bool> = Array2D::new(4, 4);
+        assert!(zero.is_empty());
+        assert!(!zero.is_full());
+
+        let full = Array2D::from_array(4, vec![true; 16]);
+        assert!(!full.is_empty());
+        assert!(full.is_full());
+
+        let mut mixed = zero.clone();
+        mixed[(3, 2)] = true;
+        assert!(!mixed.is_empty());
+        assert!(!mixed.is_full());
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        // Width is deliberately not a multiple of 8
+        let (width, height) = (17, 5);
+        let data = (0..width * height)
+            .map(|i| i % 3 == 0 || i % 7 == 0)
+            .collect();
+        let arr = Array2D::from_array(width, data);
+
+        let mut buf = vec![];
+        arr.write_packed(&mut buf).unwrap();
+        assert_eq!(buf.len(), 16 + 3 * height);
+
+        let read = Array2D::read_packed(buf.as_slice()).unwrap();
+        assert_eq!(read, arr);
+    }
+
+    #[test]
+    fn test_packed_truncated() {
+        let arr = Array2D::from_array(17, vec![true; 17 * 2]);
+        let mut buf = vec![];
+        arr.write_packed(&mut buf).unwrap();
+        buf.pop();
+        assert!(matches!(
+            Array2D::read_packed(buf.as_slice()),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_packed_header() {
+        let arr = Array2D::from_array(3, vec![true; 3]);
+        let mut buf = vec![];
+        arr.write_packed(&mut buf).unwrap();
+
+        let mut future = buf.clone();
+        future[4] = 2;
+        assert!(matches!(
+            Array2D::read_packed(future.as_slice()),
+            Err(Error::UnsupportedVersion { version: 2, .. })
+        ));
+
+        buf[0] = b'X';
+        assert!(matches!(
+            Array2D::read_packed(buf.as_slice()),
+            Err(Error::BadMagic { .. })
+        ));
+    }
+}
@@ -0,0 +1,341 @@
+use std::fmt;
+
+use crate::{
+    array2d::{Array2D, GridPos},
+    error::Error,
+};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A grid of cells packed 64 to a word, row-major, for kernel blocks. Bits past the last cell
+/// are always zero, so equal blocks have equal words and hashes.
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub struct Block {
+    width: usize,
+    height: usize,
+    words: Box<[u64]>,
+}
+
+impl Block {
+    /// All dead
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            words: vec![0; (width * height).div_ceil(WORD_BITS)].into(),
+        }
+    }
+
+    /// Build a block from a function of each (x, y)
+    pub fn from_fn(width: usize, height: usize, mut f: impl FnMut(usize, usize) -> bool) -> Self {
+        let mut block = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                if f(x, y) {
+                    block.set((x, y), true);
+                }
+            }
+        }
+        block
+    }
+
+    /// Panics if the cells can't be split into rows of `width`; see `try_from_array`
+    #[track_caller]
+    pub fn from_array(width: usize, cells: Vec<bool>) -> Self {
+        Self::try_from_array(width, cells).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Pack row-major cells, failing unless they split into whole non-empty rows of `width`
+    pub fn try_from_array(width: usize, cells: Vec<bool>) -> Result<Self, Error> {
+        Ok(Self::from(&Array2D::try_from_array(width, cells)?))
+    }
+
+    /// Rows of `#` (live) and `-` (dead); see `Array2D::from_rows`
+    pub fn from_rows(rows: &[&str]) -> Self {
+        Self::from(&Array2D::from_rows(rows))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[track_caller]
+    fn bit(&self, (x, y): GridPos) -> (usize, u64) {
+        // Checked in release builds too, since an overlong x would otherwise wrap into the next row
+        assert!(
+            x < self.width && y < self.height,
+            "{:?} out of bounds for {}x{}",
+            (x, y),
+            self.width,
+            self.height
+        );
+        let i = x + y * self.width;
+        (i / WORD_BITS, 1 << (i % WORD_BITS))
+    }
+
+    #[track_caller]
+    pub fn get(&self, pos: GridPos) -> bool {
+        let (word, mask) = self.bit(pos);
+        self.words[word] & mask != 0
+    }
+
+    #[track_caller]
+    pub fn set(&mut self, pos: GridPos, val: bool) {
+        let (word, mask) = self.bit(pos);
+        if val {
+            self.words[word] |= mask;
+        } else {
+            self.words[word] &= !mask;
+        }
+    }
+
+    /// Every cell in row-major order
+    pub fn data(&self) -> impl ExactSizeIterator<Item = bool> + '_ {
+        (0..self.width * self.height)
+            .map(|i| self.words[i / WORD_BITS] & (1 << (i % WORD_BITS)) != 0)
+    }
+
+    /// Mutable access to the cells by row-major index, standing in for a slice of cells
+    pub fn data_mut(&mut self) -> CellsMut<'_> {
+        CellsMut {
+            len: self.width * self.height,
+            words: &mut self.words,
+        }
+    }
+
+    /// Unpack row `y` into `out`, which must be one row long. Works a word at a time rather
+    /// than locating each cell afresh.
+    #[track_caller]
+    pub fn unpack_row(&self, y: usize, out: &mut [bool]) {
+        assert!(
+            y < self.height,
+            "row {y} out of bounds for height {}",
+            self.height
+        );
+        assert_eq!(out.len(), self.width, "row buffer length");
+        let mut i = y * self.width;
+        let mut out = out;
+        while !out.is_empty() {
+            let word = self.words[i / WORD_BITS] >> (i % WORD_BITS);
+            let n = (WORD_BITS - i % WORD_BITS).min(out.len());
+            let (head, rest) = out.split_at_mut(n);
+            for (b, cell) in head.iter_mut().enumerate() {
+                *cell = (word >> b) & 1 != 0;
+            }
+            out = rest;
+            i += n;
+        }
+    }
+
+    /// The packed cells: cell (x, y) is bit (x + y * width) % 64 of word (x + y * width) / 64
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Number of live cells
+    pub fn population(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Whether every cell is dead
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// Whether every cell is live
+    pub fn is_full(&self) -> bool {
+        self.population() == self.width * self.height
+    }
+
+    /// Mirror image, reversing the columns if `x` and the rows if `y`
+    pub fn flipped(&self, x: bool, y: bool) -> Self {
+        let (w, h) = (self.width, self.height);
+        Self::from_fn(w, h, |i, j| {
+            let i = if x { w - 1 - i } else { i };
+            let j = if y { h - 1 - j } else { j };
+            self.get((i, j))
+        })
+    }
+
+    /// Unpack into one bool per cell
+    pub fn to_array(&self) -> Array2D<bool> {
+        Array2D::from_fn(self.width, self.height, |x, y| self.get((x, y)))
+    }
+}
+
+/// The cells of a `Block` by row-major index, from `Block::data_mut`
+pub struct CellsMut<'a> {
+    len: usize,
+    words: &'a mut [u64],
+}
+
+impl CellsMut<'_> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[track_caller]
+    fn bit(&self, i: usize) -> (usize, u64) {
+        assert!(
+            i < self.len,
+            "cell {i} out of bounds for {} cells",
+            self.len
+        );
+        (i / WORD_BITS, 1 << (i % WORD_BITS))
+    }
+
+    #[track_caller]
+    pub fn get(&self, i: usize) -> bool {
+        let (word, mask) = self.bit(i);
+        self.words[word] & mask != 0
+    }
+
+    #[track_caller]
+    pub fn set(&mut self, i: usize, val: bool) {
+        let (word, mask) = self.bit(i);
+        if val {
+            self.words[word] |= mask;
+        } else {
+            self.words[word] &= !mask;
+        }
+    }
+
+    /// Set every cell to `val`, leaving the bits past the last cell zero
+    pub fn fill(&mut self, val: bool) {
+        for i in 0..self.len {
+            self.set(i, val);
+        }
+    }
+}
+
+impl From<&Array2D<bool>> for Block {
+    fn from(cells: &Array2D<bool>) -> Self {
+        Self::from_fn(cells.width(), cells.height(), |x, y| cells[(x, y)])
+    }
+}
+
+impl From<&Block> for Array2D<bool> {
+    fn from(block: &Block) -> Self {
+        block.to_array()
+    }
+}
+
+impl std::ops::Index<GridPos> for Block {
+    type Output = bool;
+
+    #[track_caller]
+    fn index(&self, pos: GridPos) -> &bool {
+        if self.get(pos) {
+            &true
+        } else {
+            &false
+        }
+    }
+}
+
+impl fmt::Debug for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_array(), f)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.to_array(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_set_get_round_trip() {
+        let mut rng = StdRng::seed_from_u64(503);
+        for order in 0..=7 {
+            let w = 1 << order;
+            let cells = Array2D::from_fn(w, w, |_, _| rng.gen_bool(0.5));
+            let mut block = Block::new(w, w);
+            for y in 0..w {
+                for x in 0..w {
+                    block.set((x, y), cells[(x, y)]);
+                }
+            }
+            assert_eq!(block.to_array(), cells, "order {order}");
+            assert_eq!(block, Block::from(&cells));
+            assert_eq!(block.data().collect::<Vec<_>>(), cells.data());
+            let live = cells.data().iter().filter(|&&c| c).count();
+            assert_eq!(block.population(), live);
+
+            // Clearing every cell leaves no stray bits
+            for y in 0..w {
+                for x in 0..w {
+                    block.set((x, y), false);
+                }
+            }
+            assert!(block.is_empty());
+            assert_eq!(block, Block::new(w, w));
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        let block = Block::from_rows(&["#-", "-#"]);
+        assert!(block[(0, 0)] && block[(1, 1)]);
+        assert!(!block[(1, 0)] && !block[(0, 1)]);
+        assert_eq!(block.to_string(), "#-\n-#");
+        assert!(!block.is_full() && !block.is_empty());
+        assert!(Block::from_fn(3, 3, |_, _| true).is_full());
+        assert_eq!(block.flipped(true, false), Block::from_rows(&["-#", "#-"]));
+        assert_eq!(block.flipped(true, true), block);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_get_past_row_end_panics() {
+        // (3, 0) would otherwise read the bit of (0, 1)
+        Block::new(3, 3).get((3, 0));
+    }
+
+    #[test]
+    fn test_data_mut_and_rows() {
+        let mut rng = StdRng::seed_from_u64(5031);
+        let cells = Array2D::from_fn(17, 9, |_, _| rng.gen_bool(0.5));
+        let mut block = Block::new(17, 9);
+        let mut data = block.data_mut();
+        assert_eq!(data.len(), 17 * 9);
+        for (i, &cell) in cells.data().iter().enumerate() {
+            data.set(i, cell);
+            assert_eq!(data.get(i), cell);
+        }
+        assert_eq!(block, Block::from(&cells));
+
+        let mut row = vec![false; 17];
+        for y in 0..9 {
+            block.unpack_row(y, &mut row);
+            assert_eq!(row, cells.data()[y * 17..][..17]);
+        }
+
+        block.data_mut().fill(true);
+        assert!(block.is_full());
+        block.data_mut().fill(false);
+        assert_eq!(block, Block::new(17, 9));
+    }
+
+    #[test]
+    fn test_packing() {
+        // 3x3 cells fit in one word, with the rest of it zero
+        let block = Block::from_fn(3, 3, |x, y| x == y);
+        assert_eq!(block.words(), [0b100_010_001]);
+        assert_eq!(Block::new(16, 16).words().len(), 4);
+    }
+}
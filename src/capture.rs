@@ -0,0 +1,283 @@
+//! Exporting generations as numbered PNG files, for assembling into video with external tools
+//! such as `ffmpeg -i frame_<fingerprint>_%05d.png`
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::JoinHandle,
+};
+
+use crate::{array2d::Array2D, error::Error, sim::Simulation};
+
+const LIVE_RGBA: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+const DEAD_RGBA: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+
+/// Each cell as a `scale` x `scale` square of pixels: opaque white for live cells and opaque
+/// black for dead. The first row of the image is the last row of the grid, since the app draws
+/// increasing y upwards. This is the only place cells become pixels, so that every export matches
+/// the view.
+pub fn render_image(grid: &Array2D<bool>, scale: usize) -> Array2D<[u8; 4]> {
+    let scale = scale.max(1);
+    let height = grid.height() * scale;
+    Array2D::from_fn(grid.width() * scale, height, |x, y| {
+        match grid[(x / scale, (height - 1 - y) / scale)] {
+            true => LIVE_RGBA,
+            false => DEAD_RGBA,
+        }
+    })
+}
+
+/// `render_image` at one pixel per cell, as bytes in row-major order
+pub fn render_to_rgba(grid: &Array2D<bool>) -> Vec<u8> {
+    render_image(grid, 1).data().concat()
+}
+
+/// Encode a grid as an RGBA PNG, one pixel per cell
+pub fn write_png(grid: &Array2D<bool>, writer: impl Write) -> Result<(), Error> {
+    write_image_png(&render_image(grid, 1), writer)
+}
+
+/// Encode an image from `render_image` as a PNG
+pub fn write_image_png(image: &Array2D<[u8; 4]>, writer: impl Write) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(writer, image.width() as u32, image.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.data().concat())?;
+    Ok(())
+}
+
+/// Write a simulation's current state to a PNG file, each cell `scale` pixels wide
+pub fn save_screenshot(sim: &dyn Simulation, path: &Path, scale: usize) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_image_png(&render_image(&sim.snapshot(), scale), &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Name for a screenshot taken at `unix_secs`, tagged with the rule's fingerprint (see
+/// `LayeredKernel::fingerprint`) if it has one. Shots of one rule sort in the order they were
+/// taken.
+pub fn screenshot_file_name(fingerprint: Option<u64>, unix_secs: u64) -> String {
+    format!("screenshot_{}{unix_secs}.png", rule_tag(fingerprint))
+}
+
+/// The fingerprint as it appears in file names, followed by a separator, or nothing for rules
+/// without one
+fn rule_tag(fingerprint: Option<u64>) -> String {
+    fingerprint.map_or(String::new(), |f| format!("{f:016x}_"))
+}
+
+/// Name of the `index`th frame, counting from 1, zero-padded so that the files sort in order.
+/// Tagged with the rule's fingerprint like `screenshot_file_name`, so that sequences of
+/// different rules in one directory stay apart.
+pub fn frame_file_name(fingerprint: Option<u64>, index: usize) -> String {
+    format!("frame_{}{index:05}.png", rule_tag(fingerprint))
+}
+
+/// Which generations are captured
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureRange {
+    pub generations: Range<u64>,
+    /// Capture every Nth generation, counting from the start of the range. 0 is treated as 1.
+    pub every: u64,
+}
+
+impl CaptureRange {
+    pub fn includes(&self, generation: u64) -> bool {
+        self.generations.contains(&generation)
+            && (generation - self.generations.start).is_multiple_of(self.every.max(1))
+    }
+}
+
+/// Writes frames to a directory from a background thread, so that encoding doesn't hold up the
+/// simulation. Frames are numbered in the order they were captured.
+pub struct FrameRecorder {
+    range: CaptureRange,
+    sender: Sender<Array2D<bool>>,
+    writer: JoinHandle<Result<usize, Error>>,
+    captured: usize,
+}
+
+impl FrameRecorder {
+    /// Create the directory if needed and start the writer thread. Frames are named for the
+    /// rule's `fingerprint` (see `frame_file_name`), and existing frames of that rule in the
+    /// directory are overwritten.
+    pub fn start(
+        dir: impl Into<PathBuf>,
+        range: CaptureRange,
+        fingerprint: Option<u64>,
+    ) -> Result<Self, Error> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let (sender, receiver) = mpsc::channel::<Array2D<bool>>();
+        let writer = std::thread::Builder::new()
+            .name("frame writer".into())
+            .spawn(move || {
+                let mut written = 0;
+                for grid in receiver {
+                    written += 1;
+                    write_frame(&dir.join(frame_file_name(fingerprint, written)), &grid)?;
+                }
+                Ok(written)
+            })?;
+
+        Ok(Self {
+            range,
+            sender,
+            writer,
+            captured: 0,
+        })
+    }
+
+    /// Queue the simulation's current state if `generation` is in the capture range. Returns
+    /// whether it was captured.
+    pub fn record(&mut self, generation: u64, sim: &dyn Simulation) -> bool {
+        if !self.range.includes(generation) {
+            return false;
+        }
+        // A send only fails once the writer has stopped on an error, which `finish` reports
+        let _ = self.sender.send(sim.snapshot());
+        self.captured += 1;
+        true
+    }
+
+    /// Whether no generation after `generation` is in the range
+    pub fn is_complete(&self, generation: u64) -> bool {
+        generation >= self.range.generations.end.saturating_sub(1)
+    }
+
+    pub fn range(&self) -> &CaptureRange {
+        &self.range
+    }
+
+    /// Number of frames queued so far
+    pub fn captured(&self) -> usize {
+        self.captured
+    }
+
+    /// Wait for queued frames to be written, returning how many were
+    pub fn finish(self) -> Result<usize, Error> {
+        drop(self.sender);
+        self.writer
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+fn write_frame(path: &Path, grid: &Array2D<bool>) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_png(grid, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kernels::Life, sim::Dense};
+
+    #[test]
+    fn test_render_to_rgba() {
+        let grid = Array2D::from_array(2, vec![true, false, false, true]);
+        let rgba = render_to_rgba(&grid);
+        assert_eq!(rgba.len(), 4 * 4);
+        assert_eq!(rgba[..4], DEAD_RGBA);
+        assert_eq!(rgba[4..8], LIVE_RGBA);
+        assert_eq!(rgba[8..12], LIVE_RGBA);
+        assert_eq!(rgba[12..], DEAD_RGBA);
+    }
+
+    #[test]
+    fn test_render_image_matches_view() {
+        // Cell (2, 0) is at the bottom of the view, so in the image's last rows
+        let mut grid = Array2D::new(3, 2);
+        grid[(2, 0)] = true;
+        let image = render_image(&grid, 2);
+        assert_eq!((image.width(), image.height()), (6, 4));
+        for y in 0..4 {
+            for x in 0..6 {
+                let live = x >= 4 && y >= 2;
+                assert_eq!(image[(x, y)] == LIVE_RGBA, live, "{x}, {y}");
+            }
+        }
+        assert!(render_image(&grid, 0) == render_image(&grid, 1));
+    }
+
+    #[test]
+    fn test_capture_range() {
+        let range = CaptureRange {
+            generations: 3..10,
+            every: 3,
+        };
+        let captured: Vec<u64> = (0..20).filter(|&g| range.includes(g)).collect();
+        assert_eq!(captured, [3, 6, 9]);
+
+        let every_zero = CaptureRange {
+            generations: 0..3,
+            every: 0,
+        };
+        assert!((0..3).all(|g| every_zero.includes(g)));
+    }
+
+    #[test]
+    fn test_screenshot_file_name() {
+        assert_eq!(
+            screenshot_file_name(Some(0xabc), 1700000000),
+            "screenshot_0000000000000abc_1700000000.png"
+        );
+        assert_eq!(screenshot_file_name(None, 5), "screenshot_5.png");
+    }
+
+    #[test]
+    fn test_frame_file_name() {
+        assert_eq!(frame_file_name(None, 1), "frame_00001.png");
+        assert_eq!(frame_file_name(None, 123456), "frame_123456.png");
+        assert_eq!(
+            frame_file_name(Some(u64::MAX), 7),
+            "frame_ffffffffffffffff_00007.png"
+        );
+    }
+
+    #[test]
+    fn test_recorder_writes_frames() {
+        let dir = std::env::temp_dir().join(format!("mnca-frames-{}", std::process::id()));
+        let range = CaptureRange {
+            generations: 0..6,
+            every: 2,
+        };
+        let fingerprint = Some(0x391);
+        let mut recorder = FrameRecorder::start(&dir, range, fingerprint).unwrap();
+
+        let mut sim = Dense::new(Box::new(Life), 4, 3);
+        sim.set_pixel((1, 1), true);
+        for generation in 0..10 {
+            recorder.record(generation, &sim);
+            sim.step();
+        }
+        assert_eq!(recorder.captured(), 3);
+        assert_eq!(recorder.finish().unwrap(), 3);
+
+        let decoder =
+            png::Decoder::new(File::open(dir.join(frame_file_name(fingerprint, 1))).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(
+            (info.width as usize, info.height as usize),
+            sim.pixel_dims()
+        );
+        // Cell (1, 1) is in the second row from the bottom
+        let (w, h) = sim.pixel_dims();
+        assert_eq!(pixels[4 * (1 + (h - 2) * w)..][..4], LIVE_RGBA);
+
+        assert!(dir.join(frame_file_name(fingerprint, 3)).exists());
+        assert!(!dir.join(frame_file_name(fingerprint, 4)).exists());
+        assert!(!dir.join(frame_file_name(None, 1)).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use crate::array2d::DebugElement;
+
+/// A cell state. `bool` is the two-state (dead/alive) case; formats and renderers are written
+/// against this trait so that multi-state cells can be added without rewriting them.
+pub trait Cell: Copy + Default + PartialEq + DebugElement {
+    /// Number of distinct states, including the dead state 0
+    const STATES: u8;
+
+    /// Convert from a state index, which must be less than `STATES`
+    fn from_state(state: u8) -> Self;
+
+    fn state(self) -> u8;
+}
+
+impl Cell for bool {
+    const STATES: u8 = 2;
+
+    fn from_state(state: u8) -> Self {
+        state != 0
+    }
+
+    fn state(self) -> u8 {
+        u8::from(self)
+    }
+}
+
+/// Mapping between cell states and the tokens representing them in a text format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateChars {
+    /// Token for each state, indexed by state
+    tokens: Vec<String>,
+}
+
+impl StateChars {
+    pub fn new(tokens: Vec<String>) -> Self {
+        Self { tokens }
+    }
+
+    /// Two-state RLE: `b` for dead, `o` for alive
+    pub fn rle_two_state() -> Self {
+        Self::new(vec!["b".into(), "o".into()])
+    }
+
+    /// Golly's multi-state RLE: `.` for dead, `A`..`X` for states 1-24, then `pA`..`pX`, `qA`.. etc.
+    pub fn rle_multistate(states: u8) -> Self {
+        let tokens = (0..states)
+            .map(|state| match state {
+                0 => ".".to_string(),
+                s => {
+                    let s = s - 1;
+                    let letter = char::from(b'A' + s % 24);
+                    match s / 24 {
+                        0 => letter.to_string(),
+                        prefix => format!("{}{}", char::from(b'p' + prefix - 1), letter),
+                    }
+                }
+            })
+            .collect();
+        Self::new(tokens)
+    }
+
+    /// Plaintext (`.cells`): `.` for dead, `O` for alive
+    pub fn plaintext() -> Self {
+        Self::new(vec![".".into(), "O".into()])
+    }
+
+    pub fn states(&self) -> u8 {
+        self.tokens.len() as u8
+    }
+
+    pub fn token(&self, state: u8) -> &str {
+        &self.tokens[state as usize]
+    }
+
+    /// Match the longest token at the start of the input, returning its state and length
+    pub fn parse_prefix(&self, input: &str) -> Option<(u8, usize)> {
+        self.tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| input.starts_with(token.as_str()))
+            .max_by_key(|(_, token)| token.len())
+            .map(|(state, token)| (state as u8, token.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multistate_tokens() {
+        let chars = StateChars::rle_multistate(30);
+        assert_eq!(chars.token(0), ".");
+        assert_eq!(chars.token(1), "A");
+        assert_eq!(chars.token(24), "X");
+        assert_eq!(chars.token(25), "pA");
+        assert_eq!(chars.parse_prefix("pB3"), Some((26, 2)));
+        assert_eq!(chars.parse_prefix("B3"), Some((2, 1)));
+        assert_eq!(chars.parse_prefix("$"), None);
+    }
+}
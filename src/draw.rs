@@ -0,0 +1,246 @@
+use crate::{array2d::Array2D, kernels::draw_ring};
+
+/// Symmetry group applied while drawing, about the center of the grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    None,
+    /// Mirror across the vertical axis
+    Vertical,
+    /// Mirror across the horizontal axis
+    Horizontal,
+    /// Mirror across both axes (4-fold)
+    Quad,
+    /// Mirror across both axes and both diagonals (8-fold)
+    Octo,
+}
+
+impl Symmetry {
+    /// All images of `pos` under this symmetry, including `pos` itself, on a grid of the given
+    /// (width, height). Diagonal images which don't land on a cell of the grid are omitted.
+    pub fn images(self, pos: (usize, usize), dims: (usize, usize)) -> Vec<(usize, usize)> {
+        let (w, h) = (dims.0 as i64, dims.1 as i64);
+
+        // Offsets from the center, doubled so that they are always integers
+        let dx = 2 * pos.0 as i64 - (w - 1);
+        let dy = 2 * pos.1 as i64 - (h - 1);
+
+        let offsets: Vec<(i64, i64)> = match self {
+            Symmetry::None => vec![(dx, dy)],
+            Symmetry::Vertical => vec![(dx, dy), (-dx, dy)],
+            Symmetry::Horizontal => vec![(dx, dy), (dx, -dy)],
+            Symmetry::Quad => vec![(dx, dy), (-dx, dy), (dx, -dy), (-dx, -dy)],
+            Symmetry::Octo => [(dx, dy), (dy, dx)]
+                .into_iter()
+                .flat_map(|(a, b)| [(a, b), (-a, b), (a, -b), (-a, -b)])
+                .collect(),
+        };
+
+        let mut images: Vec<(usize, usize)> = offsets
+            .into_iter()
+            .filter_map(|(dx, dy)| {
+                let x2 = dx + w - 1;
+                let y2 = dy + h - 1;
+                let on_grid = x2 % 2 == 0 && y2 % 2 == 0;
+                let (x, y) = (x2 / 2, y2 / 2);
+                (on_grid && (0..w).contains(&x) && (0..h).contains(&y))
+                    .then_some((x as usize, y as usize))
+            })
+            .collect();
+
+        images.sort_unstable();
+        images.dedup();
+        images
+    }
+}
+
+/// Outline of a brush
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    /// Every cell within the radius
+    Disk,
+    /// Only the outermost cells within the radius, one cell thick
+    Ring,
+}
+
+/// Set of cells painted around the cursor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Brush {
+    /// In cells. A radius of 0 paints just the cell under the cursor.
+    pub radius: u32,
+    pub shape: BrushShape,
+}
+
+impl Brush {
+    /// Offsets of the painted cells from the cursor, in row-major order. Cells are included if
+    /// their center is within half a cell of the radius.
+    pub fn offsets(&self) -> Vec<(i32, i32)> {
+        let r = self.radius as i32;
+        // The largest squared distance within r + 0.5 is r^2 + r
+        let outer_sq = r * r + r + 1;
+        let inner_sq = match self.shape {
+            BrushShape::Ring if r > 0 => r * r - r + 1,
+            _ => 0,
+        };
+        let size = 2 * self.radius as usize + 1;
+        let mut mask = Array2D::new(size, size);
+        draw_ring(&mut mask, inner_sq, outer_sq);
+
+        let mut offsets = vec![];
+        for y in 0..size {
+            for x in 0..size {
+                if mask[(x, y)] {
+                    offsets.push((x as i32 - r, y as i32 - r));
+                }
+            }
+        }
+        offsets
+    }
+
+    /// Cells painted with the cursor at `pos`, clipped to a grid of the given (width, height)
+    pub fn cells(&self, pos: (usize, usize), dims: (usize, usize)) -> Vec<(usize, usize)> {
+        self.offsets()
+            .into_iter()
+            .filter_map(|(dx, dy)| {
+                let x = pos.0.checked_add_signed(dx as isize)?;
+                let y = pos.1.checked_add_signed(dy as isize)?;
+                (x < dims.0 && y < dims.1).then_some((x, y))
+            })
+            .collect()
+    }
+}
+
+/// Cells on the line from `from` to `to`, both included, each 8-connected to the last
+pub fn line(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x0, y0) = (from.0 as i64, from.1 as i64);
+    let (x1, y1) = (to.0 as i64, to.1 as i64);
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+
+    // Bresenham's, with `err` tracking the distance to the true line for both axes at once
+    let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+    let mut cells = vec![from];
+    while (x, y) != (x1, y1) {
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+        cells.push((x as usize, y as usize));
+    }
+    cells
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            radius: 0,
+            shape: BrushShape::Disk,
+        }
+    }
+}
+
+/// Arrangement of cells on screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lattice {
+    Square,
+    /// Rows in axial coordinates, each shifted half a cell right of the one below, with cells
+    /// drawn as hexagons
+    Hex,
+}
+
+impl Lattice {
+    /// Horizontal offset of each row from the one below, in cells
+    pub fn shear(self) -> f32 {
+        match self {
+            Lattice::Square => 0.,
+            Lattice::Hex => 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quad_images() {
+        let images = Symmetry::Quad.images((1, 2), (10, 6));
+        assert_eq!(images, [(1, 2), (1, 3), (8, 2), (8, 3)]);
+    }
+
+    #[test]
+    fn test_center_is_fixed() {
+        assert_eq!(Symmetry::Octo.images((2, 2), (5, 5)), [(2, 2)]);
+    }
+
+    #[test]
+    fn test_octo_images() {
+        let images = Symmetry::Octo.images((0, 1), (5, 5));
+        assert_eq!(
+            images,
+            [
+                (0, 1),
+                (0, 3),
+                (1, 0),
+                (1, 4),
+                (3, 0),
+                (3, 4),
+                (4, 1),
+                (4, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_brush_offsets() {
+        let brush = |radius, shape| Brush { radius, shape };
+        assert_eq!(brush(0, BrushShape::Disk).offsets(), [(0, 0)]);
+        assert_eq!(brush(0, BrushShape::Ring).offsets(), [(0, 0)]);
+        assert_eq!(brush(1, BrushShape::Disk).offsets().len(), 9);
+        // Corners at distance sqrt(8) are outside 2.5
+        assert_eq!(brush(2, BrushShape::Disk).offsets().len(), 21);
+        let ring = brush(2, BrushShape::Ring).offsets();
+        assert_eq!(ring.len(), 12);
+        assert!(!ring.contains(&(0, 0)) && !ring.contains(&(1, 1)));
+    }
+
+    #[test]
+    fn test_brush_cells_clipped() {
+        let brush = Brush {
+            radius: 1,
+            shape: BrushShape::Disk,
+        };
+        assert_eq!(
+            brush.cells((0, 0), (4, 4)),
+            [(0, 0), (1, 0), (0, 1), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line((2, 3), (2, 3)), [(2, 3)]);
+        assert_eq!(line((0, 0), (3, 1)), [(0, 0), (1, 0), (2, 1), (3, 1)]);
+        assert_eq!(line((3, 3), (0, 0)), [(3, 3), (2, 2), (1, 1), (0, 0)]);
+
+        // Every step moves to a neighbor, so a fast stroke leaves no gaps
+        for to in [(9, 2), (0, 7), (5, 0), (1, 9)] {
+            let cells = line((4, 4), to);
+            assert_eq!(cells.last(), Some(&to));
+            for pair in cells.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert_eq!((a.0.abs_diff(b.0).max(a.1.abs_diff(b.1))), 1, "{to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_octo_off_grid_diagonal() {
+        // On a grid with mismatched parity, diagonal images fall between cells
+        let images = Symmetry::Octo.images((0, 0), (4, 5));
+        assert_eq!(images, [(0, 0), (0, 4), (3, 0), (3, 4)]);
+    }
+}
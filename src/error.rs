@@ -0,0 +1,80 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::{rle::RleError, sim::UnsupportedOrder};
+
+/// Errors returned by the crate's fallible constructors and loaders
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Kernel widths must be 2^k + 1 for some k >= 1
+    #[error("Invalid kernel width {0}, must be 2^k + 1 (3, 5, 9, 17, ...)")]
+    InvalidKernelWidth(usize),
+    /// Kernel layers must all share the first layer's dimensions
+    #[error("Kernel layer is {found:?}, expected {expected:?}")]
+    ShapeMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// Array data can't be split into rows of the given width
+    #[error("Data length {len} does not divide into rows of width {width}")]
+    DataLength { width: usize, len: usize },
+    /// Block data doesn't hold the number of cells a kernel of the given order expects
+    #[error("Block has {found} cells, but kernel order {order} expects {expected}")]
+    BlockSize {
+        order: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A pixel coordinate outside the simulation's (width, height)
+    #[error("Pixel {pos:?} is outside the {dims:?} grid")]
+    OutOfBounds {
+        pos: (usize, usize),
+        dims: (usize, usize),
+    },
+    /// A file doesn't start with the expected magic string
+    #[error("Not a {format} (found header {found:?})")]
+    BadMagic { format: &'static str, found: String },
+    /// A file is from a newer (or corrupt) version of the format
+    #[error("Unsupported {format} version {version}")]
+    UnsupportedVersion { format: &'static str, version: u32 },
+    /// A layered kernel needs at least one layer
+    #[error("Kernel has no layers")]
+    NoLayers,
+    /// A weighted layer's total weight doesn't fit in the `u16` counts deciders see
+    #[error(
+        "Kernel layer {layer} has total weight {total}, more than {}",
+        u16::MAX
+    )]
+    WeightOverflow { layer: usize, total: usize },
+    /// A kernel builder was never given a decider
+    #[error("Kernel has no decider")]
+    NoDecider,
+    /// A kernel file is valid TOML, but doesn't describe a kernel
+    #[error("Invalid kernel file: {0}")]
+    KernelFile(String),
+    /// Rows of `#` and `-` which don't form a grid
+    #[error("Invalid grid rows: {0}")]
+    GridRows(String),
+    /// A rule expression (see `expr`) which doesn't parse, or doesn't fit its kernel
+    #[error("Invalid rule expression at column {column}: {message}")]
+    RuleExpr { column: usize, message: String },
+    /// An MCell file is malformed, or uses a rule this crate can't express
+    #[error("Invalid MCell file: {0}")]
+    Mcell(String),
+    #[error(transparent)]
+    Ron(#[from] ron::error::SpannedError),
+    #[error(transparent)]
+    Toml(#[from] toml_edit::TomlError),
+    #[error(transparent)]
+    RleParse(#[from] RleError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+    /// `ffmpeg` couldn't be started, or failed while encoding
+    #[error("Video encoding failed: {0}")]
+    Video(String),
+    #[error(transparent)]
+    UnsupportedOrder(#[from] UnsupportedOrder),
+}
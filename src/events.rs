@@ -0,0 +1,170 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, format::EVENT_LOG, patterns::InitialPattern, sim::Simulation};
+
+/// A single user interaction with the simulation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    /// Set the pixel at (x, y) to the given state
+    SetPixel { x: usize, y: usize, value: bool },
+    /// Advance the simulation by one time step
+    Step,
+    /// Switch block phase without stepping; see `Simulation::flip_phase`
+    FlipPhase,
+    /// Kill every cell
+    Clear,
+    /// Clear, then fill the visible area with random cells drawn from the given seed
+    Seed(u64),
+    /// Clear, then fill the visible area with a deterministic pattern
+    Pattern(InitialPattern),
+}
+
+impl Event {
+    pub fn apply(&self, sim: &mut dyn Simulation) {
+        match *self {
+            Event::SetPixel { x, y, value } => {
+                // Ignored outside the grid, so logs recorded on a larger grid still replay
+                let _ = sim.try_set_pixel((x, y), value);
+            }
+            Event::Step => sim.step(),
+            Event::FlipPhase => sim.flip_phase(),
+            Event::Clear => sim.clear(),
+            Event::Seed(seed) => {
+                sim.clear();
+                let mut rng = StdRng::seed_from_u64(seed);
+                sim.for_each_pixel_mut(&mut |_, cell| *cell = rng.gen_bool(0.5));
+            }
+            Event::Pattern(pattern) => {
+                sim.clear();
+                let (w, h) = sim.pixel_dims();
+                let pattern = pattern.generate(w, h);
+                for y in 0..h {
+                    for x in 0..w {
+                        if pattern[(x, y)] {
+                            sim.set_pixel((x, y), true);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A recorded timeline of events, which reconstructs a session when replayed onto a blank simulation
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventLog {
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Apply every event in order
+    pub fn replay(&self, sim: &mut dyn Simulation) {
+        for event in &self.events {
+            event.apply(sim);
+        }
+    }
+
+    pub fn to_ron(&self) -> String {
+        let file = LogFile {
+            format: EVENT_LOG.magic.to_string(),
+            version: EVENT_LOG.version,
+            events: self.events.clone(),
+        };
+        ron::to_string(&file).expect("Event logs are always serializable")
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, Error> {
+        // Read the header on its own first, since the rest may be in a layout this build can't parse
+        let header: LogHeader = ron::from_str(text)?;
+        let version = EVENT_LOG.check(&header.format, header.version)?;
+
+        match version {
+            1 => {
+                let file: LogFile = ron::from_str(text)?;
+                Ok(Self {
+                    events: file.events,
+                })
+            }
+            _ => unreachable!("check rejects version {version}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LogHeader {
+    format: String,
+    version: u32,
+}
+
+/// On-disk layout of an `EventLog`, version 1
+#[derive(Serialize, Deserialize)]
+struct LogFile {
+    format: String,
+    version: u32,
+    events: Vec<Event>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kernels::Life, sim::Dense};
+
+    fn example_log() -> EventLog {
+        let mut log = EventLog::new();
+        log.push(Event::Seed(1234));
+        log.push(Event::Step);
+        log.push(Event::SetPixel {
+            x: 3,
+            y: 4,
+            value: true,
+        });
+        log.push(Event::Step);
+        log.push(Event::Pattern(InitialPattern::Checkerboard));
+        log.push(Event::Step);
+        log
+    }
+
+    #[test]
+    fn test_replay_deterministic() {
+        let log = example_log();
+
+        let mut a = Dense::new(Box::new(Life), 8, 8);
+        let mut b = Dense::new(Box::new(Life), 8, 8);
+        log.replay(&mut a);
+        log.replay(&mut b);
+
+        assert_eq!(
+            a.live_cells().collect::<Vec<_>>(),
+            b.live_cells().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_ron_round_trip() {
+        let log = example_log();
+        assert_eq!(EventLog::from_ron(&log.to_ron()).unwrap(), log);
+
+        let future = log.to_ron().replace("version:1", "version:2");
+        assert!(matches!(
+            EventLog::from_ron(&future),
+            Err(Error::UnsupportedVersion { version: 2, .. })
+        ));
+        assert!(matches!(
+            EventLog::from_ron("(events:[])"),
+            Err(Error::Ron(_))
+        ));
+    }
+}
@@ -0,0 +1,571 @@
+//! One-line rule expressions for `LayeredKernel` deciders, e.g.
+//! `center ? n0 in 0.25..0.375 : n0 == 0.375` for Life.
+//!
+//! Variables are `center`, the current state of the cell, and `n0`, `n1`, ... each layer's
+//! normalized neighbor count. Numbers combine with `+ - * /` and compare with
+//! `== != < <= > >=`, or `x in lo..hi`, which includes both ends like a band. Conditions combine
+//! with `!`, `&&` and `||` (or `not`, `and` and `or`), and `c ? a : b` picks a condition.
+//! Precedence is the usual one, loosest first: ternary, or, and, not, comparison, sums, products.
+
+use std::{fmt, sync::Arc};
+
+use crate::error::Error;
+
+/// Next state of a cell from its current state and each layer's normalized count
+pub type CompiledExpr = Box<dyn Fn(bool, &[f32]) -> bool + Send + Sync>;
+
+/// A parsed rule expression, which evaluates to the next state of the center cell
+#[derive(Clone, Debug)]
+pub struct RuleExpr {
+    source: String,
+    root: Arc<Cond>,
+    /// One more than the highest layer index read, i.e. the number of layers needed
+    layers: usize,
+    /// Where that index is first read
+    layers_column: usize,
+}
+
+#[derive(Debug)]
+enum Cond {
+    Const(bool),
+    Center,
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+    Compare(CompareOp, Num, Num),
+    In(Num, Num, Num),
+    Ternary(Box<Cond>, Box<Cond>, Box<Cond>),
+}
+
+#[derive(Debug)]
+enum Num {
+    Const(f32),
+    Layer(usize),
+    Neg(Box<Num>),
+    Arith(ArithOp, Box<Num>, Box<Num>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Cond {
+    fn eval(&self, center: bool, layer: &dyn Fn(usize) -> f32) -> bool {
+        match self {
+            Cond::Const(value) => *value,
+            Cond::Center => center,
+            Cond::Not(a) => !a.eval(center, layer),
+            Cond::And(a, b) => a.eval(center, layer) && b.eval(center, layer),
+            Cond::Or(a, b) => a.eval(center, layer) || b.eval(center, layer),
+            Cond::Compare(op, a, b) => {
+                let (a, b) = (a.eval(layer), b.eval(layer));
+                match op {
+                    CompareOp::Eq => a == b,
+                    CompareOp::Ne => a != b,
+                    CompareOp::Lt => a < b,
+                    CompareOp::Le => a <= b,
+                    CompareOp::Gt => a > b,
+                    CompareOp::Ge => a >= b,
+                }
+            }
+            Cond::In(x, lo, hi) => {
+                let x = x.eval(layer);
+                x >= lo.eval(layer) && x <= hi.eval(layer)
+            }
+            Cond::Ternary(c, a, b) => {
+                if c.eval(center, layer) {
+                    a.eval(center, layer)
+                } else {
+                    b.eval(center, layer)
+                }
+            }
+        }
+    }
+}
+
+impl Num {
+    fn eval(&self, layer: &dyn Fn(usize) -> f32) -> f32 {
+        match self {
+            Num::Const(value) => *value,
+            Num::Layer(i) => layer(*i),
+            Num::Neg(a) => -a.eval(layer),
+            Num::Arith(op, a, b) => {
+                let (a, b) = (a.eval(layer), b.eval(layer));
+                match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => a / b,
+                }
+            }
+        }
+    }
+}
+
+impl RuleExpr {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            layers: (0, 1),
+            end: source.chars().count(),
+        };
+        let root = parser.expr()?.into_cond(1)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(expr_error(
+                token.column,
+                "expected the end of the expression",
+            ));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root: Arc::new(root),
+            layers: parser.layers.0,
+            layers_column: parser.layers.1,
+        })
+    }
+
+    /// Number of layers the expression reads, i.e. one more than its highest `n` index
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    /// Fail if the expression reads more than the given number of layers
+    pub fn check_layers(&self, available: usize) -> Result<(), Error> {
+        if self.layers <= available {
+            return Ok(());
+        }
+        Err(expr_error(
+            self.layers_column,
+            format!(
+                "n{} is out of range for a kernel with {available} layers",
+                self.layers - 1
+            ),
+        ))
+    }
+
+    /// Next state of a cell, given its current state and each layer's normalized count.
+    /// Panics if there are fewer counts than `layers`.
+    pub fn eval(&self, center: bool, counts: &[f32]) -> bool {
+        self.root.eval(center, &|i| counts[i])
+    }
+
+    /// Like `eval`, with the normalized count for a layer computed on demand
+    pub fn eval_with(&self, center: bool, layer: impl Fn(usize) -> f32) -> bool {
+        self.root.eval(center, &layer)
+    }
+
+    /// An evaluator for use as a decider, sharing the parsed expression
+    pub fn compile(&self) -> CompiledExpr {
+        let root = self.root.clone();
+        Box::new(move |center, counts| root.eval(center, &|i| counts[i]))
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Display for RuleExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl PartialEq for RuleExpr {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+fn expr_error(column: usize, message: impl Into<String>) -> Error {
+    Error::RuleExpr {
+        column,
+        message: message.into(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Number(f32),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+struct Token {
+    kind: TokenKind,
+    /// 1-based, in characters
+    column: usize,
+}
+
+/// Longest first, so that e.g. `<=` isn't read as `<` then `=`
+const SYMBOLS: [&str; 18] = [
+    "..", "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "?", ":", "(", ")", "+", "-", "*", "/",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            // A fraction, but not the start of a range
+            if chars.get(i) == Some(&'.') && chars.get(i + 1) != Some(&'.') {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse()
+                .map_err(|_| expr_error(column, format!("invalid number {text:?}")))?;
+            tokens.push(Token {
+                kind: TokenKind::Number(value),
+                column,
+            });
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token {
+                kind: TokenKind::Ident(chars[start..i].iter().collect()),
+                column,
+            });
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(**s))
+                .ok_or_else(|| expr_error(column, format!("unexpected {c:?}")))?;
+            i += symbol.chars().count();
+            tokens.push(Token {
+                kind: TokenKind::Symbol(symbol),
+                column,
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// A parsed subexpression, whose type is checked where it is used
+enum Value {
+    Cond(Cond),
+    Num(Num),
+}
+
+impl Value {
+    fn into_cond(self, column: usize) -> Result<Cond, Error> {
+        match self {
+            Value::Cond(cond) => Ok(cond),
+            Value::Num(_) => Err(expr_error(column, "expected a condition, found a number")),
+        }
+    }
+
+    fn into_num(self, column: usize) -> Result<Num, Error> {
+        match self {
+            Value::Num(num) => Ok(num),
+            Value::Cond(_) => Err(expr_error(column, "expected a number, found a condition")),
+        }
+    }
+}
+
+/// Recursive descent, one method per precedence level
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Number of layers read so far, and the column of the highest index
+    layers: (usize, usize),
+    /// Column just past the last character, for errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    /// Column of the next token, or of the end of the source
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end + 1, |t| t.column)
+    }
+
+    /// Consume the next token if it is one of the given symbols or keywords
+    fn eat(&mut self, options: &[&str]) -> Option<&'static str> {
+        let found = match self.peek()? {
+            TokenKind::Symbol(s) => options.iter().find(|o| *o == s),
+            TokenKind::Ident(word) => options.iter().find(|o| *o == word),
+            TokenKind::Number(_) => None,
+        }?;
+        // Normalize keywords to their symbols
+        let symbol = match *found {
+            "and" => "&&",
+            "or" => "||",
+            "not" => "!",
+            "in" => "in",
+            other => SYMBOLS.iter().find(|s| **s == other).copied()?,
+        };
+        self.pos += 1;
+        Some(symbol)
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Error> {
+        match self.eat(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(expr_error(self.column(), format!("expected {symbol:?}"))),
+        }
+    }
+
+    fn cond(&mut self, parse: fn(&mut Self) -> Result<Value, Error>) -> Result<Cond, Error> {
+        let column = self.column();
+        parse(self)?.into_cond(column)
+    }
+
+    fn num(&mut self, parse: fn(&mut Self) -> Result<Value, Error>) -> Result<Num, Error> {
+        let column = self.column();
+        parse(self)?.into_num(column)
+    }
+
+    fn expr(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let first = self.or()?;
+        if self.eat(&["?"]).is_none() {
+            return Ok(first);
+        }
+        let test = first.into_cond(column)?;
+        let a = self.cond(Self::expr)?;
+        self.expect(":")?;
+        let b = self.cond(Self::expr)?;
+        Ok(Value::Cond(Cond::Ternary(
+            Box::new(test),
+            Box::new(a),
+            Box::new(b),
+        )))
+    }
+
+    fn or(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let mut value = self.and()?;
+        while self.eat(&["||", "or"]).is_some() {
+            let a = value.into_cond(column)?;
+            let b = self.cond(Self::and)?;
+            value = Value::Cond(Cond::Or(Box::new(a), Box::new(b)));
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let mut value = self.not()?;
+        while self.eat(&["&&", "and"]).is_some() {
+            let a = value.into_cond(column)?;
+            let b = self.cond(Self::not)?;
+            value = Value::Cond(Cond::And(Box::new(a), Box::new(b)));
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<Value, Error> {
+        if self.eat(&["!", "not"]).is_some() {
+            let a = self.cond(Self::not)?;
+            return Ok(Value::Cond(Cond::Not(Box::new(a))));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let first = self.sum()?;
+        let ops = ["==", "!=", "<=", ">=", "<", ">", "in"];
+        let Some(op) = self.eat(&ops) else {
+            return Ok(first);
+        };
+        let a = first.into_num(column)?;
+        if op == "in" {
+            let lo = self.num(Self::sum)?;
+            self.expect("..")?;
+            let hi = self.num(Self::sum)?;
+            return Ok(Value::Cond(Cond::In(a, lo, hi)));
+        }
+        let b = self.num(Self::sum)?;
+        let op = match op {
+            "==" => CompareOp::Eq,
+            "!=" => CompareOp::Ne,
+            "<" => CompareOp::Lt,
+            "<=" => CompareOp::Le,
+            ">" => CompareOp::Gt,
+            _ => CompareOp::Ge,
+        };
+        Ok(Value::Cond(Cond::Compare(op, a, b)))
+    }
+
+    fn sum(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let mut value = self.product()?;
+        while let Some(op) = self.eat(&["+", "-"]) {
+            let a = value.into_num(column)?;
+            let b = self.num(Self::product)?;
+            let op = if op == "+" {
+                ArithOp::Add
+            } else {
+                ArithOp::Sub
+            };
+            value = Value::Num(Num::Arith(op, Box::new(a), Box::new(b)));
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let mut value = self.unary()?;
+        while let Some(op) = self.eat(&["*", "/"]) {
+            let a = value.into_num(column)?;
+            let b = self.num(Self::unary)?;
+            let op = if op == "*" {
+                ArithOp::Mul
+            } else {
+                ArithOp::Div
+            };
+            value = Value::Num(Num::Arith(op, Box::new(a), Box::new(b)));
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<Value, Error> {
+        if self.eat(&["-"]).is_some() {
+            let a = self.num(Self::unary)?;
+            return Ok(Value::Num(Num::Neg(Box::new(a))));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Value, Error> {
+        let column = self.column();
+        let Some(token) = self.tokens.get(self.pos) else {
+            return Err(expr_error(column, "unexpected end of expression"));
+        };
+        let value = match &token.kind {
+            TokenKind::Number(value) => Value::Num(Num::Const(*value)),
+            TokenKind::Symbol("(") => {
+                self.pos += 1;
+                let value = self.expr()?;
+                self.expect(")")?;
+                return Ok(value);
+            }
+            TokenKind::Symbol(s) => return Err(expr_error(column, format!("unexpected {s:?}"))),
+            TokenKind::Ident(word) => match word.as_str() {
+                "center" => Value::Cond(Cond::Center),
+                "true" => Value::Cond(Cond::Const(true)),
+                "false" => Value::Cond(Cond::Const(false)),
+                _ => {
+                    let index = word
+                        .strip_prefix('n')
+                        .and_then(|i| i.parse::<usize>().ok())
+                        .ok_or_else(|| expr_error(column, format!("unknown name {word:?}")))?;
+                    if index >= self.layers.0 {
+                        self.layers = (index + 1, column);
+                    }
+                    Value::Num(Num::Layer(index))
+                }
+            },
+        };
+        self.pos += 1;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, center: bool, counts: &[f32]) -> bool {
+        RuleExpr::parse(source).unwrap().eval(center, counts)
+    }
+
+    fn error_column(source: &str) -> usize {
+        match RuleExpr::parse(source) {
+            Err(Error::RuleExpr { column, .. }) => column,
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(_) => panic!("{source:?} parsed"),
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        assert!(eval("n0 in 0.2..0.4", false, &[0.4]));
+        assert!(!eval("n0 in 0.2..0.4", false, &[0.41]));
+        assert!(eval("center ? n0 > 1 : n1 <= 2", false, &[0., 2.]));
+        assert!(!eval("center ? n0 > 1 : n1 <= 2", true, &[0., 2.]));
+        assert!(eval("n0 + n1 * 2 == 5", false, &[1., 2.]));
+        assert!(eval("(n0 + n1) * 2 == 6", false, &[1., 2.]));
+        assert!(eval("-n0 < 0 and not center", false, &[1.]));
+        assert!(eval("false || !(true && false)", false, &[]));
+        assert!(eval("n0 / 4 == .5", false, &[2.]));
+        // Ternaries nest to the right
+        let picks = "n0 < 1 ? false : n0 < 2 ? true : false";
+        assert_eq!(
+            [0.5, 1.5, 2.5].map(|n| eval(picks, false, &[n])),
+            [false, true, false]
+        );
+    }
+
+    #[test]
+    fn test_layers() {
+        assert_eq!(RuleExpr::parse("center").unwrap().layers(), 0);
+        let expr = RuleExpr::parse("n0 < n3").unwrap();
+        assert_eq!(expr.layers(), 4);
+        assert!(expr.check_layers(4).is_ok());
+        match expr.check_layers(2) {
+            Err(Error::RuleExpr { column, .. }) => assert_eq!(column, 6),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let expr = RuleExpr::parse("center || n0 == 3").unwrap();
+        let decide = expr.compile();
+        assert!(decide(true, &[0.]));
+        assert!(decide(false, &[3.]));
+        assert!(!decide(false, &[2.]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error_column("n0 >"), 5);
+        assert_eq!(error_column("n0 + center"), 6);
+        assert_eq!(error_column("center ? n0 : true"), 10);
+        assert_eq!(error_column("x > 1"), 1);
+        assert_eq!(error_column("n0 in 1 2"), 9);
+        assert_eq!(error_column("(center"), 8);
+        assert_eq!(error_column("center center"), 8);
+        assert_eq!(error_column("n0 > 1 # comment"), 8);
+        assert_eq!(error_column("n0 + 1"), 1);
+    }
+}
@@ -0,0 +1,81 @@
+//! Headers identifying persisted files, so that a loader can reject files it doesn't understand
+//! instead of misreading them.
+//!
+//! Every file starts with (or, for text formats, contains) a magic string and a version. Loaders
+//! call `Format::check` and then match on the returned version; that match is where files in an
+//! older layout get upgraded once the format changes.
+
+use crate::error::Error;
+
+/// A kind of persisted file, and the newest version of it this build can read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Format {
+    /// Human readable name, for errors
+    pub name: &'static str,
+    pub magic: &'static str,
+    pub version: u32,
+}
+
+/// `Array2D::write_packed` snapshots
+pub const PACKED_GRID: Format = Format {
+    name: "packed grid",
+    magic: "MNCG",
+    version: 1,
+};
+
+/// Kernel definitions, see `kernel_file`
+pub const KERNEL_FILE: Format = Format {
+    name: "kernel file",
+    magic: "mnca-kernel",
+    version: 1,
+};
+
+/// `EventLog` recordings
+pub const EVENT_LOG: Format = Format {
+    name: "event log",
+    magic: "mnca-events",
+    version: 1,
+};
+
+impl Format {
+    /// Check the header of a file, returning its version. Versions newer than this build (or 0)
+    /// are rejected.
+    pub fn check(&self, magic: &str, version: u32) -> Result<u32, Error> {
+        if magic != self.magic {
+            return Err(Error::BadMagic {
+                format: self.name,
+                found: magic.to_string(),
+            });
+        }
+        if version == 0 || version > self.version {
+            return Err(Error::UnsupportedVersion {
+                format: self.name,
+                version,
+            });
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(EVENT_LOG.check("mnca-events", 1).unwrap(), 1);
+        assert!(matches!(
+            EVENT_LOG.check("mnca-kernel", 1),
+            Err(Error::BadMagic {
+                format: "event log",
+                ..
+            })
+        ));
+        for version in [0, 2] {
+            assert!(matches!(
+                EVENT_LOG.check("mnca-events", version),
+                Err(Error::UnsupportedVersion { version: v, .. }) if v == version
+            ));
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+
+use crate::array2d::Array2D;
+
+/// Bounded record of past frames, for stepping backwards.
+///
+/// A full keyframe is kept every `keyframe_interval` frames, and only the cells which changed
+/// since the previous frame in between. Since MNCA typically change a modest fraction of their
+/// cells per step, this is far smaller than storing every frame; reconstructing a frame costs
+/// replaying at most `keyframe_interval - 1` diffs.
+pub struct History {
+    entries: VecDeque<Entry>,
+    keyframe_interval: usize,
+    /// Maximum number of frames kept
+    capacity: usize,
+    /// Copy of the newest frame, to diff the next one against
+    last: Option<Array2D<bool>>,
+    /// Frames pushed since the last keyframe
+    since_keyframe: usize,
+}
+
+enum Entry {
+    Keyframe(Array2D<bool>),
+    /// Indices of the cells flipped since the previous frame
+    Delta(Vec<usize>),
+}
+
+impl History {
+    /// Panics unless both the keyframe interval and the capacity are positive
+    pub fn new(keyframe_interval: usize, capacity: usize) -> Self {
+        assert!(keyframe_interval > 0, "Keyframe interval must be positive");
+        // With no room, the keyframe would be dropped as soon as it was pushed, leaving the
+        // next frame's delta without a base
+        assert!(capacity > 0, "History capacity must be positive");
+        Self {
+            entries: VecDeque::new(),
+            keyframe_interval,
+            capacity,
+            last: None,
+            since_keyframe: 0,
+        }
+    }
+
+    /// Record a new frame, dropping the oldest if over capacity
+    pub fn push(&mut self, frame: Array2D<bool>) {
+        let same_size = self
+            .last
+            .as_ref()
+            .is_some_and(|last| (last.width(), last.height()) == (frame.width(), frame.height()));
+
+        let entry = match &self.last {
+            Some(last) if same_size && self.since_keyframe < self.keyframe_interval => {
+                self.since_keyframe += 1;
+                Entry::Delta(last.diff(&frame))
+            }
+            // Frames of a different size can't be diffed, so start afresh
+            _ => {
+                self.since_keyframe = 1;
+                Entry::Keyframe(frame.clone())
+            }
+        };
+        self.entries.push_back(entry);
+        self.last = Some(frame);
+
+        while self.entries.len() > self.capacity {
+            self.pop_oldest();
+        }
+    }
+
+    /// The frame pushed `gens_ago` frames before the newest, which is `get(0)`
+    pub fn get(&self, gens_ago: usize) -> Option<Array2D<bool>> {
+        let idx = self.entries.len().checked_sub(gens_ago + 1)?;
+
+        // The front entry is always a keyframe, so this always finds one
+        let key_idx = (0..=idx)
+            .rev()
+            .find(|&i| matches!(self.entries[i], Entry::Keyframe(_)))
+            .expect("History starts with a keyframe");
+
+        let Entry::Keyframe(frame) = &self.entries[key_idx] else {
+            unreachable!()
+        };
+        let mut frame = frame.clone();
+        for entry in self.entries.range(key_idx + 1..=idx) {
+            if let Entry::Delta(diff) = entry {
+                frame.apply_diff(diff);
+            }
+        }
+        Some(frame)
+    }
+
+    /// Number of frames kept
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.last = None;
+        self.since_keyframe = 0;
+    }
+
+    /// Approximate heap usage of the stored frames, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Keyframe(frame) => frame.data().len(),
+                Entry::Delta(diff) => diff.len() * std::mem::size_of::<usize>(),
+            })
+            .sum()
+    }
+
+    /// Drop the oldest frame, promoting the next to a keyframe if it was a delta
+    fn pop_oldest(&mut self) {
+        let Some(Entry::Keyframe(mut frame)) = self.entries.pop_front() else {
+            unreachable!("History starts with a keyframe");
+        };
+        if let Some(next) = self.entries.front_mut() {
+            if let Entry::Delta(diff) = next {
+                frame.apply_diff(diff);
+                *next = Entry::Keyframe(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::Event,
+        kernels::Life,
+        sim::{Dense, Simulation},
+    };
+
+    fn life_frames(n: usize) -> Vec<Array2D<bool>> {
+        let mut sim = Dense::new(Box::new(Life), 8, 8);
+        Event::Seed(3).apply(&mut sim);
+        sim.frames().take(n).collect()
+    }
+
+    #[test]
+    fn test_get_reconstructs_every_frame() {
+        let frames = life_frames(30);
+        let mut history = History::new(8, 100);
+        for frame in &frames {
+            history.push(frame.clone());
+        }
+
+        assert_eq!(history.len(), 30);
+        for (gens_ago, frame) in frames.iter().rev().enumerate() {
+            assert_eq!(history.get(gens_ago).as_ref(), Some(frame), "{gens_ago}");
+        }
+        assert_eq!(history.get(30), None);
+    }
+
+    #[test]
+    fn test_deltas_are_small() {
+        let mut sim = Dense::new(Box::new(Life), 16, 16);
+        for xy in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            sim.set_pixel(xy, true);
+        }
+
+        let mut history = History::new(16, 32);
+        for frame in sim.frames().take(32) {
+            history.push(frame);
+        }
+
+        // Two 32x32 keyframes, plus a handful of flipped cells per glider step
+        let frame_bytes = 32 * 32;
+        assert!(history.memory_bytes() < 3 * frame_bytes);
+    }
+
+    #[test]
+    fn test_capacity_promotes_keyframes() {
+        let frames = life_frames(25);
+        let mut history = History::new(8, 10);
+        for frame in &frames {
+            history.push(frame.clone());
+        }
+
+        assert_eq!(history.len(), 10);
+        for gens_ago in 0..10 {
+            assert_eq!(history.get(gens_ago).as_ref(), Some(&frames[24 - gens_ago]));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be positive")]
+    fn test_zero_capacity_panics() {
+        History::new(4, 0);
+    }
+
+    #[test]
+    fn test_capacity_of_one_keeps_newest() {
+        let frames = life_frames(5);
+        let mut history = History::new(4, 1);
+        for frame in &frames {
+            history.push(frame.clone());
+            assert_eq!(history.len(), 1);
+            assert_eq!(history.get(0).as_ref(), Some(frame));
+        }
+    }
+
+    #[test]
+    fn test_resized_frames() {
+        let mut history = History::new(4, 10);
+        history.push(Array2D::new(2, 2));
+        history.push(Array2D::from_array(3, vec![true; 3]));
+        assert_eq!(history.get(0).unwrap().width(), 3);
+        assert_eq!(history.get(1).unwrap().width(), 2);
+    }
+}
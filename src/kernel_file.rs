@@ -0,0 +1,308 @@
+//! Layered kernels defined in TOML, so that rules can be edited and shared without recompiling.
+//! See `kernels/basic_mnca.toml` for an example.
+
+use std::path::Path;
+
+use toml_edit::{Document, Item, TableLike};
+
+use crate::{
+    array2d::Array2D,
+    error::Error,
+    format::KERNEL_FILE,
+    kernels::{
+        calculate_block_order_from_kernel_width, draw_ring, fit_masks, Band, LayeredKernel,
+        MaskFit, RuleBands,
+    },
+    sim::MAX_ORDER,
+};
+
+/// Load a kernel file from disk
+pub fn load_kernel(path: impl AsRef<Path>) -> Result<LayeredKernel, Error> {
+    parse_kernel(&std::fs::read_to_string(path)?)
+}
+
+/// Parse a kernel file, checking that the layers agree in size and give a supported block order
+pub fn parse_kernel(input: &str) -> Result<LayeredKernel, Error> {
+    let (bands, layers) = parse_parts(input)?;
+    LayeredKernel::with_bands(bands, layers)
+}
+
+fn parse_parts(input: &str) -> Result<(RuleBands, Vec<Array2D<bool>>), Error> {
+    let doc: Document = input.parse()?;
+
+    let magic = doc.get("format").and_then(Item::as_str).unwrap_or_default();
+    let version = doc
+        .get("version")
+        .and_then(Item::as_integer)
+        .and_then(|v| u32::try_from(v).ok())
+        .unwrap_or(0);
+    match KERNEL_FILE.check(magic, version)? {
+        1 => parse_v1(&doc),
+        v => unreachable!("check rejects version {v}"),
+    }
+}
+
+fn parse_v1(doc: &Document) -> Result<(RuleBands, Vec<Array2D<bool>>), Error> {
+    let layers = doc
+        .get("layers")
+        .and_then(Item::as_array_of_tables)
+        .ok_or_else(|| invalid("missing [[layers]]"))?
+        .iter()
+        .map(|t| parse_layer(t))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Hand-drawn grids needn't be of a supported width
+    let fitted = fit_masks(&layers)?;
+    if fitted.fit != MaskFit::Unchanged {
+        log::warn!("{}", fitted.fit);
+    }
+    let layers = fitted.masks;
+
+    let norms = match doc.get("norms") {
+        Some(item) => item
+            .as_array()
+            .ok_or_else(|| invalid("norms must be an array"))?
+            .iter()
+            .map(|v| number(v).ok_or_else(|| invalid("norms must be numbers")))
+            .collect::<Result<Vec<_>, _>>()?,
+        // Default to each layer's live cell count, so normalized counts span 0..=1
+        None => layers
+            .iter()
+            .map(|l| l.data().iter().filter(|&&x| x).count() as f32)
+            .collect(),
+    };
+    if norms.len() != layers.len() {
+        return Err(invalid(format!(
+            "{} norms given for {} layers",
+            norms.len(),
+            layers.len()
+        )));
+    }
+
+    let bands = match doc.get("bands") {
+        Some(item) => item
+            .as_array_of_tables()
+            .ok_or_else(|| invalid("bands must be [[bands]] tables"))?
+            .iter()
+            .map(|t| parse_band(t, layers.len()))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![],
+    };
+
+    Ok((RuleBands { norms, bands }, layers))
+}
+
+fn parse_layer(table: &dyn TableLike) -> Result<Array2D<bool>, Error> {
+    if let Some(ring) = table.get("ring") {
+        let ring = ring
+            .as_table_like()
+            .ok_or_else(|| invalid("ring must be a table"))?;
+        let field = |key| {
+            ring.get(key)
+                .and_then(Item::as_integer)
+                .ok_or_else(|| invalid(format!("ring is missing integer {key}")))
+        };
+        let size = usize::try_from(field("size")?).map_err(|_| invalid("negative ring size"))?;
+        let (inner_sq, outer_sq) = (field("inner_sq")?, field("outer_sq")?);
+        let (inner_sq, outer_sq) = i32::try_from(inner_sq)
+            .ok()
+            .zip(i32::try_from(outer_sq).ok())
+            .ok_or_else(|| invalid("ring radius out of range"))?;
+
+        // The size comes from the file, so check it before allocating a mask that big
+        if calculate_block_order_from_kernel_width(size)? > MAX_ORDER {
+            let largest = (1 << MAX_ORDER) + 1;
+            return Err(invalid(format!(
+                "ring size {size} is above the largest supported width, {largest}"
+            )));
+        }
+        let mut layer = Array2D::new(size, size);
+        draw_ring(&mut layer, inner_sq, outer_sq);
+        return Ok(layer);
+    }
+
+    let rows = table
+        .get("grid")
+        .and_then(Item::as_array)
+        .ok_or_else(|| invalid("layer needs either a ring or a grid"))?;
+
+    let mut width = None;
+    let mut data = vec![];
+    for row in rows {
+        let row = row
+            .as_array()
+            .ok_or_else(|| invalid("grid rows must be arrays"))?;
+        if *width.get_or_insert(row.len()) != row.len() {
+            return Err(invalid("grid rows differ in length"));
+        }
+        for cell in row {
+            match cell.as_integer() {
+                Some(0) => data.push(false),
+                Some(1) => data.push(true),
+                _ => return Err(invalid("grid cells must be 0 or 1")),
+            }
+        }
+    }
+
+    Array2D::try_from_array(width.unwrap_or(0), data)
+}
+
+fn parse_band(table: &dyn TableLike, n_layers: usize) -> Result<Band, Error> {
+    let get = |key| {
+        table
+            .get(key)
+            .and_then(Item::as_value)
+            .ok_or_else(|| invalid(format!("band is missing {key}")))
+    };
+
+    let layer = get("layer")?
+        .as_integer()
+        .and_then(|l| usize::try_from(l).ok())
+        .filter(|&l| l < n_layers)
+        .ok_or_else(|| invalid(format!("band layer must be below {n_layers}")))?;
+    let lo = number(get("lo")?).ok_or_else(|| invalid("band lo must be a number"))?;
+    let hi = number(get("hi")?).ok_or_else(|| invalid("band hi must be a number"))?;
+    let set = get("set")?
+        .as_bool()
+        .ok_or_else(|| invalid("band set must be a boolean"))?;
+
+    Ok(Band { layer, lo, hi, set })
+}
+
+/// Floats may also be written as integers
+fn number(value: &toml_edit::Value) -> Option<f32> {
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|i| i as f64))
+        .map(|f| f as f32)
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::KernelFile(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kernels::{basic_mnca_bands, basic_mnca_layers},
+        sim::Kernel,
+    };
+
+    #[test]
+    fn test_basic_mnca_file() {
+        let (bands, layers) = parse_parts(include_str!("../kernels/basic_mnca.toml")).unwrap();
+        assert_eq!(bands, basic_mnca_bands());
+        assert_eq!(layers, basic_mnca_layers());
+    }
+
+    #[test]
+    fn test_grid_layer() {
+        let input = "
+            format = 'mnca-kernel'
+            version = 1
+            [[layers]]
+            grid = [[1, 1, 1], [1, 0, 1], [1, 1, 1]]
+
+            [[bands]]
+            layer = 0
+            lo = 3
+            hi = 3
+            set = true
+        ";
+        let (bands, layers) = parse_parts(input).unwrap();
+        assert_eq!(bands.norms, [8.0]);
+        assert_eq!(bands.bands[0].lo, 3.0);
+        assert_eq!(layers[0].data().iter().filter(|&&x| x).count(), 8);
+        assert!(!layers[0][(1, 1)]);
+        assert!(parse_kernel(input).is_ok());
+    }
+
+    #[test]
+    fn test_grid_is_fitted() {
+        let input = "
+            format = 'mnca-kernel'
+            version = 1
+            [[layers]]
+            grid = [
+                [0, 0, 0, 0, 0, 0, 0],
+                [0, 1, 1, 1, 1, 1, 0],
+                [0, 1, 0, 0, 0, 1, 0],
+                [0, 1, 0, 0, 0, 1, 0],
+                [0, 1, 0, 0, 0, 1, 0],
+                [0, 1, 1, 1, 1, 1, 0],
+                [0, 0, 0, 0, 0, 0, 0],
+            ]
+        ";
+        let (bands, layers) = parse_parts(input).unwrap();
+        assert_eq!((layers[0].width(), layers[0].height()), (5, 5));
+        assert_eq!(bands.norms, [16.0]);
+        assert_eq!(parse_kernel(input).unwrap().order(), 2);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let bad_width = "
+            format = 'mnca-kernel'
+            version = 1
+            [[layers]]
+            grid = [[1, 1], [1, 1]]
+        ";
+        assert!(matches!(
+            parse_kernel(bad_width),
+            Err(Error::InvalidKernelWidth(2))
+        ));
+
+        let mismatch = "
+            format = 'mnca-kernel'
+            version = 1
+            [[layers]]
+            ring = { inner_sq = 1, outer_sq = 4, size = 5 }
+            [[layers]]
+            ring = { inner_sq = 1, outer_sq = 4, size = 9 }
+        ";
+        assert!(matches!(
+            parse_kernel(mismatch),
+            Err(Error::ShapeMismatch { .. })
+        ));
+
+        // Rejected before a mask of that size is allocated
+        let huge = |size: u64| {
+            format!(
+                "format = 'mnca-kernel'\nversion = 1\n[[layers]]\n\
+                 ring = {{ inner_sq = 0, outer_sq = 4, size = {size} }}"
+            )
+        };
+        assert!(matches!(
+            parse_kernel(&huge((1 << 40) + 1)),
+            Err(Error::KernelFile(_))
+        ));
+        assert!(matches!(
+            parse_kernel(&huge(1 << 40)),
+            Err(Error::InvalidKernelWidth(_))
+        ));
+
+        let bad_band = "
+            format = 'mnca-kernel'
+            version = 1
+            [[layers]]
+            ring = { inner_sq = 1, outer_sq = 4, size = 5 }
+            [[bands]]
+            layer = 1
+            lo = 0.1
+            hi = 0.2
+            set = true
+        ";
+        assert!(matches!(parse_kernel(bad_band), Err(Error::KernelFile(_))));
+
+        assert!(matches!(parse_kernel("layers = ["), Err(Error::Toml(_))));
+        assert!(matches!(
+            parse_kernel("format = 'mnca-kernel'\nversion = 2"),
+            Err(Error::UnsupportedVersion { version: 2, .. })
+        ));
+        assert!(matches!(
+            parse_kernel("[[layers]]\nring = { inner_sq = 1, outer_sq = 4, size = 5 }"),
+            Err(Error::BadMagic { .. })
+        ));
+    }
+}
@@ -1,8 +1,20 @@
-use egui::epaint::ahash::HashMap;
+use std::{
+    num::NonZeroUsize,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use ahash::HashMap;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     array2d::Array2D,
-    sim::{calc_block_width, Block, Kernel, KernelResult},
+    error::Error,
+    expr::RuleExpr,
+    sim::{calc_block_width, check_order, Block, Kernel, KernelResult},
 };
 
 pub struct Life;
@@ -12,7 +24,11 @@ impl Kernel for Life {
         1
     }
 
-    fn exec(&mut self, blocks: [Block; 4]) -> (Block, KernelResult) {
+    fn zero_stable(&self) -> bool {
+        true
+    }
+
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
         // Collect everything into a dense buffer
         // TODO: Don't allocate in hot loops lol
         let mut buf: Array2D<u8> = Array2D::new(4, 4);
@@ -55,334 +71,2761 @@ impl Kernel for Life {
             };
         }
 
-        let out_block = Array2D::from_array(2, out_data);
+        let out_block = Block::from_array(2, out_data);
 
         (out_block, KernelResult::NewBlock)
     }
 }
 
-pub struct LayeredKernel {
-    /// Given the center cell a number of neighbors overlapping the
-    /// "live" cells of each, this function returns next state of the center cell
-    decider: fn(bool, &[u16]) -> bool,
-    /// Masks from which to interpret layers
-    layers: Vec<Array2D<bool>>,
-    block_order: usize,
+/// A Margolus (block partitioning) rule: each step replaces every 2x2 block of the current
+/// partition according to a lookup table, and the partition alternates between the two block
+/// phases, so two steps make one generation. See `Simulation::zero_borders` for the layout.
+///
+/// Cells of a 2x2 block are numbered as bits: 1 top left, 2 top right, 4 bottom left and
+/// 8 bottom right. Only the low four bits of each table entry are used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MargolusKernel {
+    table: [u8; 16],
 }
 
-impl LayeredKernel {
-    pub fn new(decider: fn(bool, &[u16]) -> bool, layers: Vec<Array2D<bool>>) -> Self {
-        let dims = (layers[0].width(), layers[0].height());
-        assert!(
-            layers.iter().all(|l| (l.width(), l.height()) == dims),
-            "All kernel layers must be the same size"
-        );
-
-        let (width, _height) = dims;
-        let block_order = calculate_block_order_from_kernel_width(width);
-        dbg!(block_order);
-
+impl MargolusKernel {
+    pub fn new(table: [u8; 16]) -> Self {
         Self {
-            decider,
-            layers,
-            block_order,
+            table: table.map(|cells| cells & 0b1111),
         }
     }
+
+    /// Tabulate a rule given as a function from a block's cells to their replacement
+    pub fn from_fn(mut f: impl FnMut(u8) -> u8) -> Self {
+        Self::new(std::array::from_fn(|cells| f(cells as u8)))
+    }
+
+    /// The replacement for a block's cells
+    pub fn apply(&self, cells: u8) -> u8 {
+        self.table[usize::from(cells & 0b1111)]
+    }
 }
 
-impl Kernel for LayeredKernel {
+impl Kernel for MargolusKernel {
     fn order(&self) -> usize {
-        self.block_order
+        1
     }
 
-    fn exec(&mut self, blocks: [Block; 4]) -> (Block, KernelResult) {
-        let w = calc_block_width(&*self);
-        assert_eq!(w, blocks[0].width());
-
-        // Copy everything into a 2D buffer of u8s to make this easier
-        let mut buf: Array2D<bool> = Array2D::new(w * 2, w * 2);
+    fn zero_stable(&self) -> bool {
+        self.table[0] == 0
+    }
 
-        // For each block
-        for i in 0..2 {
-            for j in 0..2 {
-                let block = &blocks[i + 2 * j];
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
+        // The output covers the center of the four inputs: one cell from the opposite corner of
+        // each. That square is the block of the next partition.
+        let corners = [(1, 1), (0, 1), (1, 0), (0, 0)];
+        let cells = (0..4).fold(0, |acc, i| acc | u8::from(blocks[i][corners[i]]) << i);
+        let out = self.apply(cells);
+        let block = Block::from_fn(2, 2, |x, y| out & (1 << (x + 2 * y)) != 0);
+        (block, KernelResult::NewBlock)
+    }
+}
 
-                // For each pixel
-                for x in 0..w {
-                    for y in 0..w {
-                        buf[(x + i * w, y + j * w)] = block[(x, y)];
-                    }
-                }
-            }
-        }
+/// A range of normalized neighbor counts on one layer, which sets the center cell when hit
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Band {
+    pub layer: usize,
+    /// Inclusive bounds on the normalized count
+    pub lo: f32,
+    pub hi: f32,
+    /// State the center cell takes if the count falls within the band
+    pub set: bool,
+}
 
-        // Now calculate the counts by using a sliding window
-        let mut out_data = vec![];
-        for j in 0..w {
-            for i in 0..w {
-                let mut counts = vec![0_u16; self.layers.len()];
-                let center = (
-                    self.layers[0].width() / 2 + i,
-                    self.layers[0].height() / 2 + j,
-                );
-                for (layer, count) in self.layers.iter().zip(&mut counts) {
-                    for y in 0..layer.height() {
-                        for x in 0..layer.width() {
-                            if layer[(x, y)] && buf[(i + x, j + y)] {
-                                *count += 1;
-                            }
-                        }
-                    }
-                }
+/// A data-driven decider, in the style of `basic_mnca`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RuleBands {
+    /// Per-layer divisor turning raw counts into normalized counts
+    pub norms: Vec<f32>,
+    /// Bands are applied in order, so later bands take precedence
+    pub bands: Vec<Band>,
+}
 
-                let result = (self.decider)(buf[center], &counts);
-                out_data.push(result);
+impl RuleBands {
+    pub fn decide(&self, mut center: bool, counts: &[u16]) -> bool {
+        for band in &self.bands {
+            let avg = counts[band.layer] as f32 / self.norms[band.layer];
+            if avg >= band.lo && avg <= band.hi {
+                center = band.set;
             }
         }
+        center
+    }
+}
 
-        let out_block = Array2D::from_array(w, out_data);
+/// A rule expression, whose `n` values are each layer's count over its norm
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExprRule {
+    pub expr: RuleExpr,
+    /// Per-layer divisor turning raw counts into normalized counts
+    pub norms: Vec<f32>,
+}
 
-        (out_block, KernelResult::NewBlock)
+impl ExprRule {
+    pub fn decide(&self, center: bool, counts: &[u16]) -> bool {
+        self.expr
+            .eval_with(center, |i| counts[i] as f32 / self.norms[i])
     }
 }
 
-/// Given a kernel's width, decide the appropriate block order
-/// Returns None if the width is invalid
-fn calculate_block_order_from_kernel_width(kernel_width: usize) -> usize {
-    for k in 0..=usize::BITS as usize {
-        let radius = 1 << k;
-        let expected_width = 2 * radius + 1;
-        if kernel_width == expected_width {
-            let order = k + 1;
-            return order;
-        }
+/// Birth and survival ranges of the first layer's count, as in Life-like and Larger than Life rules
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Totalistic {
+    /// Counts at which a dead cell becomes live
+    pub birth: Vec<RangeInclusive<u16>>,
+    /// Counts at which a live cell stays live
+    pub survival: Vec<RangeInclusive<u16>>,
+}
+
+impl Totalistic {
+    pub fn decide(&self, center: bool, counts: &[u16]) -> bool {
+        let ranges = if center { &self.survival } else { &self.birth };
+        ranges.iter().any(|r| r.contains(&counts[0]))
     }
+}
 
-    panic!("Invalid kernel size, must be >=3x3 and <=2049x2049")
+/// A named number which a `Decider::Tuned` reads, and which may change while the kernel runs
+pub struct Param {
+    name: String,
+    range: RangeInclusive<f32>,
+    /// Bits of the current f32 value
+    value: AtomicU32,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Param {
+    pub fn new(name: impl Into<String>, range: RangeInclusive<f32>, value: f32) -> Self {
+        Self {
+            name: name.into(),
+            range,
+            value: AtomicU32::new(value.to_bits()),
+        }
+    }
 
-    #[test]
-    fn test_block_order() {
-        assert_eq!(calculate_block_order_from_kernel_width(3), 1);
-        assert_eq!(calculate_block_order_from_kernel_width(5), 2);
-        assert_eq!(calculate_block_order_from_kernel_width(9), 3);
-        assert_eq!(calculate_block_order_from_kernel_width(17), 4);
-        assert_eq!(calculate_block_order_from_kernel_width(33), 5);
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    #[test]
-    #[should_panic]
-    fn test_block_order_panic() {
-        calculate_block_order_from_kernel_width(8);
+    pub fn range(&self) -> RangeInclusive<f32> {
+        self.range.clone()
     }
 
-    #[test]
-    #[should_panic]
-    fn test_block_order_panic2() {
-        calculate_block_order_from_kernel_width(usize::MAX);
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
     }
 
-    #[test]
-    #[should_panic]
-    fn test_block_order_panic3() {
-        calculate_block_order_from_kernel_width(1);
+    /// Set the value, clamped to the range. Results a `KernelCache` memoized from the old value
+    /// are not invalidated; clear it afterwards.
+    pub fn set(&self, value: f32) {
+        let value = value.clamp(*self.range.start(), *self.range.end());
+        self.value.store(value.to_bits(), Ordering::Relaxed);
     }
 }
 
-pub fn life_layered_kernel() -> LayeredKernel {
-    fn decider(center: bool, counts: &[u16]) -> bool {
-        let neighbors = counts[0];
-        if center {
-            matches!(neighbors, 2 | 3)
-        } else {
-            matches!(neighbors, 3)
+/// A decider which may capture state, such as thresholds chosen at runtime. Kernels are shared
+/// between threads and step blocks through `&self`, so it can't mutate what it captures.
+pub type DeciderFn = Box<dyn Fn(bool, &[u16]) -> bool + Send + Sync>;
+
+/// Given the center cell a number of neighbors overlapping the
+/// "live" cells of each layer, returns next state of the center cell
+pub enum Decider {
+    Fn(DeciderFn),
+    /// Also given the current value of each parameter, in order
+    Tuned(fn(bool, &[u16], &[f32]) -> bool, Arc<[Param]>),
+    Bands(RuleBands),
+    Totalistic(Totalistic),
+    Expr(ExprRule),
+}
+
+impl Decider {
+    pub fn decide(&self, center: bool, counts: &[u16]) -> bool {
+        match self {
+            Decider::Fn(f) => f(center, counts),
+            Decider::Tuned(f, params) => f(center, counts, &param_values(params)),
+            Decider::Bands(bands) => bands.decide(center, counts),
+            Decider::Totalistic(rule) => rule.decide(center, counts),
+            Decider::Expr(rule) => rule.decide(center, counts),
         }
     }
+}
 
-    let kernel = [
-        [1, 1, 1], // .
-        [1, 0, 1], // .
-        [1, 1, 1], // .
-    ];
+fn param_values(params: &[Param]) -> Vec<f32> {
+    params.iter().map(Param::get).collect()
+}
 
-    let kernel = kernel
-        .into_iter()
-        .map(|row| row.into_iter())
-        .flatten()
-        .map(|i| i == 1)
-        .collect();
-    let kernel = Array2D::from_array(3, kernel);
+pub struct LayeredKernel {
+    decider: Decider,
+    /// Masks from which to interpret layers
+    layers: Vec<Array2D<bool>>,
+    /// Per-layer cell weights, or None where every live cell of the mask counts once
+    weights: Vec<Option<Array2D<u8>>>,
+    /// For each layer whose live cells form a filled rectangle, the bounds of that rectangle.
+    /// These layers are counted with an integral image instead of scanning the whole mask.
+    rects: Vec<Option<MaskRect>>,
+    block_order: usize,
+    histogram: Option<Arc<CountHistogram>>,
+}
 
-    LayeredKernel::new(decider, vec![kernel])
+/// Collects the normalized neighbor counts a `LayeredKernel` computes, to show which ranges cells
+/// actually experience when placing decider bands. Recording is off until enabled, so an attached
+/// but idle histogram costs one atomic load per block.
+///
+/// Only blocks the kernel itself evaluates are counted; results served by a `KernelCache` are not.
+pub struct CountHistogram {
+    enabled: AtomicBool,
+    /// Per-layer divisor turning raw counts into normalized counts, as in `RuleBands`
+    norms: Vec<f32>,
+    bins: Mutex<Vec<Vec<u64>>>,
 }
 
-pub fn larger_than_life_layered_kernel() -> LayeredKernel {
-    fn decider(center: bool, counts: &[u16]) -> bool {
-        let neighbors = counts[0];
-        let mut output = center;
+impl CountHistogram {
+    /// Number of bins spanning normalized counts 0..=1
+    pub const BINS: usize = 32;
 
-        if neighbors <= 33 {
-            output = false;
+    fn new(norms: Vec<f32>) -> Self {
+        let bins = vec![vec![0; Self::BINS]; norms.len()];
+        Self {
+            enabled: AtomicBool::new(false),
+            norms,
+            bins: Mutex::new(bins),
         }
-        if neighbors >= 34 && neighbors <= 45 {
-            output = true;
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Per-layer bin counts. Bin i covers normalized counts in [i, i + 1) / BINS, and the last bin
+    /// also holds everything above 1.
+    pub fn bins(&self) -> Vec<Vec<u64>> {
+        self.lock().clone()
+    }
+
+    pub fn clear(&self) {
+        for layer in self.lock().iter_mut() {
+            layer.fill(0);
         }
-        if neighbors >= 58 && neighbors <= 121 {
-            output = false;
+    }
+
+    fn bin(&self, layer: usize, count: u16) -> usize {
+        let avg = count as f32 / self.norms[layer];
+        ((avg * Self::BINS as f32) as usize).min(Self::BINS - 1)
+    }
+
+    fn merge(&self, local: &[Vec<u64>]) {
+        for (total, local) in self.lock().iter_mut().zip(local) {
+            for (t, l) in total.iter_mut().zip(local) {
+                *t += l;
+            }
         }
+    }
 
-        output
+    fn lock(&self) -> MutexGuard<'_, Vec<Vec<u64>>> {
+        self.bins.lock().expect("Histogram lock poisoned")
     }
+}
 
-    let kernel = [
-        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
-        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-    ];
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is specified and so stable between releases
+struct Fnv1a(u64);
 
-    let kernel = kernel
-        .into_iter()
-        .map(|row| row.into_iter())
-        .flatten()
-        .map(|i| i == 1)
-        .collect();
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
 
-    let kernel = Array2D::from_array(17, kernel);
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
 
-    print_array(&kernel);
+    /// Lengths are written as u64 so that the hash doesn't depend on the pointer width
+    fn write_usize(&mut self, n: usize) {
+        self.write(&(n as u64).to_le_bytes());
+    }
+}
 
-    LayeredKernel::new(decider, vec![kernel])
+/// Half-open rectangle within a mask
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct MaskRect {
+    min: (usize, usize),
+    max: (usize, usize),
 }
 
-pub fn basic_mnca() -> LayeredKernel {
-    let mut layer0 = Array2D::new(17, 17);
-    draw_ring(&mut layer0, 5 * 5, 8 * 7);
-    print_array(&layer0);
-    println!("{}", count_true(&layer0));
-    println!();
+impl MaskRect {
+    /// Returns the bounds of the mask's live cells, if they fill that rectangle exactly
+    fn from_mask(mask: &Array2D<bool>) -> Option<Self> {
+        let mut min = (usize::MAX, usize::MAX);
+        let mut max = (0, 0);
+        let mut count = 0;
+        for y in 0..mask.height() {
+            for x in 0..mask.width() {
+                if mask[(x, y)] {
+                    min = (min.0.min(x), min.1.min(y));
+                    max = (max.0.max(x + 1), max.1.max(y + 1));
+                    count += 1;
+                }
+            }
+        }
 
-    let mut layer1 = Array2D::new(17, 17);
-    draw_ring(&mut layer1, 1, 3 * 4);
-    print_array(&layer1);
-    println!("{}", count_true(&layer1));
+        let area = max.0.saturating_sub(min.0) * max.1.saturating_sub(min.1);
+        (count > 0 && count == area).then_some(Self { min, max })
+    }
+}
+
+impl LayeredKernel {
+    /// Panics if the layers are invalid; see `try_new`
+    #[track_caller]
+    pub fn new(
+        decider: impl Fn(bool, &[u16]) -> bool + Send + Sync + 'static,
+        layers: Vec<Array2D<bool>>,
+    ) -> Self {
+        Self::try_new(decider, layers).unwrap_or_else(|e| panic!("{e}"))
+    }
 
-    fn decider(mut center: bool, counts: &[u16]) -> bool {
-        let avg = [counts[0] as f32 / 108.0, counts[1] as f32 / 36.0];
+    /// Fails unless the layers are non-empty, equally sized, and of a supported width
+    pub fn try_new(
+        decider: impl Fn(bool, &[u16]) -> bool + Send + Sync + 'static,
+        layers: Vec<Array2D<bool>>,
+    ) -> Result<Self, Error> {
+        Self::with_decider(Decider::Fn(Box::new(decider)), layers)
+    }
 
-        if avg[0] >= 0.210 && avg[0] <= 0.220 {
-            center = true;
-        }
-        if avg[0] >= 0.350 && avg[0] <= 0.500 {
-            center = false;
-        }
-        if avg[0] >= 0.750 && avg[0] <= 0.850 {
-            center = false;
+    pub fn with_bands(bands: RuleBands, layers: Vec<Array2D<bool>>) -> Result<Self, Error> {
+        Self::with_decider(Decider::Bands(bands), layers)
+    }
+
+    /// Decide with a rule expression (see `expr`), normalizing each layer's count by its size
+    pub fn with_expr(source: &str, layers: Vec<Array2D<bool>>) -> Result<Self, Error> {
+        let norms = layers.iter().map(|l| count_true(l) as f32).collect();
+        let expr = RuleExpr::parse(source)?;
+        Self::with_decider(Decider::Expr(ExprRule { expr, norms }), layers)
+    }
+
+    /// Fails unless the layers are non-empty, equally sized, and of a supported width, and an
+    /// expression decider reads only layers which exist
+    pub fn with_decider(decider: Decider, layers: Vec<Array2D<bool>>) -> Result<Self, Error> {
+        if let Decider::Expr(rule) = &decider {
+            rule.expr.check_layers(layers.len().min(rule.norms.len()))?;
         }
-        if avg[1] >= 0.100 && avg[1] <= 0.280 {
-            center = false;
+        let first = layers.first().ok_or(Error::NoLayers)?;
+        let expected = (first.width(), first.height());
+        for layer in &layers {
+            let found = (layer.width(), layer.height());
+            if found != expected {
+                return Err(Error::ShapeMismatch { expected, found });
+            }
         }
-        if avg[1] >= 0.430 && avg[1] <= 0.550 {
-            center = true;
+
+        let (width, _height) = expected;
+        let block_order = calculate_block_order_from_kernel_width(width)?;
+        check_order(block_order)?;
+
+        let rects = layers.iter().map(MaskRect::from_mask).collect();
+
+        Ok(Self {
+            decider,
+            weights: vec![None; layers.len()],
+            layers,
+            rects,
+            block_order,
+            histogram: None,
+        })
+    }
+
+    /// Like `with_decider`, but each layer gives every cell an integer weight, and a layer's
+    /// count is the total weight of its live cells. Cells of weight zero are outside the layer,
+    /// and a layer of only zeros and ones is the same as the boolean mask. Also fails if a
+    /// layer's total weight doesn't fit in a `u16`.
+    pub fn with_weights(decider: Decider, weights: Vec<Array2D<u8>>) -> Result<Self, Error> {
+        for (layer, w) in weights.iter().enumerate() {
+            let total = total_weight(w);
+            if total > usize::from(u16::MAX) {
+                return Err(Error::WeightOverflow { layer, total });
+            }
         }
-        if avg[0] >= 0.120 && avg[0] <= 0.150 {
-            center = false;
+
+        let layers = weights
+            .iter()
+            .map(|w| Array2D::from_fn(w.width(), w.height(), |x, y| w[(x, y)] > 0))
+            .collect();
+        let mut kernel = Self::with_decider(decider, layers)?;
+
+        // Unit weights keep the boolean paths, including the integral image for rectangles
+        for ((w, slot), rect) in weights
+            .into_iter()
+            .zip(&mut kernel.weights)
+            .zip(&mut kernel.rects)
+        {
+            if w.data().iter().any(|&w| w > 1) {
+                *slot = Some(w);
+                *rect = None;
+            }
         }
+        Ok(kernel)
+    }
 
-        center
+    /// Neighborhood masks, each centered on the cell being decided. For a weighted layer this
+    /// is the cells of nonzero weight.
+    pub fn layers(&self) -> &[Array2D<bool>] {
+        &self.layers
     }
 
-    LayeredKernel::new(decider, vec![layer0, layer1])
-}
+    /// The cell weights of the given layer, or None if each of its live cells counts once
+    pub fn weights(&self, layer: usize) -> Option<&Array2D<u8>> {
+        self.weights[layer].as_ref()
+    }
 
-fn print_array(arr: &Array2D<bool>) {
-    for row in arr.data().chunks_exact(arr.width()) {
-        for &elem in row {
-            if elem {
-                print!("# ");
-            } else {
-                print!("- ");
-            }
+    /// The largest count the given layer can produce: its total weight
+    fn max_count(&self, layer: usize) -> usize {
+        match &self.weights[layer] {
+            Some(weights) => total_weight(weights),
+            None => count_true(&self.layers[layer]),
         }
-        println!();
     }
-}
 
-fn draw_ring(arr: &mut Array2D<bool>, inner_sq: i32, outer_sq: i32) {
-    let w = (arr.width() / 2) as i32;
-    for x in -w..=w {
-        for y in -w..=w {
-            let r2 = x.pow(2) + y.pow(2);
-            if r2 >= inner_sq && r2 < outer_sq {
-                let i = (x + w) as usize;
-                let j = (y + w) as usize;
-                arr[(i, j)] = true;
+    /// A stable identifier for the rule, equal for structurally identical kernels across builds
+    /// and platforms. None unless the decider is `Bands`; functions can't be inspected at all.
+    pub fn fingerprint(&self) -> Option<u64> {
+        let Decider::Bands(bands) = &self.decider else {
+            return None;
+        };
+
+        let mut hash = Fnv1a::new();
+        hash.write_usize(self.layers.len());
+        for layer in &self.layers {
+            hash.write_usize(layer.width());
+            hash.write_usize(layer.height());
+            for &cell in layer.data() {
+                hash.write(&[u8::from(cell)]);
             }
         }
+        // Only weighted kernels hash their weights, so boolean kernels keep their fingerprints
+        for (layer, weights) in self.weights.iter().enumerate() {
+            if let Some(weights) = weights {
+                hash.write_usize(layer);
+                hash.write(weights.data());
+            }
+        }
+
+        hash.write_usize(bands.norms.len());
+        for norm in &bands.norms {
+            hash.write(&norm.to_bits().to_le_bytes());
+        }
+        hash.write_usize(bands.bands.len());
+        for band in &bands.bands {
+            hash.write_usize(band.layer);
+            hash.write(&band.lo.to_bits().to_le_bytes());
+            hash.write(&band.hi.to_bits().to_le_bytes());
+            hash.write(&[u8::from(band.set)]);
+        }
+
+        Some(hash.0)
     }
-}
 
-fn count_true(arr: &Array2D<bool>) -> usize {
-    arr.data().iter().filter(|x| **x).count()
-}
+    /// Handle to the live parameters of a `Decider::Tuned`
+    pub fn params(&self) -> Option<Arc<[Param]>> {
+        match &self.decider {
+            Decider::Tuned(_, params) => Some(params.clone()),
+            _ => None,
+        }
+    }
 
-pub struct KernelCache {
-    cache: HashMap<Array2D<bool>, usize>,
-    solutions: HashMap<[usize; 4], usize>,
-    values: Vec<Array2D<bool>>,
-    wrap: Box<dyn Kernel>,
-}
+    /// Attach a histogram of the counts this kernel computes (if not already attached) and return
+    /// a handle to it. Counts are normalized by the bands' norms, or else by each layer's size.
+    pub fn count_histogram(&mut self) -> Arc<CountHistogram> {
+        let norms = self.norms();
+        self.histogram
+            .get_or_insert_with(|| Arc::new(CountHistogram::new(norms)))
+            .clone()
+    }
 
-impl KernelCache {
-    pub fn new(wrap: Box<dyn Kernel>) -> Self {
-        Self { cache: Default::default(), solutions: Default::default(), values: Default::default(), wrap }
+    /// Per-layer divisors turning raw counts into normalized counts: the bands' norms, or else
+    /// each layer's total weight
+    fn norms(&self) -> Vec<f32> {
+        match &self.decider {
+            Decider::Bands(bands) => bands.norms.clone(),
+            Decider::Expr(rule) => rule.norms.clone(),
+            Decider::Fn(_) | Decider::Tuned(..) | Decider::Totalistic(_) => (0..self.layers.len())
+                .map(|l| self.max_count(l) as f32)
+                .collect(),
+        }
     }
-}
 
-impl Kernel for KernelCache {
-    fn order(&self) -> usize {
-        self.wrap.order()
+    /// The normalized count of the given layer around every cell of a grid, i.e. the value the
+    /// decider thresholds against. Cells outside the grid are dead. This is a separate pass
+    /// rather than a by-product of stepping, so it costs nothing unless asked for.
+    pub fn count_field(&self, grid: &Array2D<bool>, layer: usize) -> Array2D<f32> {
+        let mask = &self.layers[layer];
+        let norm = self.norms()[layer];
+        let (rx, ry) = ((mask.width() / 2) as i64, (mask.height() / 2) as i64);
+        let weights = self.weights[layer].as_ref();
+        let offsets: Vec<(i64, i64, f32)> = (0..mask.height())
+            .flat_map(|y| (0..mask.width()).map(move |x| (x, y)))
+            .filter(|&xy| mask[xy])
+            .map(|(x, y)| {
+                let weight = weights.map_or(1.0, |w| f32::from(w[(x, y)]));
+                (x as i64 - rx, y as i64 - ry, weight / norm)
+            })
+            .collect();
+
+        // Scatter each live cell into the counts of the cells whose masks cover it, which is
+        // cheaper than gathering when the grid is sparse
+        let (w, h) = (grid.width() as i64, grid.height() as i64);
+        let mut field = Array2D::new(grid.width(), grid.height());
+        for y in 0..h {
+            for x in 0..w {
+                if !grid[(x as usize, y as usize)] {
+                    continue;
+                }
+                for &(dx, dy, weight) in &offsets {
+                    let (cx, cy) = (x - dx, y - dy);
+                    if (0..w).contains(&cx) && (0..h).contains(&cy) {
+                        field[(cx as usize, cy as usize)] += weight;
+                    }
+                }
+            }
+        }
+        field
     }
 
-    fn exec(&mut self, blocks: [Block; 4]) -> (Block, KernelResult) {
-        let hashes = blocks.clone().map(|block| {
-            *self.cache.entry(block.clone()).or_insert_with(|| {
-                let idx = self.values.len();
-                self.values.push(block);
-                idx
+    /// Step four identical blocks which are all dead or all live. Every cell then sees the same
+    /// counts, so the decider runs once.
+    fn exec_uniform(&self, live: bool) -> Block {
+        let w = calc_block_width(self);
+        let counts: Vec<u16> = (0..self.layers.len())
+            .map(|layer| {
+                if live {
+                    self.max_count(layer) as u16
+                } else {
+                    0
+                }
             })
-        });
+            .collect();
 
-        let block = if let Some(&soln) = self.solutions.get(&hashes) {
-            self.values[soln].clone()
-        } else {
-            let (soln, _) = self.wrap.exec(blocks);
-            let idx = self.values.len();
-            self.values.push(soln.clone());
-            self.solutions.insert(hashes, idx);
-            if self.solutions.len() % 1000 == 0 {
-                dbg!(self.solutions.len());
+        if let Some(histogram) = self.histogram.as_deref().filter(|h| h.is_enabled()) {
+            let mut local_bins = vec![vec![0; CountHistogram::BINS]; self.layers.len()];
+            for (layer, &count) in counts.iter().enumerate() {
+                local_bins[layer][histogram.bin(layer, count)] += (w * w) as u64;
             }
-            soln
-        };
+            histogram.merge(&local_bins);
+        }
 
-        (block, KernelResult::NewBlock)
+        let result = match (&self.decider, self.params().map(|p| param_values(&p))) {
+            (Decider::Tuned(f, _), Some(values)) => f(live, &counts, &values),
+            (decider, _) => decider.decide(live, &counts),
+        };
+        Block::from_array(w, vec![result; w * w])
     }
-}
\ No newline at end of file
+
+    /// Step the blocks, optionally using integral images for rectangular layers
+    fn exec_with(&self, blocks: [Block; 4], integral: bool) -> Block {
+        let w = calc_block_width(self);
+        assert_eq!(w, blocks[0].width());
+
+        let buf = gather_blocks(&blocks);
+
+        // Summed-area table, where sat[(x, y)] counts the live cells of buf above and left of (x, y)
+        let use_integral = integral && self.rects.iter().any(Option::is_some);
+        let sat = use_integral.then(|| integral_image(&buf));
+
+        // Accumulate locally, so the histogram is only locked once per block
+        let histogram = self.histogram.as_deref().filter(|h| h.is_enabled());
+        let mut local_bins =
+            histogram.map(|_| vec![vec![0; CountHistogram::BINS]; self.layers.len()]);
+
+        // Read parameters once, so that a change mid-step can't split a block between values
+        let values = self.params().map(|p| param_values(&p));
+
+        // Now calculate the counts by using a sliding window
+        let mut out_data = vec![];
+        for j in 0..w {
+            for i in 0..w {
+                let mut counts = vec![0_u16; self.layers.len()];
+                let center = (
+                    self.layers[0].width() / 2 + i,
+                    self.layers[0].height() / 2 + j,
+                );
+                let layers = self.layers.iter().zip(&self.weights).zip(&self.rects);
+                for (((layer, weights), rect), count) in layers.zip(&mut counts) {
+                    if let (Some(sat), Some(rect)) = (&sat, rect) {
+                        let (x0, y0) = (rect.min.0 + i, rect.min.1 + j);
+                        let (x1, y1) = (rect.max.0 + i, rect.max.1 + j);
+                        let sum = sat[(x1, y1)] + sat[(x0, y0)] - sat[(x0, y1)] - sat[(x1, y0)];
+                        *count = sum as u16;
+                        continue;
+                    }
+
+                    if let Some(weights) = weights {
+                        for y in 0..layer.height() {
+                            for x in 0..layer.width() {
+                                if buf[(i + x, j + y)] {
+                                    *count += u16::from(weights[(x, y)]);
+                                }
+                            }
+                        }
+                        continue;
+                    }
+
+                    for y in 0..layer.height() {
+                        for x in 0..layer.width() {
+                            if layer[(x, y)] && buf[(i + x, j + y)] {
+                                *count += 1;
+                            }
+                        }
+                    }
+                }
+
+                if let (Some(histogram), Some(local_bins)) = (histogram, &mut local_bins) {
+                    for (layer, &count) in counts.iter().enumerate() {
+                        local_bins[layer][histogram.bin(layer, count)] += 1;
+                    }
+                }
+
+                let result = match (&self.decider, &values) {
+                    (Decider::Tuned(f, _), Some(values)) => f(buf[center], &counts, values),
+                    (decider, _) => decider.decide(buf[center], &counts),
+                };
+                out_data.push(result);
+            }
+        }
+
+        if let (Some(histogram), Some(local_bins)) = (histogram, &local_bins) {
+            histogram.merge(local_bins);
+        }
+
+        Block::from_array(w, out_data)
+    }
+}
+
+/// A kernel whose decider sees the raw neighborhood of each cell instead of per-layer counts.
+/// This can express anisotropic and non-totalistic rules, at the cost of copying out a window
+/// for every cell.
+pub struct GeneralKernel {
+    /// Given the window of cells centered on a cell, returns the next state of that cell
+    decider: fn(&Array2D<bool>) -> bool,
+    /// Width of the (square) window, which must be 2^k + 1
+    width: usize,
+    block_order: usize,
+}
+
+impl GeneralKernel {
+    pub fn new(decider: fn(&Array2D<bool>) -> bool, width: usize) -> Result<Self, Error> {
+        let block_order = calculate_block_order_from_kernel_width(width)?;
+        check_order(block_order)?;
+
+        Ok(Self {
+            decider,
+            width,
+            block_order,
+        })
+    }
+}
+
+impl Kernel for GeneralKernel {
+    fn order(&self) -> usize {
+        self.block_order
+    }
+
+    /// Probes the decider with an empty window
+    fn zero_stable(&self) -> bool {
+        !(self.decider)(&Array2D::new(self.width, self.width))
+    }
+
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
+        let w = calc_block_width(self);
+        assert_eq!(w, blocks[0].width());
+
+        // Every window of a uniform input is the same
+        if let Some(live) = uniform_input(&blocks) {
+            let window = Array2D::from_array(self.width, vec![live; self.width * self.width]);
+            let out = Block::from_array(w, vec![(self.decider)(&window); w * w]);
+            return (out, KernelResult::NewBlock);
+        }
+
+        let buf = gather_blocks(&blocks);
+        let mut window: Array2D<bool> = Array2D::new(self.width, self.width);
+
+        let mut out_data = vec![];
+        for j in 0..w {
+            for i in 0..w {
+                for y in 0..self.width {
+                    let start = i + (j + y) * buf.width();
+                    let row = &buf.data()[start..start + self.width];
+                    window.data_mut()[y * self.width..][..self.width].copy_from_slice(row);
+                }
+                out_data.push((self.decider)(&window));
+            }
+        }
+
+        (Block::from_array(w, out_data), KernelResult::NewBlock)
+    }
+}
+
+/// A directional rule on the Moore neighborhood: each cell copies its left (-x) neighbor,
+/// so every pattern travels one cell in +x per step
+pub fn shift_right_kernel() -> GeneralKernel {
+    fn decider(window: &Array2D<bool>) -> bool {
+        window[(0, 1)]
+    }
+
+    GeneralKernel::new(decider, 3).expect("3x3 is a valid kernel width")
+}
+
+/// Unpack four blocks into a single 2D buffer of twice the width, to make windowing easier.
+/// Each block row is unpacked straight into its slice of the buffer.
+pub(crate) fn gather_blocks(blocks: &[Block; 4]) -> Array2D<bool> {
+    let w = blocks[0].width();
+    let mut buf: Array2D<bool> = Array2D::new(w * 2, w * 2);
+    let data = buf.data_mut();
+
+    for (idx, block) in blocks.iter().enumerate() {
+        let (i, j) = (idx % 2, idx / 2);
+        for y in 0..w {
+            let start = i * w + (y + j * w) * 2 * w;
+            block.unpack_row(y, &mut data[start..start + w]);
+        }
+    }
+
+    buf
+}
+
+/// Like `gather_blocks`, for blocks of other cell types such as multistate ones. Blocks are
+/// row-major, so each block row is copied as one slice.
+pub(crate) fn gather_arrays<T: Copy + Default>(blocks: &[Array2D<T>; 4]) -> Array2D<T> {
+    let w = blocks[0].width();
+    let mut buf: Array2D<T> = Array2D::new(w * 2, w * 2);
+    let data = buf.data_mut();
+
+    for (idx, block) in blocks.iter().enumerate() {
+        let (i, j) = (idx % 2, idx / 2);
+        for (y, row) in block.data().chunks_exact(w).enumerate() {
+            let start = i * w + (y + j * w) * 2 * w;
+            data[start..start + w].copy_from_slice(row);
+        }
+    }
+
+    buf
+}
+
+/// If the four blocks are identical and either all dead or all live, whether they are live. Such
+/// inputs are common in quiescent regions, and a translation-invariant kernel maps them to a
+/// uniform block, which is cheaper to compute (or look up) than the general case.
+pub(crate) fn uniform_input(blocks: &[Block; 4]) -> Option<bool> {
+    let first = &blocks[0];
+    let live = if first.is_empty() {
+        false
+    } else if first.is_full() {
+        true
+    } else {
+        return None;
+    };
+    blocks[1..].iter().all(|b| b == first).then_some(live)
+}
+
+/// Build a summed-area table one larger than the input in each dimension
+fn integral_image(buf: &Array2D<bool>) -> Array2D<u32> {
+    let mut sat: Array2D<u32> = Array2D::new(buf.width() + 1, buf.height() + 1);
+    for y in 0..buf.height() {
+        for x in 0..buf.width() {
+            sat[(x + 1, y + 1)] =
+                u32::from(buf[(x, y)]) + sat[(x, y + 1)] + sat[(x + 1, y)] - sat[(x, y)];
+        }
+    }
+    sat
+}
+
+impl Kernel for LayeredKernel {
+    fn order(&self) -> usize {
+        self.block_order
+    }
+
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
+        let out = match uniform_input(&blocks) {
+            Some(live) => self.exec_uniform(live),
+            None => self.exec_with(blocks, true),
+        };
+        (out, KernelResult::NewBlock)
+    }
+
+    /// Probes the decider with all counts zero. Tuned deciders are probed with their current
+    /// parameters, so the answer may change as they are adjusted.
+    fn zero_stable(&self) -> bool {
+        !self.decider.decide(false, &vec![0; self.layers.len()])
+    }
+}
+
+/// A neighborhood added to a `LayeredKernelBuilder`, drawn once the width is known
+enum Shape {
+    Mask(Array2D<bool>),
+    Ring {
+        inner_sq: i32,
+        outer_sq: i32,
+        size: usize,
+    },
+    Disk(usize),
+}
+
+/// Chainable construction of a `LayeredKernel`, one layer per call to `ring`, `disk` or `mask`
+#[derive(Default)]
+pub struct LayeredKernelBuilder {
+    shapes: Vec<Shape>,
+    decider: Option<Decider>,
+}
+
+impl LayeredKernel {
+    pub fn builder() -> LayeredKernelBuilder {
+        LayeredKernelBuilder::default()
+    }
+}
+
+impl LayeredKernelBuilder {
+    /// Cells whose squared distance from the center is in `inner_sq..outer_sq`, in a mask of
+    /// the given width
+    pub fn ring(mut self, inner_sq: i32, outer_sq: i32, size: usize) -> Self {
+        self.shapes.push(Shape::Ring {
+            inner_sq,
+            outer_sq,
+            size,
+        });
+        self
+    }
+
+    /// Cells within `radius` of the center, including the center. Drawn at the width of the
+    /// other layers, or else the smallest supported width which holds it.
+    pub fn disk(mut self, radius: usize) -> Self {
+        self.shapes.push(Shape::Disk(radius));
+        self
+    }
+
+    pub fn mask(mut self, mask: Array2D<bool>) -> Self {
+        self.shapes.push(Shape::Mask(mask));
+        self
+    }
+
+    pub fn bands(self, bands: RuleBands) -> Self {
+        self.with_decider(Decider::Bands(bands))
+    }
+
+    pub fn decider(self, decider: impl Fn(bool, &[u16]) -> bool + Send + Sync + 'static) -> Self {
+        self.with_decider(Decider::Fn(Box::new(decider)))
+    }
+
+    pub fn with_decider(mut self, decider: Decider) -> Self {
+        self.decider = Some(decider);
+        self
+    }
+
+    /// Fails without a decider, or when the layers can't form a valid kernel
+    /// (see `LayeredKernel::with_decider`)
+    pub fn build(self) -> Result<LayeredKernel, Error> {
+        let decider = self.decider.ok_or(Error::NoDecider)?;
+
+        let explicit = self.shapes.iter().find_map(|shape| match shape {
+            Shape::Mask(mask) => Some(mask.width()),
+            Shape::Ring { size, .. } => Some(*size),
+            Shape::Disk(_) => None,
+        });
+        let largest_disk = self.shapes.iter().filter_map(|shape| match shape {
+            Shape::Disk(radius) => Some(*radius),
+            _ => None,
+        });
+        let width = explicit.unwrap_or_else(|| {
+            let diameter = 2 * largest_disk.max().unwrap_or(1) + 1;
+            nearest_kernel_widths(diameter).1
+        });
+
+        let layers = self
+            .shapes
+            .into_iter()
+            .map(|shape| match shape {
+                Shape::Mask(mask) => Ok(mask),
+                Shape::Ring {
+                    inner_sq,
+                    outer_sq,
+                    size,
+                } => {
+                    let mut layer = Array2D::new(size, size);
+                    draw_ring(&mut layer, inner_sq, outer_sq);
+                    Ok(layer)
+                }
+                Shape::Disk(radius) => {
+                    if 2 * radius + 1 > width {
+                        return Err(Error::ShapeMismatch {
+                            expected: (width, width),
+                            found: (2 * radius + 1, 2 * radius + 1),
+                        });
+                    }
+                    let mut layer = Array2D::new(width, width);
+                    let r_sq = (radius * radius) as i32;
+                    draw_ring(&mut layer, 0, r_sq + 1);
+                    Ok(layer)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        LayeredKernel::with_decider(decider, layers)
+    }
+}
+
+/// Given a kernel's width, decide the appropriate block order
+pub(crate) fn calculate_block_order_from_kernel_width(kernel_width: usize) -> Result<usize, Error> {
+    let radius = kernel_width.saturating_sub(1) / 2;
+    if kernel_width % 2 == 1 && radius.is_power_of_two() {
+        Ok(radius.trailing_zeros() as usize + 1)
+    } else {
+        Err(Error::InvalidKernelWidth(kernel_width))
+    }
+}
+
+/// The supported kernel widths (2^k + 1) nearest to `width`: the largest not above it, if any,
+/// and the smallest not below it
+pub fn nearest_kernel_widths(width: usize) -> (Option<usize>, usize) {
+    let radius = width.saturating_sub(1) / 2;
+    let below = (radius >= 1).then(|| 2 * (1 << radius.ilog2()) + 1);
+    let radius_above = width.saturating_sub(1).div_ceil(2).max(1);
+    (below, 2 * radius_above.next_power_of_two() + 1)
+}
+
+/// How `fit_masks` resized its masks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskFit {
+    Unchanged,
+    /// Trimmed to a smaller width, losing no live cells
+    Cropped {
+        from: usize,
+        to: usize,
+    },
+    /// Surrounded with dead cells up to a larger width
+    Padded {
+        from: usize,
+        to: usize,
+    },
+}
+
+impl std::fmt::Display for MaskFit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaskFit::Unchanged => write!(f, "Mask unchanged"),
+            MaskFit::Cropped { from, to } => {
+                write!(f, "Mask cropped from {from}x{from} to {to}x{to}")
+            }
+            MaskFit::Padded { from, to } => {
+                write!(f, "Mask padded from {from}x{from} to {to}x{to}")
+            }
+        }
+    }
+}
+
+/// Masks resized to a supported kernel width by `fit_masks`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FittedMasks {
+    pub masks: Vec<Array2D<bool>>,
+    /// Block order for the new width
+    pub order: usize,
+    pub fit: MaskFit,
+}
+
+/// Resize masks of a common odd, square width to the nearest supported kernel width, keeping
+/// them centered. Crops to the smaller width if that loses no live cells, and otherwise pads.
+pub fn fit_masks(masks: &[Array2D<bool>]) -> Result<FittedMasks, Error> {
+    let first = masks.first().ok_or(Error::NoLayers)?;
+    let width = first.width();
+    for mask in masks {
+        let found = (mask.width(), mask.height());
+        if found != (width, width) {
+            return Err(Error::ShapeMismatch {
+                expected: (width, width),
+                found,
+            });
+        }
+    }
+    if width.is_multiple_of(2) {
+        // There is no center cell to keep centered
+        return Err(Error::InvalidKernelWidth(width));
+    }
+
+    let (below, above) = nearest_kernel_widths(width);
+    let fit = if above == width {
+        MaskFit::Unchanged
+    } else if let Some(to) = below.filter(|&to| masks.iter().all(|m| fits_within(m, to))) {
+        MaskFit::Cropped { from: width, to }
+    } else {
+        MaskFit::Padded {
+            from: width,
+            to: above,
+        }
+    };
+
+    let to = match fit {
+        MaskFit::Unchanged => width,
+        MaskFit::Cropped { to, .. } | MaskFit::Padded { to, .. } => to,
+    };
+    let masks = masks.iter().map(|m| recenter(m, to)).collect();
+
+    Ok(FittedMasks {
+        masks,
+        order: calculate_block_order_from_kernel_width(to)?,
+        fit,
+    })
+}
+
+/// True if every live cell lies within the centered `width` x `width` square
+fn fits_within(mask: &Array2D<bool>, width: usize) -> bool {
+    let margin = (mask.width() - width) / 2;
+    let inside = margin..margin + width;
+    (0..mask.height())
+        .flat_map(|y| (0..mask.width()).map(move |x| (x, y)))
+        .all(|(x, y)| !mask[(x, y)] || (inside.contains(&x) && inside.contains(&y)))
+}
+
+/// Copy a square mask into a square of another width with the same center, dropping cells which
+/// fall outside
+fn recenter(mask: &Array2D<bool>, width: usize) -> Array2D<bool> {
+    let mut out = Array2D::new(width, width);
+    let shift = |i: usize| (i + width / 2).checked_sub(mask.width() / 2);
+    for y in 0..mask.height() {
+        for x in 0..mask.width() {
+            if let (Some(u), Some(v)) = (shift(x), shift(y)) {
+                if u < width && v < width {
+                    out[(u, v)] = mask[(x, y)];
+                }
+            }
+        }
+    }
+    out
+}
+
+pub fn life_layered_kernel() -> LayeredKernel {
+    fn decider(center: bool, counts: &[u16]) -> bool {
+        let neighbors = counts[0];
+        if center {
+            matches!(neighbors, 2 | 3)
+        } else {
+            matches!(neighbors, 3)
+        }
+    }
+
+    let kernel = [
+        [1, 1, 1], // .
+        [1, 0, 1], // .
+        [1, 1, 1], // .
+    ];
+
+    let kernel = kernel
+        .into_iter()
+        .flat_map(|row| row.into_iter())
+        .map(|i| i == 1)
+        .collect();
+    let kernel = Array2D::from_array(3, kernel);
+
+    LayeredKernel::new(decider, vec![kernel])
+}
+
+pub fn larger_than_life_layered_kernel() -> LayeredKernel {
+    fn decider(center: bool, counts: &[u16]) -> bool {
+        let neighbors = counts[0];
+        let mut output = center;
+
+        if neighbors <= 33 {
+            output = false;
+        }
+        if (34..=45).contains(&neighbors) {
+            output = true;
+        }
+        if (58..=121).contains(&neighbors) {
+            output = false;
+        }
+
+        output
+    }
+
+    let kernel = [
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0],
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    ];
+
+    let kernel = kernel
+        .into_iter()
+        .flat_map(|row| row.into_iter())
+        .map(|i| i == 1)
+        .collect();
+
+    let kernel = Array2D::from_array(17, kernel);
+
+    LayeredKernel::new(decider, vec![kernel])
+}
+
+pub fn basic_mnca() -> LayeredKernel {
+    LayeredKernel::builder()
+        .ring(5 * 5, 8 * 7, 17)
+        .ring(1, 3 * 4, 17)
+        .bands(basic_mnca_bands())
+        .build()
+        .expect("Basic MNCA kernel is valid")
+}
+
+/// `basic_mnca` with its two birth bands exposed as parameters, for tuning while it runs
+pub fn tunable_mnca() -> LayeredKernel {
+    fn decider(center: bool, counts: &[u16], params: &[f32]) -> bool {
+        let outer = counts[0] as f32 / 108.0;
+        let inner = counts[1] as f32 / 36.0;
+        let within = |avg: f32, lo: f32, hi: f32| avg >= lo && avg <= hi;
+
+        let mut output = center;
+        if within(outer, params[0], params[1]) {
+            output = true;
+        }
+        if within(outer, 0.350, 0.500) || within(outer, 0.750, 0.850) {
+            output = false;
+        }
+        if within(inner, 0.100, 0.280) {
+            output = false;
+        }
+        if within(inner, params[2], params[3]) {
+            output = true;
+        }
+        if within(outer, 0.120, 0.150) {
+            output = false;
+        }
+        output
+    }
+
+    let params = [
+        Param::new("Outer birth min", 0.0..=1.0, 0.210),
+        Param::new("Outer birth max", 0.0..=1.0, 0.220),
+        Param::new("Inner birth min", 0.0..=1.0, 0.430),
+        Param::new("Inner birth max", 0.0..=1.0, 0.550),
+    ];
+
+    LayeredKernel::with_decider(Decider::Tuned(decider, params.into()), basic_mnca_layers())
+        .expect("Tunable MNCA kernel is valid")
+}
+
+/// The ring-shaped neighborhoods of `basic_mnca`
+pub fn basic_mnca_layers() -> Vec<Array2D<bool>> {
+    let mut layer0 = Array2D::new(17, 17);
+    draw_ring(&mut layer0, 5 * 5, 8 * 7);
+
+    let mut layer1 = Array2D::new(17, 17);
+    draw_ring(&mut layer1, 1, 3 * 4);
+
+    vec![layer0, layer1]
+}
+
+/// The decider thresholds of `basic_mnca`
+pub fn basic_mnca_bands() -> RuleBands {
+    let band = |layer, lo, hi, set| Band { layer, lo, hi, set };
+
+    RuleBands {
+        norms: vec![108.0, 36.0],
+        bands: vec![
+            band(0, 0.210, 0.220, true),
+            band(0, 0.350, 0.500, false),
+            band(0, 0.750, 0.850, false),
+            band(1, 0.100, 0.280, false),
+            band(1, 0.430, 0.550, true),
+            band(0, 0.120, 0.150, false),
+        ],
+    }
+}
+
+/// Distance on a hex grid stored in axial coordinates, where rows are skewed so that the six
+/// neighbors of a cell are offset by (±1, 0), (0, ±1), (1, -1) and (-1, 1). Every cell has the
+/// same neighbor offsets, so hex neighborhoods are ordinary translation-invariant masks.
+fn hex_distance(dx: i32, dy: i32) -> i32 {
+    (dx.abs() + dy.abs() + (dx + dy).abs()) / 2
+}
+
+/// Cells whose hex distance from the center is in `inner..=outer`, in a mask of the given width;
+/// see `hex_distance`
+pub fn hex_ring(inner: i32, outer: i32, size: usize) -> Array2D<bool> {
+    let c = (size / 2) as i32;
+    Array2D::from_fn(size, size, |x, y| {
+        (inner..=outer).contains(&hex_distance(x as i32 - c, y as i32 - c))
+    })
+}
+
+/// Hexagonal Life (B2/S34H), counting the six hex neighbors of each cell
+pub fn hex_life_kernel() -> LayeredKernel {
+    LayeredKernel::builder()
+        .mask(hex_ring(1, 1, 3))
+        .with_decider(Decider::Totalistic(Totalistic {
+            birth: std::iter::once(2..=2).collect(),
+            survival: std::iter::once(3..=4).collect(),
+        }))
+        .build()
+        .expect("Hex Life kernel is valid")
+}
+
+/// Fredkin and Toffoli's billiard ball model: a lone particle crosses its block diagonally,
+/// and two particles meeting head on leave along the other diagonal. Reversible.
+pub fn billiard_ball_kernel() -> MargolusKernel {
+    MargolusKernel::from_fn(|cells| match cells {
+        0b0001 => 0b1000,
+        0b1000 => 0b0001,
+        0b0010 => 0b0100,
+        0b0100 => 0b0010,
+        0b1001 => 0b0110,
+        0b0110 => 0b1001,
+        other => other,
+    })
+}
+
+/// Falling sand: grains drop into an empty cell below them, and a grain resting on another
+/// topples into an empty cell diagonally below. Grains fall off the bottom of the grid.
+pub fn sand_kernel() -> MargolusKernel {
+    MargolusKernel::from_fn(|cells| {
+        let [mut tl, mut tr, mut bl, mut br] = [0, 1, 2, 3].map(|i| cells & (1 << i) != 0);
+        if tl && !bl {
+            (tl, bl) = (false, true);
+        }
+        if tr && !br {
+            (tr, br) = (false, true);
+        }
+        if tl && !br {
+            (tl, br) = (false, true);
+        }
+        if tr && !bl {
+            (tr, bl) = (false, true);
+        }
+        [tl, tr, bl, br]
+            .into_iter()
+            .enumerate()
+            .fold(0, |acc, (i, cell)| acc | u8::from(cell) << i)
+    })
+}
+
+pub(crate) fn draw_ring(arr: &mut Array2D<bool>, inner_sq: i32, outer_sq: i32) {
+    let w = (arr.width() / 2) as i32;
+    for x in -w..=w {
+        for y in -w..=w {
+            let r2 = x.pow(2) + y.pow(2);
+            if r2 >= inner_sq && r2 < outer_sq {
+                let i = (x + w) as usize;
+                let j = (y + w) as usize;
+                arr[(i, j)] = true;
+            }
+        }
+    }
+}
+
+fn count_true(arr: &Array2D<bool>) -> usize {
+    arr.data().iter().filter(|x| **x).count()
+}
+
+fn total_weight(arr: &Array2D<u8>) -> usize {
+    arr.data().iter().map(|&w| usize::from(w)).sum()
+}
+
+/// Memoizes the results of the wrapped kernel.
+///
+/// The maps live behind a single mutex so that the cache can be shared between threads. The lock
+/// is held only for lookups and insertions, never while the wrapped kernel runs, so contention is
+/// limited to the (cheap) hashing of blocks.
+///
+/// Every table grows with each novel input, and nothing is evicted one entry at a time. The
+/// limits in its `KernelCacheConfig` only trigger collections. A collection first compacts the
+/// value store, which merges duplicate blocks and so shrinks `values` but never `solutions`
+/// or `cache`. If the cache is still above the config's `keep_fraction` of any limit
+/// afterwards, every table is emptied at once. So a small `solution_capacity` clears the
+/// cache often. Large `max_cache` and `max_values` limits let it grow as far as those two maps
+/// do before then. With `new` there are no limits, and the cache grows without bound.
+pub struct KernelCache {
+    state: Mutex<CacheState>,
+    wrap: Box<dyn Kernel>,
+    config: KernelCacheConfig,
+}
+
+/// Limits and lookup settings for a `KernelCache`; see `KernelCache::with_config`. The default
+/// has no limits and exact lookups, as `KernelCache::new`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KernelCacheConfig {
+    /// Memoized single steps above which the cache collects
+    pub solution_capacity: NonZeroUsize,
+    /// Distinct input blocks above which the cache collects
+    pub max_cache: usize,
+    /// Blocks in the value store above which the cache collects
+    pub max_values: usize,
+    /// Estimated bytes (see `KernelCache::memory_bytes`) above which the cache collects
+    pub capacity_bytes: Option<usize>,
+    /// Fraction of each limit a compacted cache must come under to be kept rather than emptied,
+    /// so that it has room to grow before collecting again
+    pub keep_fraction: f64,
+    /// Side of the cell groups input blocks are reduced by before lookup; see `with_downsample`
+    pub downsample: usize,
+}
+
+impl Default for KernelCacheConfig {
+    fn default() -> Self {
+        Self {
+            solution_capacity: NonZeroUsize::MAX,
+            max_cache: usize::MAX,
+            max_values: usize::MAX,
+            capacity_bytes: None,
+            keep_fraction: 0.75,
+            downsample: 1,
+        }
+    }
+}
+
+impl KernelCacheConfig {
+    /// Whether the state is above `fraction` of any limit
+    fn exceeded(&self, state: &CacheState, fraction: f64) -> bool {
+        let over = |len: usize, limit: usize| len as f64 > limit as f64 * fraction;
+        over(state.solutions.len(), self.solution_capacity.get())
+            || over(state.cache.len(), self.max_cache)
+            || over(state.values.len(), self.max_values)
+            || self
+                .capacity_bytes
+                .is_some_and(|capacity| over(state.memory_bytes(), capacity))
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    cache: HashMap<Block, usize>,
+    solutions: HashMap<[usize; 4], usize>,
+    /// Composite solutions advanced by 2^k steps, keyed by the input blocks and k
+    jumps: HashMap<([usize; 4], usize), usize>,
+    values: Vec<Block>,
+    /// Solutions for uniform dead and live inputs (see `uniform_input`), which skip hashing
+    uniform: [Option<usize>; 2],
+    /// Incremented whenever the cache is emptied, invalidating any indices held outside the lock
+    epoch: u64,
+    /// Size of every stored block as held, counting interned keys twice (as a value and as a
+    /// key). Kept as a running total, since it's checked on every insert.
+    block_bytes: usize,
+    /// Single-step lookups answered from the cache, and those which ran the wrapped kernel
+    hits: u64,
+    misses: u64,
+}
+
+/// A snapshot of a `KernelCache`'s effectiveness and size, from `KernelCache::stats`. Hits and
+/// misses count `step_block` lookups since the cache was created, and survive clearing it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Memoized single steps
+    pub solutions_len: usize,
+    /// Memoized `step_pow2` jumps
+    pub jumps_len: usize,
+    /// Blocks in the value store
+    pub values_len: usize,
+    /// Distinct input blocks, keyed to their index in the value store
+    pub cache_len: usize,
+    /// Estimated heap usage, as `KernelCache::memory_bytes`
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups which were hits, or None before the first lookup
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+}
+
+impl CacheState {
+    /// Estimated heap usage. Blocks are charged for their packed words, so downsampled keys
+    /// cost less than full blocks, and map entries are counted at their payload size.
+    fn memory_bytes(&self) -> usize {
+        let index = std::mem::size_of::<usize>();
+        self.block_bytes
+            + self.cache.len() * index
+            + self.solutions.len() * std::mem::size_of::<([usize; 4], usize)>()
+            + self.jumps.len() * std::mem::size_of::<(([usize; 4], usize), usize)>()
+    }
+
+    fn clear(&mut self) {
+        *self = Self {
+            epoch: self.epoch + 1,
+            hits: self.hits,
+            misses: self.misses,
+            ..Default::default()
+        };
+    }
+
+    /// Renumber the value store so that each distinct block has one index, with indices
+    /// contiguous from 0 and in order of first insertion. Solutions are stored without interning,
+    /// so the same block may otherwise be held at several indices. Bumps the epoch, since any
+    /// indices held outside the lock no longer mean the same blocks.
+    fn compact(&mut self) {
+        let mut remap = vec![0; self.values.len()];
+        let mut values = Vec::new();
+        let mut seen: HashMap<&Block, usize> = HashMap::default();
+        for (old, block) in self.values.iter().enumerate() {
+            remap[old] = *seen.entry(block).or_insert_with(|| {
+                values.push(block.clone());
+                values.len() - 1
+            });
+        }
+
+        for idx in self.cache.values_mut() {
+            *idx = remap[*idx];
+        }
+        self.solutions = std::mem::take(&mut self.solutions)
+            .into_iter()
+            .map(|(key, soln)| (key.map(|idx| remap[idx]), remap[soln]))
+            .collect();
+        self.jumps = std::mem::take(&mut self.jumps)
+            .into_iter()
+            .map(|((key, k), soln)| ((key.map(|idx| remap[idx]), k), remap[soln]))
+            .collect();
+        self.uniform = self.uniform.map(|idx| idx.map(|idx| remap[idx]));
+        self.values = values;
+        self.block_bytes = self
+            .values
+            .iter()
+            .chain(self.cache.keys())
+            .map(stored_size)
+            .sum();
+        self.epoch += 1;
+        if cfg!(debug_assertions) {
+            self.check_indices();
+        }
+    }
+
+    /// Panics unless every index held by the maps points into the value store
+    fn check_indices(&self) {
+        let len = self.values.len();
+        let solutions = self
+            .solutions
+            .iter()
+            .flat_map(|(key, &soln)| key.iter().copied().chain([soln]));
+        let jumps = self
+            .jumps
+            .iter()
+            .flat_map(|((key, _), &soln)| key.iter().copied().chain([soln]));
+        let mut indices = self
+            .cache
+            .values()
+            .copied()
+            .chain(solutions)
+            .chain(jumps)
+            .chain(self.uniform.iter().flatten().copied());
+        if let Some(idx) = indices.find(|&idx| idx >= len) {
+            panic!("Kernel cache index {idx} past the {len} stored blocks");
+        }
+    }
+
+    /// Returns the index of this block in the value store, inserting it if it is novel
+    fn intern(&mut self, block: Block) -> usize {
+        *self.cache.entry(block.clone()).or_insert_with(|| {
+            self.block_bytes += 2 * stored_size(&block);
+            let idx = self.values.len();
+            self.values.push(block);
+            idx
+        })
+    }
+
+    /// Store a block without interning it, returning its index
+    fn push_value(&mut self, block: Block) -> usize {
+        self.block_bytes += stored_size(&block);
+        self.values.push(block);
+        self.values.len() - 1
+    }
+}
+
+/// Bytes a block occupies in the cache: its handle plus its packed words
+fn stored_size(block: &Block) -> usize {
+    std::mem::size_of::<Block>() + std::mem::size_of_val(block.words())
+}
+
+impl KernelCache {
+    pub fn new(wrap: Box<dyn Kernel>) -> Self {
+        Self::with_config(wrap, KernelCacheConfig::default())
+    }
+
+    /// Panics if `config.downsample` is zero or `config.keep_fraction` is outside 0..=1
+    pub fn with_config(wrap: Box<dyn Kernel>, config: KernelCacheConfig) -> Self {
+        assert!(config.downsample > 0, "Downsample step must be positive");
+        assert!(
+            (0.0..=1.0).contains(&config.keep_fraction),
+            "Keep fraction {} must be within 0..=1",
+            config.keep_fraction
+        );
+        Self {
+            state: Default::default(),
+            wrap,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &KernelCacheConfig {
+        &self.config
+    }
+
+    /// Look up inputs by their `summarize`d form at the given step, so that blocks which differ
+    /// in a few cells share a result. Results found this way are `KernelResult::Approximate`,
+    /// since they were computed from whichever input was seen first. A step of 1 is exact.
+    pub fn with_downsample(mut self, step: usize) -> Self {
+        assert!(step > 0, "Downsample step must be positive");
+        self.config.downsample = step;
+        self
+    }
+
+    /// A cache which compacts itself whenever its estimated size exceeds `capacity_bytes`, and
+    /// empties itself if that doesn't free a quarter of the capacity
+    pub fn with_capacity(wrap: Box<dyn Kernel>, capacity_bytes: usize) -> Self {
+        let config = KernelCacheConfig {
+            capacity_bytes: Some(capacity_bytes),
+            ..Default::default()
+        };
+        Self::with_config(wrap, config)
+    }
+
+    /// Estimated memory held by the cache, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.state().memory_bytes()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            solutions_len: state.solutions.len(),
+            jumps_len: state.jumps.len(),
+            values_len: state.values.len(),
+            cache_len: state.cache.len(),
+            bytes: state.memory_bytes(),
+        }
+    }
+
+    /// Forget all memoized results, e.g. after the wrapped kernel's parameters changed
+    pub fn clear(&self) {
+        self.state().clear();
+    }
+
+    /// Merge duplicate blocks in the value store, so that its size reflects the working set.
+    /// Memoized results are kept.
+    pub fn compact(&self) {
+        self.state().compact();
+    }
+
+    /// Index of the key under which `block` is cached
+    fn key(&self, state: &mut CacheState, block: Block) -> usize {
+        match self.config.downsample {
+            1 => state.intern(block),
+            step => state.intern(summarize(&block, step)),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().expect("Kernel cache lock poisoned")
+    }
+
+    /// Lock the state for an insertion of results computed from indices taken at `epoch`.
+    /// Returns None (after which the result should just be returned uncached) if the cache has
+    /// since been emptied.
+    fn state_for_insert(&self, epoch: u64) -> Option<MutexGuard<'_, CacheState>> {
+        let mut state = self.state();
+        if self.config.exceeded(&state, 1.0) {
+            // Only keep the compacted state if it leaves room to grow, or every insert from
+            // here on would compact again
+            state.compact();
+            if self.config.exceeded(&state, self.config.keep_fraction) {
+                log::info!("Kernel cache exceeded its limits, clearing");
+                state.clear();
+            }
+        }
+        (state.epoch == epoch).then_some(state)
+    }
+
+    /// Advance the given blocks by 2^k steps, composing cached single steps.
+    ///
+    /// Four blocks only determine a single step exactly; after that the neighborhood of the
+    /// intermediate result is unknown. Each composed step therefore approximates the surroundings
+    /// of the intermediate block by tiling it with itself, which is exact for regions that are
+    /// periodic at the block width (empty space, uniform fills) and a guess everywhere else.
+    /// For k = 0 this is identical to `step_block`; for k > 0 the result is `KernelResult::Approximate`.
+    ///
+    /// On `basic_mnca`, a two-step jump is exact over empty space, ~1% wrong on sparse (1%) soups
+    /// and ~35% wrong on dense (50%) soups (see `measure_step_pow2_basic_mnca`).
+    pub fn step_pow2(&self, blocks: [Block; 4], k: usize) -> (Block, KernelResult) {
+        if k == 0 {
+            return self.step_block(blocks);
+        }
+
+        let (hashes, epoch) = {
+            let mut state = self.state();
+            let hashes = blocks.clone().map(|block| self.key(&mut state, block));
+            if let Some(&soln) = state.jumps.get(&(hashes, k)) {
+                return (state.values[soln].clone(), KernelResult::Approximate);
+            }
+            (hashes, state.epoch)
+        };
+
+        let (half, _) = self.step_pow2(blocks, k - 1);
+        let tiled = [half.clone(), half.clone(), half.clone(), half];
+        let (soln, _) = self.step_pow2(tiled, k - 1);
+
+        if let Some(mut state) = self.state_for_insert(epoch) {
+            let idx = state.intern(soln.clone());
+            state.jumps.insert((hashes, k), idx);
+        }
+
+        (soln, KernelResult::Approximate)
+    }
+}
+
+/// Reduce each `step` x `step` group of cells to one, live if at least half the group is. Groups
+/// along the right and bottom edges are smaller if `step` doesn't divide the block.
+fn summarize(block: &Block, step: usize) -> Block {
+    let (w, h) = (block.width(), block.height());
+    Block::from_fn(w.div_ceil(step), h.div_ceil(step), |i, j| {
+        let (xs, ys) = (
+            i * step..((i + 1) * step).min(w),
+            j * step..((j + 1) * step).min(h),
+        );
+        let group = xs.len() * ys.len();
+        let live = ys
+            .flat_map(|y| xs.clone().map(move |x| (x, y)))
+            .filter(|&pos| block[pos])
+            .count();
+        2 * live >= group
+    })
+}
+
+impl Kernel for KernelCache {
+    fn order(&self) -> usize {
+        self.wrap.order()
+    }
+
+    fn zero_stable(&self) -> bool {
+        self.wrap.zero_stable()
+    }
+
+    fn prewarm(&self, samples: &[[Block; 4]]) {
+        for sample in samples {
+            self.step_block(sample.clone());
+        }
+    }
+
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
+        let uniform = uniform_input(&blocks);
+        let (hashes, epoch) = {
+            let mut state = self.state();
+            if let Some(idx) = uniform.and_then(|live| state.uniform[usize::from(live)]) {
+                state.hits += 1;
+                return (state.values[idx].clone(), KernelResult::NewBlock);
+            }
+            let hashes = blocks.clone().map(|block| self.key(&mut state, block));
+            // A downsampled match is only exact for the input it was computed from, so uniform
+            // inputs are stepped instead, to give the fast path above an exact result
+            let exact = self.config.downsample == 1;
+            let matched = state.solutions.get(&hashes).copied();
+            if let Some(soln) = matched.filter(|_| exact || uniform.is_none()) {
+                state.hits += 1;
+                if !exact {
+                    return (state.values[soln].clone(), KernelResult::Approximate);
+                }
+                if let Some(live) = uniform {
+                    state.uniform[usize::from(live)] = Some(soln);
+                }
+                return (state.values[soln].clone(), KernelResult::NewBlock);
+            }
+            state.misses += 1;
+            (hashes, state.epoch)
+        };
+
+        // Computed without holding the lock; a concurrent miss on the same input just
+        // duplicates the work
+        let (soln, _) = self.wrap.step_block(blocks);
+
+        if let Some(mut state) = self.state_for_insert(epoch) {
+            let idx = state.push_value(soln.clone());
+            state.solutions.insert(hashes, idx);
+            if let Some(live) = uniform {
+                state.uniform[usize::from(live)] = Some(idx);
+            }
+            if state.solutions.len().is_multiple_of(1000) {
+                log::debug!("Kernel cache holds {} solutions", state.solutions.len());
+            }
+        }
+
+        (soln, KernelResult::NewBlock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{Dense, Simulation};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_hex_ring() {
+        let neighbors = hex_ring(1, 1, 3);
+        assert_eq!(neighbors.to_string(), "-##\n#-#\n##-");
+        // 1 + 6 + 12 cells within distance 2
+        assert_eq!(count_true(&hex_ring(0, 2, 5)), 19);
+    }
+
+    /// Hex Life stepped directly on a torus, as a reference
+    fn hex_life_step(grid: &Array2D<bool>) -> Array2D<bool> {
+        let (w, h) = (grid.width() as i32, grid.height() as i32);
+        let offsets = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, -1), (-1, 1)];
+        let mut out = Array2D::new(grid.width(), grid.height());
+        for y in 0..h {
+            for x in 0..w {
+                let count = offsets
+                    .iter()
+                    .filter(|(dx, dy)| {
+                        grid[(
+                            (x + dx).rem_euclid(w) as usize,
+                            (y + dy).rem_euclid(h) as usize,
+                        )]
+                    })
+                    .count();
+                let live = grid[(x as usize, y as usize)];
+                out[(x as usize, y as usize)] = if live {
+                    matches!(count, 3 | 4)
+                } else {
+                    count == 2
+                };
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_hex_life_matches_reference() {
+        let mut rng = StdRng::seed_from_u64(406);
+        let mut sim = Dense::new(Box::new(hex_life_kernel()), 8, 8);
+        sim.set_wrap(true);
+        sim.for_each_pixel_mut(|_, cell| *cell = rng.gen_bool(0.3));
+
+        let mut expected = sim.snapshot();
+        for _ in 0..8 {
+            sim.step();
+            expected = hex_life_step(&expected);
+            assert_eq!(sim.snapshot(), expected);
+        }
+    }
+
+    #[test]
+    fn test_hex_life_oscillators() {
+        // A ring of six cells around an empty center has period 3, and a pair of neighbors
+        // flips to the pair of cells they both touch and back, with period 2
+        let ring = [(1, 0), (2, 0), (0, 1), (2, 1), (0, 2), (1, 2)];
+        let pair = [(0, 0), (1, 0)];
+        for (cells, period) in [(&ring[..], 3), (&pair[..], 2)] {
+            let mut sim = Dense::new(Box::new(hex_life_kernel()), 8, 8);
+            for &(x, y) in cells {
+                sim.set_pixel((x + 6, y + 6), true);
+            }
+            let start = sim.snapshot();
+            for generation in 1..=period {
+                sim.step();
+                let same = sim.snapshot() == start;
+                assert_eq!(same, generation == period, "generation {generation}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_expr_life_matches_life() {
+        let layers = life_layered_kernel().layers().to_vec();
+        for source in [
+            "center ? n0 in 0.25..0.375 : n0 == 0.375",
+            "n0 * 8 == 3 || center and n0 * 8 == 2",
+        ] {
+            let kernel = LayeredKernel::with_expr(source, layers.clone()).unwrap();
+            let mut rng = StdRng::seed_from_u64(420);
+            let mut expected = Dense::new(Box::new(Life), 16, 16);
+            expected.for_each_pixel_mut(|_, cell| *cell = rng.gen_bool(0.4));
+            let start = expected.snapshot();
+            let mut sim = Dense::new(Box::new(kernel), 16, 16);
+            sim.for_each_pixel_mut(|pos, cell| *cell = start[pos]);
+            for generation in 0..16 {
+                sim.step();
+                expected.step();
+                assert_eq!(sim.snapshot(), expected.snapshot(), "{source} {generation}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_closure_decider_captures_rule() {
+        // Birth and survival counts as if read from a config, rather than hard coded
+        let (birth, survive): (Vec<u16>, Vec<u16>) = (vec![3], vec![2, 3]);
+        let layers = life_layered_kernel().layers().to_vec();
+        let kernel = LayeredKernel::new(
+            move |center, counts| match center {
+                true => survive.contains(&counts[0]),
+                false => birth.contains(&counts[0]),
+            },
+            layers,
+        );
+
+        let mut rng = StdRng::seed_from_u64(508);
+        for _ in 0..32 {
+            let blocks = [(); 4].map(|_| random_block(&mut rng, 2, 0.4));
+            assert_eq!(
+                kernel.step_block(blocks.clone()).0,
+                Life.step_block(blocks).0
+            );
+        }
+    }
+
+    #[test]
+    fn test_diagonal_weights_change_counts() {
+        // The Moore neighborhood, with diagonals weighing twice as much as edges
+        let weights = Array2D::from_array(3, vec![2, 1, 2, 1, 0, 1, 2, 1, 2]);
+        let weighted = LayeredKernel::with_weights(
+            Decider::Fn(Box::new(|_, counts| counts[0] == 8)),
+            vec![weights.clone()],
+        )
+        .unwrap();
+        let boolean = LayeredKernel::new(|_, counts| counts[0] == 8, weighted.layers().to_vec());
+        assert_eq!(weighted.layers(), life_layered_kernel().layers());
+        assert!(weighted.weights(0).is_some() && boolean.weights(0).is_none());
+
+        // The cell at (0, 0) of the output has exactly its four diagonal neighbors live
+        let corner = Block::from_rows(&["#-", "--"]);
+        let blocks = [(); 4].map(|_| corner.clone());
+        assert!(weighted.step_block(blocks.clone()).0[(0, 0)]);
+        assert!(!boolean.step_block(blocks).0[(0, 0)]);
+
+        let grid = Array2D::from_rows(&["#-#", "---", "#-#"]);
+        assert_eq!(weighted.count_field(&grid, 0)[(1, 1)], 8.0 / 12.0);
+        assert_eq!(boolean.count_field(&grid, 0)[(1, 1)], 4.0 / 8.0);
+
+        // Unit weights are just a boolean mask
+        let unit = Array2D::from_fn(3, 3, |x, y| u8::from((x, y) != (1, 1)));
+        let kernel = LayeredKernel::with_weights(Decider::Fn(Box::new(|c, _| c)), vec![unit]);
+        assert!(kernel.unwrap().weights(0).is_none());
+
+        let heavy = Array2D::from_array(17, vec![255; 17 * 17]);
+        assert!(matches!(
+            LayeredKernel::with_weights(Decider::Fn(Box::new(|c, _| c)), vec![heavy]),
+            Err(Error::WeightOverflow {
+                layer: 0,
+                total: 73695
+            })
+        ));
+    }
+
+    #[test]
+    fn test_expr_layer_out_of_range() {
+        let layers = life_layered_kernel().layers().to_vec();
+        assert!(matches!(
+            LayeredKernel::with_expr("n1 > 0", layers.clone()),
+            Err(Error::RuleExpr { column: 1, .. })
+        ));
+        assert!(matches!(
+            LayeredKernel::with_expr("n0 >", layers),
+            Err(Error::RuleExpr { column: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_billiard_ball_is_reversible() {
+        let kernel = billiard_ball_kernel();
+        let mut images: Vec<u8> = (0..16).map(|cells| kernel.apply(cells)).collect();
+        images.sort();
+        assert_eq!(images, (0..16).collect::<Vec<u8>>());
+        for cells in 0..16 {
+            assert_eq!(kernel.apply(kernel.apply(cells)), cells);
+        }
+    }
+
+    #[test]
+    fn test_billiard_ball_travels_diagonally() {
+        let mut sim = Dense::new(Box::new(billiard_ball_kernel()), 16, 16);
+        sim.set_pixel((15, 15), true);
+        for generation in 1..=6 {
+            sim.step();
+            let cells: Vec<_> = (0..32 * 32)
+                .map(|i| (i % 32, i / 32))
+                .filter(|&pos| sim.get_pixel(pos))
+                .collect();
+            // One cell per step in every partition, so along a straight diagonal
+            assert_eq!(cells.len(), 1);
+            let (x, y) = cells[0];
+            let (dx, dy) = (x as i32 - 15, y as i32 - 15);
+            assert_eq!((dx.abs(), dy.abs()), (generation, generation), "{cells:?}");
+        }
+    }
+
+    #[test]
+    fn test_sand() {
+        let mut sim = Dense::new(Box::new(sand_kernel()), 8, 8);
+        // A lone grain falls a cell per step once it is in the top row of a block, which it
+        // stays in as the partitions alternate
+        sim.set_pixel((5, 1), true);
+        for y in 2..9 {
+            sim.step();
+            assert!(sim.get_pixel((5, y)), "y {y}\n{}", sim.snapshot());
+            assert_eq!(sim.population(), 1);
+        }
+
+        let kernel = sand_kernel();
+        // Both top cells fall
+        assert_eq!(kernel.apply(0b0011), 0b1100);
+        // A grain resting on another topples, unless the cell it would land in is full
+        assert_eq!(kernel.apply(0b0101), 0b1100);
+        assert_eq!(kernel.apply(0b1010), 0b1100);
+        assert_eq!(kernel.apply(0b1101), 0b1101);
+        for cells in 0..16u8 {
+            assert_eq!(kernel.apply(cells).count_ones(), cells.count_ones());
+        }
+    }
+
+    #[test]
+    fn test_zero_stable() {
+        let kernels: Vec<Box<dyn Kernel>> = vec![
+            Box::new(Life),
+            Box::new(life_layered_kernel()),
+            Box::new(larger_than_life_layered_kernel()),
+            Box::new(basic_mnca()),
+            Box::new(tunable_mnca()),
+            Box::new(shift_right_kernel()),
+            Box::new(KernelCache::new(Box::new(Life))),
+        ];
+        for kernel in kernels {
+            assert!(kernel.zero_stable());
+            let w = calc_block_width(&*kernel);
+            let zeros = std::array::from_fn(|_| Block::new(w, w));
+            assert!(kernel.step_block(zeros).0.is_empty());
+        }
+
+        let void = LayeredKernel::new(|_, _| true, vec![Array2D::from_array(3, vec![true; 9])]);
+        assert!(!void.zero_stable());
+    }
+
+    #[test]
+    fn test_block_order() {
+        for (width, order) in [(3, 1), (5, 2), (9, 3), (17, 4), (33, 5)] {
+            assert_eq!(
+                calculate_block_order_from_kernel_width(width).unwrap(),
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_order_invalid() {
+        for width in [0, 1, 4, 7, 8, usize::MAX] {
+            assert!(matches!(
+                calculate_block_order_from_kernel_width(width),
+                Err(Error::InvalidKernelWidth(w)) if w == width
+            ));
+        }
+    }
+
+    #[test]
+    fn test_count_histogram() {
+        let mut kernel = life_layered_kernel();
+        let histogram = kernel.count_histogram();
+        let full = Block::from_array(2, vec![true; 4]);
+        let blocks = [full.clone(), full.clone(), full.clone(), full];
+
+        // Disabled by default
+        kernel.step_block(blocks.clone());
+        assert!(histogram.bins()[0].iter().all(|&n| n == 0));
+
+        // Every cell of a full block sees all 8 neighbors
+        histogram.set_enabled(true);
+        kernel.step_block(blocks);
+        let bins = histogram.bins();
+        assert_eq!(bins[0][CountHistogram::BINS - 1], 4);
+        assert_eq!(bins[0].iter().sum::<u64>(), 4);
+
+        histogram.clear();
+        assert!(histogram.bins()[0].iter().all(|&n| n == 0));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let original = basic_mnca().fingerprint().unwrap();
+        let rebuilt = LayeredKernel::with_bands(basic_mnca_bands(), basic_mnca_layers()).unwrap();
+        assert_eq!(rebuilt.fingerprint(), Some(original));
+
+        let mut bands = basic_mnca_bands();
+        bands.bands[2].hi += 0.01;
+        let tweaked = LayeredKernel::with_bands(bands, basic_mnca_layers()).unwrap();
+        assert_ne!(tweaked.fingerprint(), Some(original));
+
+        let mut layers = basic_mnca_layers();
+        layers.swap(0, 1);
+        let swapped = LayeredKernel::with_bands(basic_mnca_bands(), layers).unwrap();
+        assert_ne!(swapped.fingerprint(), Some(original));
+
+        assert_eq!(life_layered_kernel().fingerprint(), None);
+    }
+
+    #[test]
+    fn test_layer_shape_mismatch() {
+        let layers = vec![Array2D::new(3, 3), Array2D::new(5, 5)];
+        assert!(matches!(
+            LayeredKernel::try_new(|center, _| center, layers),
+            Err(Error::ShapeMismatch {
+                expected: (3, 3),
+                found: (5, 5)
+            })
+        ));
+        assert!(matches!(
+            LayeredKernel::try_new(|center, _| center, vec![]),
+            Err(Error::NoLayers)
+        ));
+        assert!(matches!(
+            LayeredKernel::try_new(|center, _| center, vec![Array2D::new(7, 7)]),
+            Err(Error::InvalidKernelWidth(7))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Kernel has no layers")]
+    fn test_new_panics_without_layers() {
+        LayeredKernel::new(|center, _| center, vec![]);
+    }
+
+    fn random_block(rng: &mut impl Rng, width: usize, density: f64) -> Block {
+        let data = (0..width * width).map(|_| rng.gen_bool(density)).collect();
+        Block::from_array(width, data)
+    }
+
+    /// Exact two-step result for the center of a 3x3 arrangement of blocks (row-major)
+    fn exact_two_steps(kernel: &dyn Kernel, grid: &[Block; 9]) -> Block {
+        let quad = |x: usize, y: usize| {
+            [
+                grid[x + 3 * y].clone(),
+                grid[x + 1 + 3 * y].clone(),
+                grid[x + 3 * (y + 1)].clone(),
+                grid[x + 1 + 3 * (y + 1)].clone(),
+            ]
+        };
+        let first = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, y)| kernel.step_block(quad(x, y)).0);
+        kernel.step_block(first).0
+    }
+
+    #[test]
+    fn test_step_pow2_zero_order_matches_exec() {
+        let mut rng = StdRng::seed_from_u64(339);
+        let cache = KernelCache::new(Box::new(Life));
+        let bare = Life;
+        for _ in 0..100 {
+            let blocks = [(); 4].map(|_| random_block(&mut rng, 2, 0.5));
+            let (cached, _) = cache.step_pow2(blocks.clone(), 0);
+            assert_eq!(cached, bare.step_block(blocks).0);
+        }
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let mut rng = StdRng::seed_from_u64(369);
+        let capacity = 4096;
+        let cache = KernelCache::with_capacity(Box::new(Life), capacity);
+        let bare = Life;
+
+        let mut peak = 0;
+        for _ in 0..500 {
+            let blocks = [(); 4].map(|_| random_block(&mut rng, 2, 0.5));
+            let (cached, _) = cache.step_block(blocks.clone());
+            assert_eq!(cached, bare.step_block(blocks).0);
+            peak = peak.max(cache.memory_bytes());
+        }
+
+        assert!(peak > capacity / 2);
+        // One step_block call adds at most five blocks and a solution beyond the check
+        assert!(peak < capacity + 512, "peak {peak}");
+
+        let uncapped = KernelCache::new(Box::new(Life));
+        assert_eq!(uncapped.memory_bytes(), 0);
+        uncapped.step_block([(); 4].map(|_| random_block(&mut rng, 2, 0.5)));
+        assert!(uncapped.memory_bytes() > 0);
+    }
+
+    #[test]
+    fn test_cache_stats() {
+        let mut rng = StdRng::seed_from_u64(516);
+        let cache = KernelCache::new(Box::new(Life));
+        assert_eq!(cache.stats(), CacheStats::default());
+        assert_eq!(cache.stats().hit_rate(), None);
+
+        let blocks = [(); 4].map(|_| random_block(&mut rng, 2, 0.5));
+        cache.step_block(blocks.clone());
+        for _ in 0..3 {
+            cache.step_block(blocks.clone());
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+        assert_eq!(stats.hit_rate(), Some(0.75));
+        assert_eq!(stats.solutions_len, 1);
+        assert_eq!(stats.values_len, stats.cache_len + 1);
+        assert_eq!(stats.bytes, cache.memory_bytes());
+
+        // Clearing empties the tables but keeps counting
+        cache.clear();
+        cache.step_block(blocks);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.solutions_len), (3, 2, 1));
+    }
+
+    /// Collections under constant pressure, interleaving single steps with jumps, must never
+    /// leave an index to a block that isn't stored
+    #[test]
+    fn test_collection_keeps_indices_valid() {
+        let mut rng = StdRng::seed_from_u64(518);
+        let cache = KernelCache::with_capacity(Box::new(Life), 2048);
+        let mut epochs = 0;
+        for i in 0..2000 {
+            let blocks = [(); 4].map(|_| random_block(&mut rng, 2, 0.4));
+            if i % 3 == 0 {
+                cache.step_pow2(blocks.clone(), 1 + i % 2);
+            }
+            let (cached, _) = cache.step_block(blocks.clone());
+            assert_eq!(cached, Life.step_block(blocks).0, "iteration {i}");
+
+            let state = cache.state();
+            state.check_indices();
+            epochs = state.epoch;
+        }
+        assert!(epochs > 10, "only {epochs} collections");
+    }
+
+    #[test]
+    fn test_config_limits_solutions() {
+        let mut rng = StdRng::seed_from_u64(517);
+        let inputs: Vec<[Block; 4]> = (0..100)
+            .map(|_| [(); 4].map(|_| random_block(&mut rng, 2, 0.5)))
+            .collect();
+
+        let unbounded = KernelCache::new(Box::new(Life));
+        let config = KernelCacheConfig {
+            solution_capacity: NonZeroUsize::new(8).unwrap(),
+            ..Default::default()
+        };
+        let limited = KernelCache::with_config(Box::new(Life), config);
+        for blocks in &inputs {
+            let (cached, _) = limited.step_block(blocks.clone());
+            assert_eq!(cached, unbounded.step_block(blocks.clone()).0);
+            // Limits are checked before inserting, so one insert may pass them
+            assert!(limited.stats().solutions_len <= 9);
+        }
+
+        // Without limits nothing is ever collected
+        assert_eq!(unbounded.state().epoch, 0);
+        assert!(unbounded.stats().solutions_len > 8);
+        assert!(limited.state().epoch > 5);
+    }
+
+    #[test]
+    fn test_memory_bytes_counts_packed_words() {
+        // One step of four distinct 2x2 blocks stores them as keys and values, plus a solution
+        let cache = KernelCache::new(Box::new(Life));
+        let blocks = ["--", "#-", "-#", "##"].map(|row| Block::from_rows(&[row, "--"]));
+        cache.step_block(blocks.clone());
+        let block = std::mem::size_of::<Block>() + 8;
+        let index = std::mem::size_of::<usize>();
+        let solution = std::mem::size_of::<([usize; 4], usize)>();
+        assert_eq!(cache.memory_bytes(), 9 * block + 4 * index + solution);
+
+        // Downsampled keys are smaller than the blocks they summarize
+        let full = KernelCache::new(Box::new(basic_mnca()));
+        let reduced = KernelCache::new(Box::new(basic_mnca())).with_downsample(4);
+        let mut rng = StdRng::seed_from_u64(0xb17e5);
+        let w = calc_block_width(&basic_mnca());
+        let blocks = [(); 4].map(|_| random_block(&mut rng, w, 0.5));
+        full.step_block(blocks.clone());
+        reduced.step_block(blocks);
+        assert!(reduced.memory_bytes() < full.memory_bytes());
+    }
+
+    /// The cache must be transparent to the kernel it wraps, including across evictions
+    #[test]
+    fn test_cache_matches_bare_kernel() {
+        let mut rng = StdRng::seed_from_u64(0xcac4e);
+        let bare = basic_mnca();
+        let w = calc_block_width(&bare);
+        let cache = KernelCache::with_capacity(Box::new(basic_mnca()), 4 * 1024);
+
+        // Draw from a small pool, so that lookups hit as well as miss
+        let pool: Vec<Block> = [0.0, 0.05, 0.2, 0.5, 0.5, 0.8]
+            .into_iter()
+            .map(|density| random_block(&mut rng, w, density))
+            .collect();
+        for i in 0..150 {
+            let blocks = [(); 4].map(|_| pool[rng.gen_range(0..pool.len())].clone());
+            let (cached, _) = cache.step_block(blocks.clone());
+            assert_eq!(cached, bare.step_block(blocks).0, "iteration {i}");
+        }
+
+        assert!(cache.state().epoch > 0, "cache was never collected");
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut rng = StdRng::seed_from_u64(0xc0);
+        let cache = KernelCache::new(Box::new(Life));
+        let inputs: Vec<[Block; 4]> = (0..200)
+            .map(|_| [(); 4].map(|_| random_block(&mut rng, 2, 0.4)))
+            .collect();
+        let expected: Vec<Block> = inputs
+            .iter()
+            .map(|blocks| {
+                cache.step_pow2(blocks.clone(), 1);
+                cache.step_block(blocks.clone()).0
+            })
+            .collect();
+
+        // There are only 16 distinct 2x2 blocks, so solutions duplicate interned inputs
+        let before = cache.state().values.len();
+        cache.compact();
+        let state = cache.state();
+        assert!(state.values.len() < before);
+        assert!(state.values.len() <= 16);
+
+        let mut used = vec![false; state.values.len()];
+        let indices = state
+            .cache
+            .values()
+            .copied()
+            .chain(
+                state
+                    .solutions
+                    .iter()
+                    .flat_map(|(k, &v)| k.iter().copied().chain([v])),
+            )
+            .chain(
+                state
+                    .jumps
+                    .iter()
+                    .flat_map(|((k, _), &v)| k.iter().copied().chain([v])),
+            );
+        for idx in indices {
+            used[idx] = true;
+        }
+        assert!(used.iter().all(|&u| u), "indices are not contiguous");
+        for (block, &idx) in &state.cache {
+            assert_eq!(&state.values[idx], block);
+        }
+        drop(state);
+
+        for (blocks, expected) in inputs.into_iter().zip(expected) {
+            assert_eq!(cache.step_block(blocks).0, expected);
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        // Half of the top left group is live either way
+        let a = Block::from_rows(&["##--", "----", "----", "---#"]);
+        let b = Block::from_rows(&["##--", "#---", "----", "---#"]);
+        assert_ne!(a, b);
+        assert_eq!(summarize(&a, 2), summarize(&b, 2));
+        assert_eq!(summarize(&a, 2), Block::from_rows(&["#-", "--"]));
+        assert_eq!(summarize(&a, 1), a);
+        // Edge groups are smaller when the step doesn't divide the block
+        assert_eq!(summarize(&a, 3), Block::from_rows(&["--", "-#"]));
+    }
+
+    #[test]
+    fn test_downsampled_hits_are_approximate() {
+        let cache = KernelCache::new(Box::new(Life)).with_downsample(2);
+        let zero = Block::new(2, 2);
+        let input = |block: &Block| [block.clone(), zero.clone(), zero.clone(), zero.clone()];
+
+        // Each of these is one live cell in four, so summarizes to dead
+        let first = Block::from_rows(&["#-", "--"]);
+        let second = Block::from_rows(&["--", "-#"]);
+        let (out, result) = cache.step_block(input(&first));
+        assert!(matches!(result, KernelResult::NewBlock));
+        let (approx, result) = cache.step_block(input(&second));
+        assert!(matches!(result, KernelResult::Approximate));
+        assert_eq!(approx, out);
+
+        // Empty space shares the same summary, but is still stepped exactly
+        let (empty, result) = cache.step_block(input(&zero));
+        assert!(matches!(result, KernelResult::NewBlock));
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_prewarm() {
+        let cache = Arc::new(KernelCache::new(Box::new(Life)));
+        assert_eq!(cache.memory_bytes(), 0);
+
+        // Dense warms the cache with empty space
+        let _sim = Dense::new(Box::new(cache.clone()), 4, 4);
+        let zeros = [(); 4].map(|_| Block::new(2, 2));
+        assert_eq!(cache.state().solutions.len(), 1);
+        let hashes = {
+            let mut state = cache.state();
+            zeros.clone().map(|block| state.intern(block))
+        };
+        assert!(cache.state().solutions.contains_key(&hashes));
+
+        let mut rng = StdRng::seed_from_u64(402);
+        let samples: Vec<[Block; 4]> = (0..3)
+            .map(|_| [(); 4].map(|_| random_block(&mut rng, 2, 0.5)))
+            .collect();
+        cache.prewarm(&samples);
+        assert!(cache.state().solutions.len() > 1);
+    }
+
+    #[test]
+    fn test_step_pow2_empty_is_exact() {
+        let cache = KernelCache::new(Box::new(basic_mnca()));
+        let zero = Block::new(16, 16);
+        let (out, result) = cache.step_pow2([(); 4].map(|_| zero.clone()), 3);
+        assert_eq!(out, zero);
+        assert!(matches!(result, KernelResult::Approximate));
+    }
+
+    /// Measures speedup and accuracy of two-step jumps against exact stepping on `basic_mnca`.
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn measure_step_pow2_basic_mnca() {
+        let mut rng = rand::thread_rng();
+        let exact = basic_mnca();
+        let cache = KernelCache::new(Box::new(basic_mnca()));
+        let w = calc_block_width(&exact);
+
+        for density in [0.0, 0.01, 0.1, 0.5] {
+            // A small pool of distinct blocks, so that repeated neighborhoods occur as in a real run
+            let pool: Vec<Block> = (0..4).map(|_| random_block(&mut rng, w, density)).collect();
+            let grids: Vec<[Block; 9]> = (0..50)
+                .map(|_| [(); 9].map(|_| pool[rng.gen_range(0..pool.len())].clone()))
+                .collect();
+
+            let start = std::time::Instant::now();
+            let expected: Vec<Block> = grids
+                .iter()
+                .map(|grid| exact_two_steps(&exact, grid))
+                .collect();
+            let exact_time = start.elapsed();
+
+            let start = std::time::Instant::now();
+            let approx: Vec<Block> = grids
+                .iter()
+                .map(|grid| {
+                    let quad = [0, 1, 3, 4].map(|i| grid[i].clone());
+                    cache.step_pow2(quad, 1).0
+                })
+                .collect();
+            let approx_time = start.elapsed();
+
+            let total = grids.len() * w * w;
+            let wrong: usize = expected
+                .iter()
+                .zip(&approx)
+                .map(|(e, a)| e.data().zip(a.data()).filter(|(e, a)| e != a).count())
+                .sum();
+
+            println!(
+                "density {density}: exact {exact_time:?}, jump {approx_time:?}, {:.2}% cells wrong",
+                100. * wrong as f64 / total as f64
+            );
+        }
+    }
+
+    #[test]
+    fn test_basic_mnca_bands_match_original_decider() {
+        fn original(mut center: bool, counts: &[u16]) -> bool {
+            let avg = [counts[0] as f32 / 108.0, counts[1] as f32 / 36.0];
+
+            if avg[0] >= 0.210 && avg[0] <= 0.220 {
+                center = true;
+            }
+            if avg[0] >= 0.350 && avg[0] <= 0.500 {
+                center = false;
+            }
+            if avg[0] >= 0.750 && avg[0] <= 0.850 {
+                center = false;
+            }
+            if avg[1] >= 0.100 && avg[1] <= 0.280 {
+                center = false;
+            }
+            if avg[1] >= 0.430 && avg[1] <= 0.550 {
+                center = true;
+            }
+            if avg[0] >= 0.120 && avg[0] <= 0.150 {
+                center = false;
+            }
+
+            center
+        }
+
+        let bands = basic_mnca_bands();
+        for center in [false, true] {
+            for n0 in 0..=108 {
+                for n1 in 0..=36 {
+                    let counts = [n0, n1];
+                    assert_eq!(bands.decide(center, &counts), original(center, &counts));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tunable_mnca_params() {
+        let kernel = tunable_mnca();
+        let params = kernel.params().unwrap();
+        assert_eq!(params.len(), 4);
+        assert!(kernel.fingerprint().is_none());
+
+        // Defaults reproduce basic_mnca
+        let bands = basic_mnca_bands();
+        for center in [false, true] {
+            for n0 in 0..=108 {
+                for n1 in 0..=36 {
+                    let counts = [n0, n1];
+                    assert_eq!(
+                        kernel.decider.decide(center, &counts),
+                        bands.decide(center, &counts)
+                    );
+                }
+            }
+        }
+
+        // Changes are seen by the running kernel, and clamped to the range
+        let counts = [(0.3 * 108.0) as u16, 0];
+        assert!(!kernel.decider.decide(false, &counts));
+        params[1].set(0.32);
+        assert!(kernel.decider.decide(false, &counts));
+        params[1].set(2.0);
+        assert_eq!(params[1].get(), 1.0);
+    }
+
+    #[test]
+    fn test_count_field_matches_gather() {
+        // Asymmetric, so that a flipped mask would be caught
+        let mut mask = Array2D::new(5, 5);
+        for xy in [(0, 0), (1, 0), (4, 2), (2, 3), (2, 2)] {
+            mask[xy] = true;
+        }
+        let kernel = LayeredKernel::new(|c, _| c, vec![mask.clone()]);
+
+        let mut rng = StdRng::seed_from_u64(385);
+        let grid = Array2D::from_array(13, (0..13 * 9).map(|_| rng.gen_bool(0.4)).collect());
+        let field = kernel.count_field(&grid, 0);
+
+        for y in 0..9_i64 {
+            for x in 0..13_i64 {
+                let mut count = 0;
+                for my in 0..5 {
+                    for mx in 0..5 {
+                        let (gx, gy) = (x + mx - 2, y + my - 2);
+                        let live = (0..13).contains(&gx)
+                            && (0..9).contains(&gy)
+                            && grid[(gx as usize, gy as usize)];
+                        count += u8::from(mask[(mx as usize, my as usize)] && live);
+                    }
+                }
+                let got = field[(x as usize, y as usize)] * 5.0;
+                assert!((got - count as f32).abs() < 1e-4, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_kernel_widths() {
+        assert_eq!(nearest_kernel_widths(1), (None, 3));
+        assert_eq!(nearest_kernel_widths(3), (Some(3), 3));
+        assert_eq!(nearest_kernel_widths(7), (Some(5), 9));
+        assert_eq!(nearest_kernel_widths(11), (Some(9), 17));
+        assert_eq!(nearest_kernel_widths(17), (Some(17), 17));
+        assert_eq!(nearest_kernel_widths(6), (Some(5), 9));
+    }
+
+    #[test]
+    fn test_fit_masks() {
+        // A 7x7 ring reaching the edge must be padded
+        let mut ring = Array2D::new(7, 7);
+        draw_ring(&mut ring, 4, 10);
+        let fitted = fit_masks(&[ring.clone()]).unwrap();
+        assert_eq!(fitted.fit, MaskFit::Padded { from: 7, to: 9 });
+        assert_eq!(fitted.order, 3);
+        assert_eq!(count_true(&fitted.masks[0]), count_true(&ring));
+        assert!(fitted.masks[0][(1, 4)] && !fitted.masks[0][(0, 4)]);
+
+        // A 7x7 mask holding only a 3x3 neighborhood can be cropped, as long as every layer fits
+        let mut small = Array2D::new(7, 7);
+        small[(2, 3)] = true;
+        small[(4, 4)] = true;
+        let fitted = fit_masks(&[small.clone()]).unwrap();
+        assert_eq!(fitted.fit, MaskFit::Cropped { from: 7, to: 5 });
+        assert!(fitted.masks[0][(1, 2)] && fitted.masks[0][(3, 3)]);
+        let fitted = fit_masks(&[small, ring]).unwrap();
+        assert_eq!(fitted.fit, MaskFit::Padded { from: 7, to: 9 });
+        assert!(LayeredKernel::try_new(|c, _| c, fitted.masks).is_ok());
+
+        let valid = Array2D::new(5, 5);
+        assert_eq!(fit_masks(&[valid]).unwrap().fit, MaskFit::Unchanged);
+        assert!(matches!(
+            fit_masks(&[Array2D::new(4, 4)]),
+            Err(Error::InvalidKernelWidth(4))
+        ));
+        assert!(matches!(
+            fit_masks(&[Array2D::new(5, 3)]),
+            Err(Error::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(basic_mnca().layers(), basic_mnca_layers());
+
+        // Disks take the width of the other layers
+        let kernel = LayeredKernel::builder()
+            .ring(1, 3 * 4, 17)
+            .disk(2)
+            .decider(|c, _| c)
+            .build()
+            .unwrap();
+        assert_eq!(kernel.layers()[1].width(), 17);
+        assert_eq!(count_true(&kernel.layers()[1]), 13);
+
+        // Or the smallest width that holds them
+        let kernel = LayeredKernel::builder()
+            .disk(3)
+            .decider(|c, _| c)
+            .build()
+            .unwrap();
+        assert_eq!(kernel.layers()[0].width(), 9);
+
+        assert!(matches!(
+            LayeredKernel::builder().disk(1).build(),
+            Err(Error::NoDecider)
+        ));
+        assert!(matches!(
+            LayeredKernel::builder()
+                .ring(1, 4, 5)
+                .disk(3)
+                .decider(|c, _| c)
+                .build(),
+            Err(Error::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mask_rect() {
+        let mut mask = Array2D::new(5, 5);
+        assert_eq!(MaskRect::from_mask(&mask), None);
+
+        for (x, y) in [(1, 2), (2, 2), (3, 2), (1, 3), (2, 3), (3, 3)] {
+            mask[(x, y)] = true;
+        }
+        let rect = MaskRect {
+            min: (1, 2),
+            max: (4, 4),
+        };
+        assert_eq!(MaskRect::from_mask(&mask), Some(rect));
+
+        mask[(2, 3)] = false;
+        assert_eq!(MaskRect::from_mask(&mask), None);
+    }
+
+    #[test]
+    fn test_uniform_fast_path_matches_general() {
+        let uniform = |w, live| [(); 4].map(|_| Block::from_array(w, vec![live; w * w]));
+        let kernels = [
+            basic_mnca(),
+            tunable_mnca(),
+            life_layered_kernel(),
+            larger_than_life_layered_kernel(),
+        ];
+        for mut kernel in kernels {
+            let histogram = kernel.count_histogram();
+            histogram.set_enabled(true);
+            let w = calc_block_width(&kernel);
+            for live in [false, true] {
+                let blocks = uniform(w, live);
+                assert_eq!(uniform_input(&blocks), Some(live));
+
+                histogram.clear();
+                let fast = kernel.step_block(blocks.clone()).0;
+                let fast_bins = histogram.bins();
+                histogram.clear();
+                assert_eq!(fast, kernel.exec_with(blocks, true), "live {live}");
+                assert_eq!(fast_bins, histogram.bins(), "live {live}");
+            }
+        }
+
+        let invert = GeneralKernel::new(|window| !window[(1, 1)], 3).unwrap();
+        for general in [invert, shift_right_kernel()] {
+            let w = calc_block_width(&general);
+            for live in [false, true] {
+                let blocks = uniform(w, live);
+                let buf = gather_blocks(&blocks);
+                let expected = Block::from_fn(w, w, |i, j| {
+                    (general.decider)(&Array2D::from_fn(3, 3, |x, y| buf[(i + x, j + y)]))
+                });
+                assert_eq!(general.step_block(blocks).0, expected, "live {live}");
+            }
+        }
+
+        let mut mixed = uniform(2, true);
+        mixed[3].set((0, 0), false);
+        assert_eq!(uniform_input(&mixed), None);
+
+        let cache = KernelCache::new(Box::new(basic_mnca()));
+        let w = calc_block_width(&cache);
+        for live in [false, true, false, true] {
+            assert_eq!(
+                cache.step_block(uniform(w, live)).0,
+                basic_mnca().step_block(uniform(w, live)).0
+            );
+        }
+        assert!(cache.state().uniform.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_integral_image_matches_naive() {
+        let mut rng = StdRng::seed_from_u64(353);
+        let kernel = larger_than_life_layered_kernel();
+        assert!(kernel.rects[0].is_some());
+
+        let w = calc_block_width(&kernel);
+        for density in [0.1, 0.5, 0.9] {
+            let blocks = [(); 4].map(|_| random_block(&mut rng, w, density));
+            assert_eq!(
+                kernel.exec_with(blocks.clone(), true),
+                kernel.exec_with(blocks, false)
+            );
+        }
+    }
+
+    /// Cell-by-cell reference for `gather_blocks`
+    fn gather_blocks_scalar(blocks: &[Block; 4]) -> Array2D<bool> {
+        let w = blocks[0].width();
+        let mut buf: Array2D<bool> = Array2D::new(w * 2, w * 2);
+        for i in 0..2 {
+            for j in 0..2 {
+                let block = &blocks[i + 2 * j];
+                for x in 0..w {
+                    for y in 0..w {
+                        buf[(x + i * w, y + j * w)] = block[(x, y)];
+                    }
+                }
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn test_gather_blocks() {
+        let mut rng = StdRng::seed_from_u64(355);
+        for w in [2, 4, 16, 32] {
+            let blocks = [(); 4].map(|_| random_block(&mut rng, w, 0.5));
+            assert_eq!(gather_blocks(&blocks), gather_blocks_scalar(&blocks));
+        }
+    }
+
+    /// Measures the block copy-in against a full step of `larger_than_life_layered_kernel`.
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn measure_gather_blocks() {
+        let mut rng = rand::thread_rng();
+        let kernel = larger_than_life_layered_kernel();
+        let w = calc_block_width(&kernel);
+        let blocks = [(); 4].map(|_| random_block(&mut rng, w, 0.5));
+        let iters = 1000;
+
+        let time = |f: &dyn Fn()| {
+            let start = std::time::Instant::now();
+            for _ in 0..iters {
+                f();
+            }
+            start.elapsed() / iters
+        };
+
+        let scalar = time(&|| {
+            std::hint::black_box(gather_blocks_scalar(&blocks));
+        });
+        let rows = time(&|| {
+            std::hint::black_box(gather_blocks(&blocks));
+        });
+        let exec = time(&|| {
+            std::hint::black_box(kernel.step_block(blocks.clone()));
+        });
+
+        println!("{w}x{w} blocks: scalar copy {scalar:?}, row copy {rows:?}, full exec {exec:?}");
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture measure_uniform`
+    #[test]
+    #[ignore]
+    fn measure_uniform_fast_path() {
+        let mut rng = rand::thread_rng();
+        let kernel = basic_mnca();
+        let w = calc_block_width(&kernel);
+
+        // The quads of a largely quiescent frame: 90% of blocks are uniformly live, so most quads
+        // are too. Live rather than dead, since `Dense` already skips dead quads of this kernel.
+        let frame: Vec<Block> = (0..16 * 16)
+            .map(|_| match rng.gen_bool(0.9) {
+                true => Block::from_array(w, vec![true; w * w]),
+                false => random_block(&mut rng, w, 0.5),
+            })
+            .collect();
+        let quads: Vec<[Block; 4]> = (0..15 * 15)
+            .map(|i| [0, 1, 16, 17].map(|d| frame[i / 15 * 16 + i % 15 + d].clone()))
+            .collect();
+        let uniform = quads.iter().filter(|q| uniform_input(q).is_some()).count();
+
+        let time = |f: &dyn Fn(&[Block; 4]) -> Block| {
+            let start = std::time::Instant::now();
+            let out: Vec<Block> = quads.iter().map(f).collect();
+            (start.elapsed(), out)
+        };
+        let (general, expected) = time(&|q| kernel.exec_with(q.clone(), true));
+        let (fast, out) = time(&|q| kernel.step_block(q.clone()).0);
+        assert_eq!(out, expected);
+
+        let cache = KernelCache::new(Box::new(basic_mnca()));
+        time(&|q| cache.step_block(q.clone()).0);
+        let (cached, out) = time(&|q| cache.step_block(q.clone()).0);
+        assert_eq!(out, expected);
+
+        println!(
+            "{uniform} of {} quads uniform: general {general:?}, fast path {fast:?}, \
+             warm cache {cached:?}",
+            quads.len()
+        );
+    }
+
+    #[test]
+    fn test_shift_right_kernel() {
+        let mut sim = Dense::new(Box::new(shift_right_kernel()), 4, 4);
+        sim.set_pixel((1, 3), true);
+        sim.set_pixel((2, 5), true);
+
+        for step in 1..=4 {
+            sim.step();
+            let cells: Vec<_> = sim.live_cells().collect();
+            assert_eq!(cells, [(1 + step, 3), (2 + step, 5)]);
+        }
+    }
+}
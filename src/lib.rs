@@ -1,7 +1,49 @@
 #![warn(clippy::all, rust_2018_idioms)]
+//! Approximate Hashlife for multiple neighborhood cellular automata (MNCA).
+//!
+//! The items re-exported at the crate root are the stable API for embedding the simulator.
+//! The modules themselves are public for experimentation, and anything reachable only through
+//! them may change between releases. The egui application lives behind the `app` feature
+//! (enabled by default); disable default features to depend on just the engine.
 
+pub mod analysis;
+#[cfg(feature = "app")]
 mod app;
+pub mod array2d;
+pub mod block;
+pub mod capture;
+pub mod cell;
+pub mod draw;
+pub mod error;
+pub mod events;
+pub mod expr;
+pub mod format;
+pub mod history;
+pub mod kernel_file;
+pub mod kernels;
+pub mod macrocell;
+pub mod mcell;
+pub mod multistate;
+pub mod patterns;
+pub mod plaintext;
+pub mod registry;
+pub mod rle;
+pub mod sim;
+pub mod sparse;
+pub mod sweep;
+#[cfg(feature = "video")]
+pub mod video;
+
+#[cfg(feature = "app")]
 pub use app::TemplateApp;
-mod array2d;
-mod kernels;
-mod sim;
+
+pub use array2d::Array2D;
+pub use error::Error;
+pub use kernel_file::load_kernel;
+pub use kernels::{
+    basic_mnca, larger_than_life_layered_kernel, life_layered_kernel, CacheStats, KernelCache,
+    KernelCacheConfig, LayeredKernel, Life,
+};
+pub use registry::KernelRegistry;
+pub use sim::{debug_step_blocks, Block, BoundaryMode, Dense, Kernel, KernelResult, Simulation};
+pub use sparse::Sparse;
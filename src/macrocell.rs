@@ -0,0 +1,512 @@
+//! Hashlife proper: a hash-consed quadtree of blocks whose nodes memoize their own futures.
+//!
+//! A leaf (level 0) is one kernel block of 2^order cells square, and a node of level L is
+//! 2^L blocks square, made of four level L - 1 children. Nodes are interned, so identical
+//! subtrees are the same node wherever and whenever they occur, and stepping a node is computed
+//! once: the center half of a level L node, advanced 2^(L - 1) generations. At level 1 that is
+//! exactly one kernel call, which makes the level 1 memo what `KernelCache` provides; the levels
+//! above it reuse whole regions at a time, so a repeating pattern costs nothing once seen.
+//!
+//! Kernel outputs are offset by half a block (see `Simulation::zero_borders`), so on a fixed
+//! block lattice generations advance in pairs: above level 1, steps are by 2^j generations
+//! for j >= 1.
+
+use ahash::HashMap;
+
+use crate::{
+    array2d::Array2D,
+    error::Error,
+    sim::{calc_block_width, check_order, Block, Kernel},
+};
+
+/// Handle to an interned node of a `MacroCell` store. Handles from one store mean nothing to
+/// another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+enum Node {
+    Leaf(Block),
+    Branch {
+        level: usize,
+        /// Top left, top right, bottom left, bottom right, as the kernel takes its blocks
+        children: [NodeId; 4],
+    },
+}
+
+/// Work done by a `MacroCell` store since it was created
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepStats {
+    /// Blocks computed by the kernel
+    pub kernel_calls: u64,
+    /// Steps answered from the memo, at any level
+    pub hits: u64,
+    /// Steps which had to be computed from their children
+    pub misses: u64,
+}
+
+/// Interning table and step memo for the nodes of one kernel's quadtrees. Nodes are never
+/// freed; drop the store to reclaim them.
+pub struct MacroCell {
+    kernel: Box<dyn Kernel>,
+    nodes: Vec<Node>,
+    /// Live cell count of each node
+    populations: Vec<usize>,
+    leaves: HashMap<Block, NodeId>,
+    branches: HashMap<[NodeId; 4], NodeId>,
+    /// Center of each node advanced 2^j generations, by (node, j)
+    results: HashMap<(NodeId, usize), NodeId>,
+    /// The all-dead node of each level, once built
+    empty: Vec<NodeId>,
+    stats: StepStats,
+}
+
+impl MacroCell {
+    /// Panics if the kernel's order is unsupported; see `try_new`
+    pub fn new(kernel: Box<dyn Kernel>) -> Self {
+        Self::try_new(kernel).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fails if the kernel's order exceeds `MAX_ORDER`
+    pub fn try_new(kernel: Box<dyn Kernel>) -> Result<Self, Error> {
+        check_order(kernel.order())?;
+        let w = calc_block_width(&*kernel);
+        let mut store = Self {
+            kernel,
+            nodes: vec![],
+            populations: vec![],
+            leaves: HashMap::default(),
+            branches: HashMap::default(),
+            results: HashMap::default(),
+            empty: vec![],
+            stats: StepStats::default(),
+        };
+        let zero = store.leaf(Block::new(w, w));
+        store.empty.push(zero);
+        Ok(store)
+    }
+
+    /// Width of a leaf in cells
+    pub fn block_width(&self) -> usize {
+        calc_block_width(&*self.kernel)
+    }
+
+    /// The interned leaf holding `block`. Panics unless it is the kernel's block size.
+    pub fn leaf(&mut self, block: Block) -> NodeId {
+        let w = self.block_width();
+        assert_eq!(
+            (block.width(), block.height()),
+            (w, w),
+            "leaves must be kernel blocks"
+        );
+        if let Some(&id) = self.leaves.get(&block) {
+            return id;
+        }
+        let population = block.population();
+        let id = self.push(Node::Leaf(block.clone()), population);
+        self.leaves.insert(block, id);
+        id
+    }
+
+    /// The interned node with these children, top left to bottom right. Panics unless they are
+    /// all of one level.
+    pub fn branch(&mut self, children: [NodeId; 4]) -> NodeId {
+        if let Some(&id) = self.branches.get(&children) {
+            return id;
+        }
+        let level = self.level(children[0]);
+        assert!(
+            children.iter().all(|&c| self.level(c) == level),
+            "children must share a level"
+        );
+        let population = children.iter().map(|&c| self.population(c)).sum();
+        let id = self.push(
+            Node::Branch {
+                level: level + 1,
+                children,
+            },
+            population,
+        );
+        self.branches.insert(children, id);
+        id
+    }
+
+    fn push(&mut self, node: Node, population: usize) -> NodeId {
+        let id = NodeId(self.nodes.len().try_into().expect("too many nodes"));
+        self.nodes.push(node);
+        self.populations.push(population);
+        id
+    }
+
+    /// The all-dead node of the given level
+    pub fn empty(&mut self, level: usize) -> NodeId {
+        while self.empty.len() <= level {
+            let below = *self.empty.last().expect("the empty leaf is built first");
+            let node = self.branch([below; 4]);
+            self.empty.push(node);
+        }
+        self.empty[level]
+    }
+
+    /// 0 for leaves; a node of level L is 2^L blocks square
+    pub fn level(&self, id: NodeId) -> usize {
+        match &self.nodes[id.0 as usize] {
+            Node::Leaf(_) => 0,
+            Node::Branch { level, .. } => *level,
+        }
+    }
+
+    pub fn population(&self, id: NodeId) -> usize {
+        self.populations[id.0 as usize]
+    }
+
+    /// Number of distinct nodes interned so far
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn stats(&self) -> StepStats {
+        self.stats
+    }
+
+    /// The four children of a branch; panics on a leaf
+    pub fn children(&self, id: NodeId) -> [NodeId; 4] {
+        match &self.nodes[id.0 as usize] {
+            Node::Leaf(_) => panic!("leaves have no children"),
+            Node::Branch { children, .. } => *children,
+        }
+    }
+
+    /// The block of a leaf; panics on a branch
+    pub fn block(&self, id: NodeId) -> &Block {
+        match &self.nodes[id.0 as usize] {
+            Node::Leaf(block) => block,
+            Node::Branch { .. } => panic!("branches have no block"),
+        }
+    }
+
+    /// The center half of a node of level 2 or more, without stepping
+    pub fn center(&mut self, id: NodeId) -> NodeId {
+        let [a, b, c, d] = self.children(id).map(|child| self.children(child));
+        self.branch([a[3], b[2], c[1], d[0]])
+    }
+
+    /// The center half of a node of level L >= 1, advanced 2^(L - 1) generations
+    pub fn step(&mut self, id: NodeId) -> NodeId {
+        let level = self.level(id);
+        assert!(level >= 1, "leaves can't be stepped");
+        self.step_by(id, level - 1)
+    }
+
+    /// The center half of a node of level L, advanced 2^j generations. Requires j < L, and also
+    /// j >= 1 unless L is 1, since the result must lie on the block lattice.
+    pub fn step_by(&mut self, id: NodeId, j: usize) -> NodeId {
+        let level = self.level(id);
+        assert!(
+            j < level && (j >= 1 || level == 1),
+            "can't step a level {level} node by 2^{j} generations"
+        );
+        if let Some(&result) = self.results.get(&(id, j)) {
+            self.stats.hits += 1;
+            return result;
+        }
+        self.stats.misses += 1;
+
+        let result = if self.population(id) == 0 && self.kernel.zero_stable() {
+            self.empty(level - 1)
+        } else if level == 1 {
+            let blocks = self.children(id).map(|child| self.block(child).clone());
+            self.stats.kernel_calls += 1;
+            let (block, _) = self.kernel.step_block(blocks);
+            self.leaf(block)
+        } else {
+            self.step_children(id, j)
+        };
+        self.results.insert((id, j), result);
+        result
+    }
+
+    /// Step each of the nine overlapping half-size subnodes, then the four quarter-overlapping
+    /// nodes their results form: twice by 2^(L - 2) generations at full speed, or once by 2^j
+    /// and then re-centered when slower
+    fn step_children(&mut self, id: NodeId, j: usize) -> NodeId {
+        let level = self.level(id);
+        let [a, b, c, d] = self.children(id);
+        let [a_, b_, c_, d_] = [a, b, c, d].map(|child| self.children(child));
+        let subnodes = [
+            a,
+            self.branch([a_[1], b_[0], a_[3], b_[2]]),
+            b,
+            self.branch([a_[2], a_[3], c_[0], c_[1]]),
+            self.branch([a_[3], b_[2], c_[1], d_[0]]),
+            self.branch([b_[2], b_[3], d_[0], d_[1]]),
+            c,
+            self.branch([c_[1], d_[0], c_[3], d_[2]]),
+            d,
+        ];
+
+        let full_speed = j == level - 1;
+        let inner = if full_speed { j - 1 } else { j };
+        let r = subnodes.map(|node| self.step_by(node, inner));
+
+        let quadrants = [[0, 1, 3, 4], [1, 2, 4, 5], [3, 4, 6, 7], [4, 5, 7, 8]]
+            .map(|[p, q, s, t]| self.branch([r[p], r[q], r[s], r[t]]));
+        let quadrants = quadrants.map(|node| {
+            if full_speed {
+                self.step_by(node, inner)
+            } else {
+                self.center(node)
+            }
+        });
+        self.branch(quadrants)
+    }
+}
+
+/// An unbounded grid stepped by a `MacroCell` store. Cell (0, 0) is at the middle of the root,
+/// so coordinates may be negative.
+pub struct Hashlife {
+    store: MacroCell,
+    root: NodeId,
+    generation: u64,
+}
+
+impl Hashlife {
+    /// An empty grid. Panics if the kernel's order is unsupported.
+    pub fn new(kernel: Box<dyn Kernel>) -> Self {
+        let mut store = MacroCell::new(kernel);
+        let root = store.empty(1);
+        Self {
+            store,
+            root,
+            generation: 0,
+        }
+    }
+
+    /// A grid holding `grid` with its top left cell at (0, 0)
+    pub fn from_grid(kernel: Box<dyn Kernel>, grid: &Array2D<bool>) -> Self {
+        let mut life = Self::new(kernel);
+        let w = life.store.block_width();
+        let blocks = grid.width().max(grid.height()).div_ceil(w).max(1);
+        let level = blocks.next_power_of_two().trailing_zeros() as usize;
+        let quadrant = build(&mut life.store, grid, level, (0, 0));
+        let e = life.store.empty(level);
+        life.root = life.store.branch([e, e, e, quadrant]);
+        life
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn population(&self) -> usize {
+        self.store.population(self.root)
+    }
+
+    pub fn store(&self) -> &MacroCell {
+        &self.store
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Half the root's width in cells
+    fn half_width(&self) -> i64 {
+        (calc_block_width(&*self.store.kernel) << self.store.level(self.root)) as i64 / 2
+    }
+
+    pub fn get_pixel(&self, (x, y): (i64, i64)) -> bool {
+        let half = self.half_width();
+        if !(-half..half).contains(&x) || !(-half..half).contains(&y) {
+            return false;
+        }
+        let (mut id, mut x, mut y) = (self.root, (x + half) as usize, (y + half) as usize);
+        loop {
+            let level = self.store.level(id);
+            if level == 0 {
+                return self.store.block(id)[(x, y)];
+            }
+            let half = self.store.block_width() << (level - 1);
+            let (right, down) = (x >= half, y >= half);
+            id = self.store.children(id)[usize::from(right) + 2 * usize::from(down)];
+            x -= half * usize::from(right);
+            y -= half * usize::from(down);
+        }
+    }
+
+    /// The cells of a rectangle with its top left at `origin`
+    pub fn region(&self, origin: (i64, i64), width: usize, height: usize) -> Array2D<bool> {
+        Array2D::from_fn(width, height, |x, y| {
+            self.get_pixel((origin.0 + x as i64, origin.1 + y as i64))
+        })
+    }
+
+    /// Surround the root with empty space, doubling its width about the same center
+    fn expand(&mut self) {
+        let level = self.store.level(self.root);
+        let e = self.store.empty(level - 1);
+        let [a, b, c, d] = self.store.children(self.root);
+        let children = [[e, e, e, a], [e, e, b, e], [e, c, e, e], [d, e, e, e]]
+            .map(|grandchildren| self.store.branch(grandchildren));
+        self.root = self.store.branch(children);
+    }
+
+    /// Advance 2^k generations, for k >= 1
+    pub fn step_pow2(&mut self, k: usize) {
+        assert!(k >= 1, "generations advance in pairs");
+        // Everything must fit in the root's center quarter, leaving room to spread by up to
+        // half a block per generation within the center half that stepping returns
+        while self.store.level(self.root) < k + 2 || !self.centered() {
+            self.expand();
+        }
+        self.root = self.store.step_by(self.root, k);
+        self.generation += 1 << k;
+    }
+
+    /// Whether every live cell is within the center quarter of the root
+    fn centered(&mut self) -> bool {
+        if self.store.level(self.root) < 3 {
+            return false;
+        }
+        let center = self.store.center(self.root);
+        let quarter = self.store.center(center);
+        self.store.population(quarter) == self.population()
+    }
+}
+
+/// The node of the given level covering the blocks of `grid` from `origin` (in blocks)
+fn build(
+    store: &mut MacroCell,
+    grid: &Array2D<bool>,
+    level: usize,
+    origin: (usize, usize),
+) -> NodeId {
+    let w = store.block_width();
+    let size = w << level;
+    if origin.0 * w >= grid.width() || origin.1 * w >= grid.height() {
+        return store.empty(level);
+    }
+    if level == 0 {
+        let (x0, y0) = (origin.0 * w, origin.1 * w);
+        let block = Block::from_fn(w, w, |x, y| {
+            let (x, y) = (x0 + x, y0 + y);
+            x < grid.width() && y < grid.height() && grid[(x, y)]
+        });
+        return store.leaf(block);
+    }
+    let half = size / w / 2;
+    let children = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(i, j)| {
+        build(
+            store,
+            grid,
+            level - 1,
+            (origin.0 + i * half, origin.1 + j * half),
+        )
+    });
+    store.branch(children)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kernels::{life_layered_kernel, Life},
+        sim::{Dense, Simulation},
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_interning() {
+        let mut store = MacroCell::new(Box::new(Life));
+        let live = store.leaf(Block::from_rows(&["#-", "--"]));
+        let again = store.leaf(Block::from_rows(&["#-", "--"]));
+        assert_eq!(live, again);
+        let e = store.empty(0);
+        let a = store.branch([live, e, e, live]);
+        let b = store.branch([live, e, e, live]);
+        assert_eq!(a, b);
+        assert_eq!((store.level(a), store.population(a)), (1, 2));
+        assert_eq!(store.empty(3), store.empty(3));
+        let e3 = store.empty(3);
+        assert_eq!((store.level(e3), store.population(e3)), (3, 0));
+    }
+
+    #[test]
+    fn test_blinker_is_memoized() {
+        let grid = Array2D::from_rows(&["---", "###", "---"]);
+        let mut life = Hashlife::from_grid(Box::new(Life), &grid);
+        life.step_pow2(1);
+        assert_eq!(life.region((0, 0), 3, 3), grid);
+
+        // Once a full period has been seen, the same root recurs and nothing is recomputed
+        let warm = life.store().stats();
+        let nodes = life.store().node_count();
+        for _ in 0..100 {
+            life.step_pow2(1);
+        }
+        let stats = life.store().stats();
+        assert_eq!(stats.kernel_calls, warm.kernel_calls);
+        assert_eq!(stats.misses, warm.misses);
+        assert_eq!(life.store().node_count(), nodes);
+        assert_eq!(life.region((0, 0), 3, 3), grid);
+        assert_eq!(life.generation(), 202);
+    }
+
+    #[test]
+    fn test_matches_dense() {
+        let mut rng = StdRng::seed_from_u64(501);
+        for kernel in [
+            || -> Box<dyn Kernel> { Box::new(Life) },
+            || -> Box<dyn Kernel> { Box::new(life_layered_kernel()) },
+        ] {
+            let mut dense = Dense::new(kernel(), 64, 64);
+            for y in 56..72 {
+                for x in 56..72 {
+                    dense.set_pixel((x, y), rng.gen_bool(0.4));
+                }
+            }
+            let mut life = Hashlife::from_grid(kernel(), &dense.snapshot());
+            for _ in 0..16 {
+                life.step_pow2(1);
+                dense.step();
+                dense.step();
+                assert_eq!(life.region((0, 0), 128, 128), dense.snapshot());
+            }
+            assert_eq!(life.population(), dense.population());
+        }
+    }
+
+    #[test]
+    fn test_jump_matches_small_steps() {
+        let mut rng = StdRng::seed_from_u64(5010);
+        let grid = Array2D::from_fn(24, 24, |_, _| rng.gen_bool(0.35));
+        let mut small = Hashlife::from_grid(Box::new(Life), &grid);
+        let mut jump = Hashlife::from_grid(Box::new(Life), &grid);
+        for _ in 0..32 {
+            small.step_pow2(1);
+        }
+        jump.step_pow2(6);
+        assert_eq!(jump.generation(), small.generation());
+        let span = 24 + 2 * 64;
+        assert_eq!(
+            jump.region((-64, -64), span, span),
+            small.region((-64, -64), span, span)
+        );
+    }
+
+    #[test]
+    fn test_glider_reuses_leaf_steps() {
+        let glider = Array2D::from_rows(&["-#-", "--#", "###"]);
+        let mut life = Hashlife::from_grid(Box::new(Life), &glider);
+        for _ in 0..8 {
+            life.step_pow2(1);
+        }
+        let warm = life.store().stats();
+        for _ in 0..64 {
+            life.step_pow2(1);
+        }
+        // It moves onto new nodes, but its phases recur, so only a few blocks are new
+        let stats = life.store().stats();
+        assert!(stats.kernel_calls - warm.kernel_calls < 64, "{stats:?}");
+        assert_eq!(life.population(), 5);
+    }
+}
@@ -0,0 +1,384 @@
+//! Import of MCell (`.mcl`) files, for the Life-like and Larger than Life rules with two states.
+//!
+//! Only the `#GAME`, `#RULE`, `#BOARD` and `#L` lines are read; everything else is ignored.
+//! Sizes are checked before anything is allocated, so boards and patterns are limited to
+//! `MAX_SIDE` cells a side and `MAX_CELLS` in all.
+
+use std::{ops::RangeInclusive, path::Path};
+
+use crate::{
+    array2d::Array2D,
+    error::Error,
+    kernels::{Decider, LayeredKernel, Totalistic},
+    sim::MAX_ORDER,
+};
+
+/// Longest side of a board or pattern
+pub const MAX_SIDE: usize = 1 << 16;
+
+/// Most cells in a board or pattern
+pub const MAX_CELLS: usize = 1 << 26;
+
+/// Load an MCell file from disk
+pub fn load_mcl(path: impl AsRef<Path>) -> Result<(LayeredKernel, Array2D<bool>), Error> {
+    parse_mcl(&std::fs::read_to_string(path)?)
+}
+
+/// Parse an MCell file into its rule and pattern. If the file gives a `#BOARD` size, the pattern
+/// is centered on a board of that size, as MCell places it; otherwise it is cropped to the `#L`
+/// lines. Row 0 is the first (top) row.
+pub fn parse_mcl(input: &str) -> Result<(LayeredKernel, Array2D<bool>), Error> {
+    let mut game = "Life";
+    let mut rule = None;
+    let mut board = None;
+    let mut body = String::new();
+
+    for line in input.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("#GAME") {
+            game = rest.trim();
+        } else if let Some(rest) = line.strip_prefix("#RULE") {
+            rule = Some(rest.trim());
+        } else if let Some(rest) = line.strip_prefix("#BOARD") {
+            board = Some(parse_board(rest.trim())?);
+        } else if let Some(rest) = line.strip_prefix("#L") {
+            body.push_str(rest.trim());
+        }
+    }
+
+    let rule = rule.ok_or_else(|| invalid("missing #RULE"))?;
+    let kernel = match game {
+        "Life" => life_like(rule)?,
+        "Generations" => generations(rule)?,
+        "Larger than Life" => larger_than_life(rule)?,
+        _ => return Err(invalid(format!("unsupported game {game:?}"))),
+    };
+
+    let pattern = parse_body(&body)?;
+    let pattern = match board {
+        Some(dims) => center_on_board(&pattern, dims)?,
+        None => pattern,
+    };
+
+    Ok((kernel, pattern))
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::Mcell(msg.into())
+}
+
+/// `S/B`, with each side a list of digits, e.g. `23/3` for Conway's Life
+fn life_like(rule: &str) -> Result<LayeredKernel, Error> {
+    let (survival, birth) = rule
+        .split_once('/')
+        .ok_or_else(|| invalid(format!("bad Life rule {rule:?}")))?;
+    let decider = Totalistic {
+        birth: digits(birth)?,
+        survival: digits(survival)?,
+    };
+    neighborhood_kernel(decider, 1, false, Neighborhood::Moore)
+}
+
+/// `S/B/C`, which is Life-like when there are only two states
+fn generations(rule: &str) -> Result<LayeredKernel, Error> {
+    let (life, states) = rule
+        .rsplit_once('/')
+        .ok_or_else(|| invalid(format!("bad Generations rule {rule:?}")))?;
+    match states.trim().parse::<u32>() {
+        Ok(0..=2) => life_like(life),
+        Ok(n) => Err(invalid(format!(
+            "Generations with {n} states is unsupported"
+        ))),
+        Err(_) => Err(invalid(format!("bad state count {states:?}"))),
+    }
+}
+
+/// Comma separated fields, e.g. `R5,C0,M1,S34..58,B34..45,NM`
+fn larger_than_life(rule: &str) -> Result<LayeredKernel, Error> {
+    let mut radius = 1;
+    let mut middle = false;
+    let mut neighborhood = Neighborhood::Moore;
+    let mut survival = vec![];
+    let mut birth = vec![];
+
+    for field in rule.split(',').map(str::trim) {
+        let bad = || invalid(format!("bad Larger than Life field {field:?}"));
+        let (key, value) = field.split_at(field.chars().next().map_or(0, char::len_utf8));
+        match key {
+            "R" => radius = value.parse().map_err(|_| bad())?,
+            "C" => match value.parse::<u32>() {
+                Ok(0..=2) => (),
+                Ok(n) => return Err(invalid(format!("{n} states are unsupported"))),
+                Err(_) => return Err(bad()),
+            },
+            "M" => middle = value == "1",
+            "S" => survival = vec![range(value).ok_or_else(bad)?],
+            "B" => birth = vec![range(value).ok_or_else(bad)?],
+            "N" => {
+                neighborhood = match value {
+                    "M" => Neighborhood::Moore,
+                    "N" => Neighborhood::VonNeumann,
+                    _ => return Err(invalid(format!("unsupported neighborhood {value:?}"))),
+                }
+            }
+            _ => return Err(bad()),
+        }
+    }
+
+    if radius == 0 {
+        return Err(invalid("radius must be at least 1"));
+    }
+    // The mask is padded to a power of two radius, which must give a supported order. Checked
+    // here because the radius comes from the file, and the mask is allocated before the order
+    // is checked.
+    let largest = 1 << (MAX_ORDER - 1);
+    if radius > largest {
+        return Err(invalid(format!(
+            "radius {radius} is above the largest supported, {largest}"
+        )));
+    }
+
+    let decider = Totalistic { birth, survival };
+    neighborhood_kernel(decider, radius, middle, neighborhood)
+}
+
+#[derive(Clone, Copy)]
+enum Neighborhood {
+    /// Square
+    Moore,
+    /// Diamond
+    VonNeumann,
+}
+
+/// A single layer covering the neighborhood of the given radius. Kernel widths are 2^k + 1, so
+/// the mask is padded out to the next power of two radius.
+fn neighborhood_kernel(
+    decider: Totalistic,
+    radius: usize,
+    middle: bool,
+    neighborhood: Neighborhood,
+) -> Result<LayeredKernel, Error> {
+    let half = radius.next_power_of_two();
+    let width = 2 * half + 1;
+    let mut mask = Array2D::new(width, width);
+    for y in 0..width {
+        for x in 0..width {
+            let (dx, dy) = (x.abs_diff(half), y.abs_diff(half));
+            let within = match neighborhood {
+                Neighborhood::Moore => dx.max(dy) <= radius,
+                Neighborhood::VonNeumann => dx + dy <= radius,
+            };
+            mask[(x, y)] = within && (middle || (dx, dy) != (0, 0));
+        }
+    }
+
+    LayeredKernel::with_decider(Decider::Totalistic(decider), vec![mask])
+}
+
+/// Each digit as a count, e.g. `23` for 2 or 3
+fn digits(s: &str) -> Result<Vec<RangeInclusive<u16>>, Error> {
+    s.trim()
+        .chars()
+        .map(|c| {
+            let n = c
+                .to_digit(10)
+                .ok_or_else(|| invalid(format!("bad count {c:?}")))?;
+            Ok(n as u16..=n as u16)
+        })
+        .collect()
+}
+
+/// `lo..hi`, inclusive
+fn range(s: &str) -> Option<RangeInclusive<u16>> {
+    let (lo, hi) = s.split_once("..")?;
+    Some(lo.parse().ok()?..=hi.parse().ok()?)
+}
+
+fn parse_board(s: &str) -> Result<(usize, usize), Error> {
+    let bad = || invalid(format!("bad #BOARD {s:?}"));
+    let (w, h) = s.split_once('x').ok_or_else(bad)?;
+    let dims = (
+        w.trim().parse().map_err(|_| bad())?,
+        h.trim().parse().map_err(|_| bad())?,
+    );
+    check_size(dims, "#BOARD")?;
+    Ok(dims)
+}
+
+/// Fail unless a grid of the given dimensions is within `MAX_SIDE` and `MAX_CELLS`
+fn check_size((width, height): (usize, usize), what: &str) -> Result<(), Error> {
+    let cells = width.checked_mul(height);
+    if width > MAX_SIDE || height > MAX_SIDE || cells.is_none_or(|n| n > MAX_CELLS) {
+        return Err(invalid(format!(
+            "{what} of {width}x{height} is above the limit of {MAX_SIDE} a side and {MAX_CELLS} cells"
+        )));
+    }
+    Ok(())
+}
+
+/// The concatenated `#L` lines: `.` is dead and `A` live, each optionally preceded by a run
+/// count, and `$` ends a row
+fn parse_body(body: &str) -> Result<Array2D<bool>, Error> {
+    let mut rows: Vec<Vec<bool>> = vec![vec![]];
+    let mut count = None;
+    for c in body.chars() {
+        if let Some(d) = c.to_digit(10) {
+            let next = count.unwrap_or(0_usize).checked_mul(10);
+            count = Some(
+                next.and_then(|n| n.checked_add(d as usize))
+                    .ok_or_else(|| invalid("run count is too large"))?,
+            );
+            continue;
+        }
+        let n = count.take().unwrap_or(1);
+        // Runs are bounded before resizing, so a huge count fails rather than aborting
+        let too_long = |len: usize| len.checked_add(n).is_none_or(|end| end > MAX_SIDE);
+        match c {
+            '.' | 'A' => {
+                let row = rows.last_mut().expect("rows is never empty");
+                if too_long(row.len()) {
+                    return Err(invalid(format!("pattern row is over {MAX_SIDE} cells")));
+                }
+                row.resize(row.len() + n, c == 'A');
+            }
+            '$' => {
+                if too_long(rows.len()) {
+                    return Err(invalid(format!("pattern is over {MAX_SIDE} rows")));
+                }
+                rows.extend((0..n).map(|_| vec![]));
+            }
+            'B'..='X' | 'a'..='j' => {
+                return Err(invalid(format!("state {c:?} is unsupported")));
+            }
+            _ if c.is_whitespace() => (),
+            _ => return Err(invalid(format!("unknown pattern token {c:?}"))),
+        }
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    check_size((width, rows.len()), "pattern")?;
+    let mut out = Array2D::new(width, rows.len());
+    for (y, row) in rows.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            out[(x, y)] = cell;
+        }
+    }
+    Ok(out)
+}
+
+fn center_on_board(
+    pattern: &Array2D<bool>,
+    (width, height): (usize, usize),
+) -> Result<Array2D<bool>, Error> {
+    if pattern.width() > width || pattern.height() > height {
+        return Err(invalid(format!(
+            "pattern is larger than the {width}x{height} board"
+        )));
+    }
+
+    let (x0, y0) = (
+        (width - pattern.width()) / 2,
+        (height - pattern.height()) / 2,
+    );
+    let mut board = Array2D::new(width, height);
+    for y in 0..pattern.height() {
+        for x in 0..pattern.width() {
+            board[(x0 + x, y0 + y)] = pattern[(x, y)];
+        }
+    }
+    Ok(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        kernels::Life,
+        sim::{Block, Kernel},
+    };
+
+    const GLIDER: &str = "#MCell 4.20\n#GAME Life\n#RULE 23/3\n#D A glider\n#L .A$..A\n#L $3A\n";
+
+    #[test]
+    fn test_life_matches_life() {
+        let (kernel, pattern) = parse_mcl(GLIDER).unwrap();
+        assert_eq!(kernel.order(), 1);
+        assert_eq!((pattern.width(), pattern.height()), (3, 3));
+        assert_eq!(
+            pattern.data(),
+            [false, true, false, false, false, true, true, true, true]
+        );
+
+        let mut rng = StdRng::seed_from_u64(381);
+        for _ in 0..64 {
+            let blocks: [Block; 4] =
+                std::array::from_fn(|_| Block::from_array(2, (0..4).map(|_| rng.gen()).collect()));
+            assert_eq!(
+                kernel.step_block(blocks.clone()).0,
+                Life.step_block(blocks).0
+            );
+        }
+    }
+
+    #[test]
+    fn test_larger_than_life() {
+        let input =
+            "#GAME Larger than Life\n#RULE R5,C0,M1,S34..58,B34..45,NM\n#BOARD 40x30\n#L 2A\n";
+        let (kernel, pattern) = parse_mcl(input).unwrap();
+
+        // Radius 5 is padded to 8
+        assert_eq!(kernel.order(), 4);
+        let mask = &kernel.layers()[0];
+        assert_eq!(mask.data().iter().filter(|&&x| x).count(), 11 * 11);
+
+        assert_eq!((pattern.width(), pattern.height()), (40, 30));
+        assert!(pattern[(19, 14)] && pattern[(20, 14)]);
+        assert_eq!(pattern.data().iter().filter(|&&x| x).count(), 2);
+    }
+
+    #[test]
+    fn test_von_neumann_without_middle() {
+        let (kernel, _) =
+            parse_mcl("#GAME Larger than Life\n#RULE R2,M0,S1..2,B1..1,NN\n").unwrap();
+        let mask = &kernel.layers()[0];
+        assert_eq!(mask.width(), 5);
+        assert_eq!(mask.data().iter().filter(|&&x| x).count(), 12);
+        assert!(!mask[(2, 2)]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        for input in [
+            "#GAME Weighted Life\n#RULE NW1\n",
+            "#GAME Generations\n#RULE 345/2/4\n",
+            "#GAME Larger than Life\n#RULE R5,C3,S1..2,B1..2\n",
+            "#GAME Life\n#RULE 23/3\n#L 2B\n",
+            "#GAME Life\n",
+        ] {
+            assert!(matches!(parse_mcl(input), Err(Error::Mcell(_))), "{input}");
+        }
+
+        // Sizes from the file are rejected before allocating
+        let too_big = [
+            "#GAME Larger than Life\n#RULE R100000,S1..2,B1..2\n",
+            "#GAME Larger than Life\n#RULE R1000,S1..2,B1..2\n",
+            "#GAME Life\n#RULE 23/3\n#BOARD 100000x100000\n",
+            "#GAME Life\n#RULE 23/3\n#BOARD 18446744073709551615x2\n",
+            "#GAME Life\n#RULE 23/3\n#L 99999999999A\n",
+            "#GAME Life\n#RULE 23/3\n#L 99999999999999999999A\n",
+            "#GAME Life\n#RULE 23/3\n#L 99999999999$A\n",
+        ];
+        for input in too_big {
+            assert!(matches!(parse_mcl(input), Err(Error::Mcell(_))), "{input}");
+        }
+        // Within limits on each side, but not in area
+        let wide = format!("#GAME Life\n#RULE 23/3\n#L {MAX_SIDE}A$\n#L {MAX_SIDE}$A\n");
+        assert!(matches!(parse_mcl(&wide), Err(Error::Mcell(_))));
+        // The largest radius is still allowed
+        assert!(parse_mcl("#GAME Larger than Life\n#RULE R64,S1..2,B1..2\n").is_ok());
+
+        // Two-state Generations is just Life
+        assert!(parse_mcl("#GAME Generations\n#RULE 23/3/2\n").is_ok());
+    }
+}
@@ -0,0 +1,225 @@
+//! Rules over cells with more than two states, such as the Generations family.
+//!
+//! These don't fit the boolean `Kernel` used by the simulators, so `StateMachineKernel` steps
+//! grids of state indices (0 being dead) directly.
+
+use crate::{
+    array2d::Array2D,
+    error::Error,
+    kernels::{calculate_block_order_from_kernel_width, gather_arrays},
+    sim::check_order,
+};
+
+/// Given the center cell's state and, for each layer, the number of neighbors in each state
+/// (`counts[layer][state]`), returns the next state of the center cell
+pub type StateDecider = fn(u8, &[Vec<u16>]) -> u8;
+
+pub struct StateMachineKernel {
+    /// Number of states, including the dead state 0
+    states: u8,
+    decider: StateDecider,
+    /// Masks from which to count neighbors, as in `LayeredKernel`
+    layers: Vec<Array2D<bool>>,
+    block_order: usize,
+}
+
+impl StateMachineKernel {
+    pub fn new(
+        states: u8,
+        decider: StateDecider,
+        layers: Vec<Array2D<bool>>,
+    ) -> Result<Self, Error> {
+        let first = layers.first().ok_or(Error::NoLayers)?;
+        let expected = (first.width(), first.height());
+        if let Some(layer) = layers.iter().find(|l| (l.width(), l.height()) != expected) {
+            return Err(Error::ShapeMismatch {
+                expected,
+                found: (layer.width(), layer.height()),
+            });
+        }
+
+        let block_order = calculate_block_order_from_kernel_width(expected.0)?;
+        check_order(block_order)?;
+
+        Ok(Self {
+            states,
+            decider,
+            layers,
+            block_order,
+        })
+    }
+
+    pub fn states(&self) -> u8 {
+        self.states
+    }
+
+    /// Power law size of the basic block, as in `Kernel::order`
+    pub fn order(&self) -> usize {
+        self.block_order
+    }
+
+    /// Step the block at the center of four blocks, arranged as for `Kernel::step_block`
+    pub fn step_block(&self, blocks: [Array2D<u8>; 4]) -> Array2D<u8> {
+        let w = 1 << self.block_order;
+        assert_eq!(w, blocks[0].width());
+
+        let buf = gather_arrays(&blocks);
+        let mut counts = vec![vec![0_u16; self.states as usize]; self.layers.len()];
+
+        let mut out_data = vec![];
+        for j in 0..w {
+            for i in 0..w {
+                let half = self.layers[0].width() / 2;
+                let center = buf[(half + i, half + j)];
+                self.count((i, j), &buf, &mut counts);
+                out_data.push((self.decider)(center, &counts));
+            }
+        }
+
+        Array2D::from_array(w, out_data)
+    }
+
+    /// Step a whole grid, treating everything outside it as dead
+    pub fn step_grid(&self, grid: &Array2D<u8>) -> Array2D<u8> {
+        let half = self.layers[0].width() / 2;
+
+        // Pad with dead cells, so that every window lies inside the buffer
+        let mut buf = Array2D::new(grid.width() + 2 * half, grid.height() + 2 * half);
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                buf[(x + half, y + half)] = grid[(x, y)];
+            }
+        }
+
+        let mut counts = vec![vec![0_u16; self.states as usize]; self.layers.len()];
+        let mut out = Array2D::new(grid.width(), grid.height());
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                self.count((x, y), &buf, &mut counts);
+                out[(x, y)] = (self.decider)(grid[(x, y)], &counts);
+            }
+        }
+        out
+    }
+
+    /// Count the states under each layer, with the layer's top-left corner at `corner`
+    fn count(&self, corner: (usize, usize), buf: &Array2D<u8>, counts: &mut [Vec<u16>]) {
+        for (layer, counts) in self.layers.iter().zip(counts) {
+            counts.iter_mut().for_each(|c| *c = 0);
+            for y in 0..layer.height() {
+                for x in 0..layer.width() {
+                    if layer[(x, y)] {
+                        counts[buf[(corner.0 + x, corner.1 + y)] as usize] += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// RGB display color for each state: black for dead, then a ramp from white (state 1) to dim
+/// blue, so that e.g. the dying cells of Generations rules fade out
+pub fn palette(states: u8) -> Vec<[u8; 3]> {
+    let (first, last) = ([255.0, 255.0, 255.0], [40.0, 60.0, 160.0]);
+    (0..states)
+        .map(|state| match state {
+            0 => [0, 0, 0],
+            s => {
+                let t = match states {
+                    0..=2 => 0.0,
+                    _ => (s - 1) as f32 / (states - 2) as f32,
+                };
+                [0, 1, 2].map(|c| (first[c] + t * (last[c] - first[c])) as u8)
+            }
+        })
+        .collect()
+}
+
+/// Brian's Brain: a dead cell with exactly two firing neighbors fires, firing cells start dying,
+/// and dying cells die. States are 0 (dead), 1 (firing) and 2 (dying).
+pub fn brians_brain() -> StateMachineKernel {
+    fn decider(center: u8, counts: &[Vec<u16>]) -> u8 {
+        match center {
+            0 if counts[0][1] == 2 => 1,
+            0 => 0,
+            1 => 2,
+            _ => 0,
+        }
+    }
+
+    let mut moore = Array2D::from_array(3, vec![true; 9]);
+    moore[(1, 1)] = false;
+
+    StateMachineKernel::new(3, decider, vec![moore]).expect("Brian's Brain kernel is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn grid(width: usize, cells: &[((usize, usize), u8)]) -> Array2D<u8> {
+        let mut grid = Array2D::new(width, width);
+        for &(xy, state) in cells {
+            grid[xy] = state;
+        }
+        grid
+    }
+
+    #[test]
+    fn test_brians_brain_spaceship() {
+        let kernel = brians_brain();
+
+        // The 2x2 orthogonal spaceship: a firing pair, led by nothing and trailed by a dying pair
+        let cells = |x: usize| [((x, 4), 2), ((x, 5), 2), ((x + 1, 4), 1), ((x + 1, 5), 1)];
+        let mut state = grid(12, &cells(1));
+        for step in 1..8 {
+            state = kernel.step_grid(&state);
+            assert_eq!(state, grid(12, &cells(1 + step)), "step {step}");
+        }
+    }
+
+    #[test]
+    fn test_brians_brain_single_fire_dies() {
+        let kernel = brians_brain();
+        let state = kernel.step_grid(&grid(5, &[((2, 2), 1)]));
+        assert_eq!(state, grid(5, &[((2, 2), 2)]));
+        assert_eq!(kernel.step_grid(&state), grid(5, &[]));
+    }
+
+    #[test]
+    fn test_exec_matches_step_grid() {
+        let kernel = brians_brain();
+        let mut rng = StdRng::seed_from_u64(373);
+
+        let full: Array2D<u8> =
+            Array2D::from_array(4, (0..16).map(|_| rng.gen_range(0..3)).collect());
+        let stepped = kernel.step_grid(&full);
+
+        // The center 2x2 of the 4x4 grid has its whole neighborhood inside the grid
+        let block = |x0: usize, y0: usize| {
+            let data = (0..4).map(|i| full[(x0 + i % 2, y0 + i / 2)]).collect();
+            Array2D::from_array(2, data)
+        };
+        let out = kernel.step_block([block(0, 0), block(2, 0), block(0, 2), block(2, 2)]);
+        for y in 0..2 {
+            for x in 0..2 {
+                assert_eq!(out[(x, y)], stepped[(x + 1, y + 1)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        assert_eq!(palette(2), [[0, 0, 0], [255, 255, 255]]);
+        assert_eq!(palette(3), [[0, 0, 0], [255, 255, 255], [40, 60, 160]]);
+    }
+
+    #[test]
+    fn test_rejects_bad_layers() {
+        assert!(matches!(
+            StateMachineKernel::new(3, |c, _| c, vec![Array2D::new(4, 4)]),
+            Err(Error::InvalidKernelWidth(4))
+        ));
+    }
+}
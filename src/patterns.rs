@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+
+use crate::array2d::Array2D;
+
+/// Alternating live and dead cells, with (0, 0) live
+pub fn checkerboard(width: usize, height: usize) -> Array2D<bool> {
+    Array2D::from_fn(width, height, |x, y| (x + y) % 2 == 0)
+}
+
+/// A single live cell at `pos`
+pub fn single_cell(width: usize, height: usize, pos: (usize, usize)) -> Array2D<bool> {
+    Array2D::from_fn(width, height, |x, y| (x, y) == pos)
+}
+
+/// A full-width line across the middle row
+pub fn horizontal_line(width: usize, height: usize) -> Array2D<bool> {
+    Array2D::from_fn(width, height, |_, y| y == height / 2)
+}
+
+/// A full-height line down the middle column
+pub fn vertical_line(width: usize, height: usize) -> Array2D<bool> {
+    Array2D::from_fn(width, height, |x, _| x == width / 2)
+}
+
+/// A line from (0, 0) with slope 1, stopping at the edge of the grid
+pub fn diagonal(width: usize, height: usize) -> Array2D<bool> {
+    Array2D::from_fn(width, height, |x, y| x == y)
+}
+
+/// Value noise thresholded at one half, giving blobs roughly `scale` cells across. Two octaves are
+/// summed, so that blob edges are ragged rather than smooth.
+pub fn noise(width: usize, height: usize, seed: u64, scale: u32) -> Array2D<bool> {
+    let scale = scale.max(1) as f32;
+    Array2D::from_fn(width, height, |x, y| {
+        let (x, y) = (x as f32 / scale, y as f32 / scale);
+        let value = (2. * value_noise(seed, x, y) + value_noise(seed ^ 1, 2. * x, 2. * y)) / 3.;
+        value > 0.5
+    })
+}
+
+/// Smoothly interpolated random values in 0..1, on a unit lattice
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (ix, iy) = (x0 as i64, y0 as i64);
+
+    let corner = |dx, dy| lattice_value(seed, ix + dx, iy + dy);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+/// Random value in 0..1 for a lattice point, from a splitmix64 hash of its coordinates
+fn lattice_value(seed: u64, x: i64, y: i64) -> f32 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Deterministic patterns available for seeding the grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InitialPattern {
+    Checkerboard,
+    /// One cell at the center
+    SingleCell,
+    HorizontalLine,
+    VerticalLine,
+    Diagonal,
+    /// Clumps of live cells about `scale` cells across; see `noise`
+    Noise {
+        seed: u64,
+        scale: u32,
+    },
+}
+
+impl InitialPattern {
+    /// One of each kind of pattern. Parameterized patterns have placeholder parameters.
+    pub const ALL: [Self; 6] = [
+        Self::Checkerboard,
+        Self::SingleCell,
+        Self::HorizontalLine,
+        Self::VerticalLine,
+        Self::Diagonal,
+        Self::Noise { seed: 0, scale: 16 },
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Checkerboard => "Checkerboard",
+            Self::SingleCell => "Single cell",
+            Self::HorizontalLine => "Horizontal line",
+            Self::VerticalLine => "Vertical line",
+            Self::Diagonal => "Diagonal",
+            Self::Noise { .. } => "Noise",
+        }
+    }
+
+    pub fn generate(self, width: usize, height: usize) -> Array2D<bool> {
+        match self {
+            Self::Checkerboard => checkerboard(width, height),
+            Self::SingleCell => single_cell(width, height, (width / 2, height / 2)),
+            Self::HorizontalLine => horizontal_line(width, height),
+            Self::VerticalLine => vertical_line(width, height),
+            Self::Diagonal => diagonal(width, height),
+            Self::Noise { seed, scale } => noise(width, height, seed, scale),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kernels::Life, sim::Dense};
+
+    fn live(arr: &Array2D<bool>) -> Vec<(usize, usize)> {
+        (0..arr.height())
+            .flat_map(|y| (0..arr.width()).map(move |x| (x, y)))
+            .filter(|&xy| arr[xy])
+            .collect()
+    }
+
+    #[test]
+    fn test_checkerboard() {
+        assert_eq!(
+            checkerboard(3, 2).data(),
+            [true, false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(live(&single_cell(3, 3, (2, 1))), [(2, 1)]);
+        assert_eq!(live(&horizontal_line(3, 3)), [(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(live(&vertical_line(3, 2)), [(1, 0), (1, 1)]);
+        assert_eq!(live(&diagonal(4, 2)), [(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn test_noise_is_clumpy() {
+        let (w, h) = (128, 96);
+        let pattern = noise(w, h, 7, 16);
+        assert_eq!(pattern, noise(w, h, 7, 16));
+        assert_ne!(pattern, noise(w, h, 8, 16));
+
+        let density = live(&pattern).len() as f32 / (w * h) as f32;
+        assert!((0.2..0.8).contains(&density), "density {density}");
+
+        // Unlike uniform noise, horizontal neighbors mostly agree
+        let agree = (0..h)
+            .flat_map(|y| (1..w).map(move |x| (x, y)))
+            .filter(|&(x, y)| pattern[(x, y)] == pattern[(x - 1, y)])
+            .count();
+        let agreement = agree as f32 / ((w - 1) * h) as f32;
+        assert!(agreement > 0.85, "agreement {agreement}");
+
+        assert_eq!(noise(4, 4, 7, 0), noise(4, 4, 7, 1));
+    }
+
+    #[test]
+    fn test_single_cell_dies_under_life() {
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        for (x, y) in live(&single_cell(8, 8, (3, 4))) {
+            sim.set_pixel((x, y), true);
+        }
+        sim.step();
+        assert_eq!(sim.live_cells().count(), 0);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{
+    array2d::Array2D,
+    cell::{Cell, StateChars},
+};
+
+/// Parse a plaintext (`.cells`) pattern, with states spelled by the given tokens.
+/// Lines starting with `!` are comments; short lines are padded with dead cells.
+/// Returns None if a line contains an unknown token.
+pub fn parse_cells_with<C: Cell>(input: &str, chars: &StateChars) -> Option<Array2D<C>> {
+    let rows: Vec<Vec<C>> = input
+        .lines()
+        .filter(|line| !line.starts_with('!'))
+        .map(|line| {
+            let mut row = vec![];
+            let mut rest = line.trim_end();
+            while !rest.is_empty() {
+                let (state, len) = chars.parse_prefix(rest)?;
+                if state >= C::STATES {
+                    return None;
+                }
+                row.push(C::from_state(state));
+                rest = &rest[len..];
+            }
+            Some(row)
+        })
+        .collect::<Option<_>>()?;
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    let mut out = Array2D::new(width, rows.len());
+    for (y, row) in rows.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate() {
+            out[(x, y)] = cell;
+        }
+    }
+
+    Some(out)
+}
+
+/// Encode a pattern in plaintext (`.cells`) format
+pub fn write_cells_with<C: Cell>(pattern: &Array2D<C>, chars: &StateChars) -> String {
+    let mut out = String::new();
+    for row in pattern.data().chunks_exact(pattern.width()) {
+        for cell in row {
+            out.push_str(chars.token(cell.state()));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Parse a two-state plaintext pattern
+pub fn parse_cells(input: &str) -> Option<Array2D<bool>> {
+    parse_cells_with(input, &StateChars::plaintext())
+}
+
+/// Encode a two-state pattern in plaintext format
+pub fn write_cells(pattern: &Array2D<bool>) -> String {
+    write_cells_with(pattern, &StateChars::plaintext())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "!Name: Glider\n.O\n..O\nOOO\n";
+        let glider = parse_cells(text).unwrap();
+        assert_eq!((glider.width(), glider.height()), (3, 3));
+        assert!(glider[(1, 0)] && !glider[(2, 0)]);
+        assert_eq!(write_cells(&glider), ".O.\n..O\nOOO\n");
+        assert_eq!(parse_cells(&write_cells(&glider)).unwrap(), glider);
+    }
+
+    #[test]
+    fn test_unknown_token() {
+        assert!(parse_cells(".X.\n").is_none());
+    }
+}
@@ -0,0 +1,177 @@
+//! Named kernel factories, which the app offers as its rules. Embedders (or other binaries)
+//! register their own kernels here instead of editing the app.
+
+use crate::{
+    draw::Lattice,
+    kernels::{
+        basic_mnca, billiard_ball_kernel, hex_life_kernel, larger_than_life_layered_kernel,
+        life_layered_kernel, sand_kernel, tunable_mnca, LayeredKernel, Life,
+    },
+    sim::Kernel,
+};
+
+/// Builds a fresh kernel each time a simulation is created
+pub type KernelFactory = Box<dyn Fn() -> Box<dyn Kernel>>;
+
+/// Builds a fresh layered kernel, whose layers, counts and parameters the UI can inspect
+pub type LayeredFactory = Box<dyn Fn() -> LayeredKernel>;
+
+enum Factory {
+    Layered(LayeredFactory),
+    Opaque(KernelFactory),
+}
+
+/// A kernel made by a registered factory
+pub enum BuiltKernel {
+    Layered(LayeredKernel),
+    /// Any other kernel; only the cells can be shown
+    Opaque(Box<dyn Kernel>),
+}
+
+pub struct RegisteredKernel {
+    name: String,
+    factory: Factory,
+    /// How the kernel's cells are arranged on screen
+    pub lattice: Lattice,
+}
+
+impl RegisteredKernel {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn build(&self) -> BuiltKernel {
+        match &self.factory {
+            Factory::Layered(factory) => BuiltKernel::Layered(factory()),
+            Factory::Opaque(factory) => BuiltKernel::Opaque(factory()),
+        }
+    }
+}
+
+/// Kernels by name, in the order they were registered
+#[derive(Default)]
+pub struct KernelRegistry {
+    entries: Vec<RegisteredKernel>,
+}
+
+impl KernelRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding the kernels built into the crate, `basic_mnca` first
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register_layered("Basic MNCA", Box::new(basic_mnca));
+        registry.register_layered("Tunable", Box::new(tunable_mnca));
+        registry
+            .register_layered("Hex Life", Box::new(hex_life_kernel))
+            .lattice = Lattice::Hex;
+        registry.register_layered("Life (layered)", Box::new(life_layered_kernel));
+        registry.register_layered(
+            "Larger than Life",
+            Box::new(larger_than_life_layered_kernel),
+        );
+        registry.register_kernel("Life", Box::new(|| Box::new(Life)));
+        registry.register_kernel(
+            "Billiard balls",
+            Box::new(|| Box::new(billiard_ball_kernel())),
+        );
+        registry.register_kernel("Sand", Box::new(|| Box::new(sand_kernel())));
+        registry
+    }
+
+    /// Add a kernel under `name`, replacing (in place) any kernel already registered under it.
+    /// The result can be used to set the lattice, which defaults to square.
+    pub fn register_kernel(&mut self, name: &str, factory: KernelFactory) -> &mut RegisteredKernel {
+        self.insert(name, Factory::Opaque(factory))
+    }
+
+    /// Like `register_kernel`, but for layered kernels, so that their layers, counts and
+    /// parameters are available too
+    pub fn register_layered(
+        &mut self,
+        name: &str,
+        factory: LayeredFactory,
+    ) -> &mut RegisteredKernel {
+        self.insert(name, Factory::Layered(factory))
+    }
+
+    fn insert(&mut self, name: &str, factory: Factory) -> &mut RegisteredKernel {
+        let entry = RegisteredKernel {
+            name: name.to_string(),
+            factory,
+            lattice: Lattice::Square,
+        };
+        let i = match self.entries.iter().position(|e| e.name == name) {
+            Some(i) => {
+                self.entries[i] = entry;
+                i
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[i]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredKernel> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Registered names, in registration order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.name.as_str())
+    }
+
+    /// The entry registered after `name`, wrapping around to the first
+    pub fn next_after(&self, name: &str) -> Option<&RegisteredKernel> {
+        let i = self.entries.iter().position(|e| e.name == name)?;
+        self.entries.get((i + 1) % self.entries.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins() {
+        let registry = KernelRegistry::with_builtins();
+        assert_eq!(registry.names().next(), Some("Basic MNCA"));
+        assert_eq!(registry.get("Hex Life").unwrap().lattice, Lattice::Hex);
+        assert!(matches!(
+            registry.get("Life").unwrap().build(),
+            BuiltKernel::Opaque(kernel) if kernel.order() == 1
+        ));
+        assert!(matches!(
+            registry.get("Tunable").unwrap().build(),
+            BuiltKernel::Layered(kernel) if kernel.params().is_some()
+        ));
+        assert!(registry.get("Brian's Brain").is_none());
+    }
+
+    #[test]
+    fn test_register_replaces_in_place() {
+        let mut registry = KernelRegistry::new();
+        registry.register_kernel("A", Box::new(|| Box::new(Life)));
+        registry.register_layered("B", Box::new(life_layered_kernel));
+        registry.register_layered("A", Box::new(basic_mnca)).lattice = Lattice::Hex;
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["A", "B"]);
+        let a = registry.get("A").unwrap();
+        assert_eq!(a.lattice, Lattice::Hex);
+        assert!(matches!(a.build(), BuiltKernel::Layered(_)));
+        assert_eq!(registry.next_after("A").unwrap().name(), "B");
+        assert_eq!(registry.next_after("B").unwrap().name(), "A");
+    }
+}
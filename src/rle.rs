@@ -0,0 +1,318 @@
+use std::fmt;
+
+use crate::{
+    array2d::Array2D,
+    cell::{Cell, StateChars},
+};
+
+/// Errors encountered while parsing RLE
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RleError {
+    /// No `x = .., y = ..` header line was found
+    MissingHeader,
+    /// The header line could not be parsed
+    BadHeader(String),
+    /// A character which is not a state token, `$`, `!`, or a run count
+    UnknownToken(char),
+    /// A token refers to a state the cell type can't represent
+    UnsupportedState(u8),
+    /// The pattern body doesn't fit within the dimensions given in the header
+    OutOfBounds,
+}
+
+impl fmt::Display for RleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RleError::MissingHeader => write!(f, "Missing RLE header line"),
+            RleError::BadHeader(line) => write!(f, "Invalid RLE header: {line}"),
+            RleError::UnknownToken(c) => write!(f, "Unknown RLE token {c:?}"),
+            RleError::UnsupportedState(s) => write!(f, "Unsupported cell state {s}"),
+            RleError::OutOfBounds => write!(f, "RLE pattern exceeds its declared size"),
+        }
+    }
+}
+
+impl std::error::Error for RleError {}
+
+/// A decoded pattern, with the metadata that places it in a larger scene
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RlePattern<C: Cell> {
+    pub cells: Array2D<C>,
+    /// Where the top-left of `cells` belongs, from a `#CXRLE Pos=x,y` line
+    pub pos: Option<(i64, i64)>,
+}
+
+/// Parse an RLE pattern, with states spelled by the given tokens.
+/// Row 0 of the result is the first (top) row of the pattern.
+pub fn parse_rle_with<C: Cell>(input: &str, chars: &StateChars) -> Result<Array2D<C>, RleError> {
+    Ok(parse_rle_pattern_with(input, chars)?.cells)
+}
+
+/// Parse an RLE pattern along with its position, if given. See `parse_rle_with`.
+pub fn parse_rle_pattern_with<C: Cell>(
+    input: &str,
+    chars: &StateChars,
+) -> Result<RlePattern<C>, RleError> {
+    let mut pos = None;
+    for line in input.lines().map(str::trim) {
+        if let Some(fields) = line.strip_prefix("#CXRLE") {
+            pos = parse_cxrle_pos(line, fields)?.or(pos);
+        }
+    }
+
+    let mut lines = input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    let header = lines.next().ok_or(RleError::MissingHeader)?;
+    let (width, height) = parse_header(header)?;
+
+    let mut out = Array2D::new(width.max(1), height);
+    let (mut x, mut y) = (0_usize, 0_usize);
+
+    let body: String = lines.collect();
+    let mut rest = body.as_str();
+    while !rest.is_empty() {
+        // Run count prefix
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let count = match digits {
+            0 => 1,
+            n => rest[..n].parse().map_err(|_| RleError::OutOfBounds)?,
+        };
+        rest = &rest[digits..];
+
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        match c {
+            '!' => break,
+            '$' => {
+                y = y.checked_add(count).ok_or(RleError::OutOfBounds)?;
+                x = 0;
+                rest = &rest[1..];
+            }
+            _ => {
+                let (state, len) = chars.parse_prefix(rest).ok_or(RleError::UnknownToken(c))?;
+                if state >= C::STATES {
+                    return Err(RleError::UnsupportedState(state));
+                }
+                let past_end = x.checked_add(count).is_none_or(|end| end > width);
+                if past_end || (count > 0 && y >= height) {
+                    return Err(RleError::OutOfBounds);
+                }
+                if state != 0 {
+                    for i in x..x + count {
+                        out[(i, y)] = C::from_state(state);
+                    }
+                }
+                x += count;
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    Ok(RlePattern { cells: out, pos })
+}
+
+/// The `Pos=x,y` field of a `#CXRLE` line, ignoring other fields such as `Gen`
+fn parse_cxrle_pos(line: &str, fields: &str) -> Result<Option<(i64, i64)>, RleError> {
+    let bad = || RleError::BadHeader(line.to_string());
+    let Some(value) = fields
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("Pos="))
+    else {
+        return Ok(None);
+    };
+    let (x, y) = value.split_once(',').ok_or_else(bad)?;
+    Ok(Some((
+        x.trim().parse().map_err(|_| bad())?,
+        y.trim().parse().map_err(|_| bad())?,
+    )))
+}
+
+/// Parse the `x = .., y = ..` header, ignoring any other fields such as the rule
+fn parse_header(line: &str) -> Result<(usize, usize), RleError> {
+    let bad = || RleError::BadHeader(line.to_string());
+
+    let mut width = None;
+    let mut height = None;
+    for field in line.split(',') {
+        let (key, value) = field.split_once('=').ok_or_else(bad)?;
+        match key.trim() {
+            "x" => width = Some(value.trim().parse().map_err(|_| bad())?),
+            "y" => height = Some(value.trim().parse().map_err(|_| bad())?),
+            _ => (),
+        }
+    }
+
+    Ok((width.ok_or_else(bad)?, height.ok_or_else(bad)?))
+}
+
+/// Encode a pattern as RLE, with states spelled by the given tokens.
+/// Row 0 of the pattern is written first (top).
+pub fn write_rle_with<C: Cell>(pattern: &Array2D<C>, chars: &StateChars) -> String {
+    const MAX_LINE: usize = 70;
+
+    let mut out = format!("x = {}, y = {}\n", pattern.width(), pattern.height());
+
+    let mut tokens = vec![];
+    let mut pending_rows = 0;
+    for row in pattern.data().chunks_exact(pattern.width()) {
+        // Trailing dead cells are implied
+        let len = row
+            .iter()
+            .rposition(|c| c.state() != 0)
+            .map_or(0, |i| i + 1);
+        if len == 0 {
+            pending_rows += 1;
+            continue;
+        }
+
+        if !tokens.is_empty() || pending_rows > 0 {
+            // Count includes the end of the previous row
+            let rows = pending_rows + usize::from(!tokens.is_empty());
+            tokens.push(run(rows, "$"));
+        }
+        pending_rows = 0;
+
+        let mut cells = row[..len].iter().peekable();
+        while let Some(&cell) = cells.next() {
+            let mut count = 1;
+            while cells.next_if(|&&c| c == cell).is_some() {
+                count += 1;
+            }
+            tokens.push(run(count, chars.token(cell.state())));
+        }
+    }
+    tokens.push("!".to_string());
+
+    let mut line_len = 0;
+    for token in tokens {
+        if line_len + token.len() > MAX_LINE {
+            out.push('\n');
+            line_len = 0;
+        }
+        line_len += token.len();
+        out.push_str(&token);
+    }
+    out.push('\n');
+
+    out
+}
+
+fn run(count: usize, token: &str) -> String {
+    match count {
+        1 => token.to_string(),
+        n => format!("{n}{token}"),
+    }
+}
+
+/// Parse a two-state RLE pattern
+pub fn parse_rle(input: &str) -> Result<Array2D<bool>, RleError> {
+    parse_rle_with(input, &StateChars::rle_two_state())
+}
+
+/// Parse a two-state RLE pattern along with its position, if given
+pub fn parse_rle_pattern(input: &str) -> Result<RlePattern<bool>, RleError> {
+    parse_rle_pattern_with(input, &StateChars::rle_two_state())
+}
+
+/// Encode a two-state pattern as RLE
+pub fn write_rle(pattern: &Array2D<bool>) -> String {
+    write_rle_with(pattern, &StateChars::rle_two_state())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLIDER: &str = "#N Glider\nx = 3, y = 3, rule = B3/S23\nbob$2bo$3o!\n";
+
+    fn live(arr: &Array2D<bool>) -> Vec<(usize, usize)> {
+        let mut cells = vec![];
+        for y in 0..arr.height() {
+            for x in 0..arr.width() {
+                if arr[(x, y)] {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn test_parse_glider() {
+        let glider = parse_rle(GLIDER).unwrap();
+        assert_eq!((glider.width(), glider.height()), (3, 3));
+        assert_eq!(live(&glider), [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let glider = parse_rle(GLIDER).unwrap();
+        assert_eq!(write_rle(&glider), "x = 3, y = 3\nbo$2bo$3o!\n");
+        assert_eq!(parse_rle(&write_rle(&glider)).unwrap(), glider);
+    }
+
+    #[test]
+    fn test_blank_rows() {
+        let mut arr = Array2D::new(4, 5);
+        arr[(0, 0)] = true;
+        arr[(3, 3)] = true;
+        let rle = write_rle(&arr);
+        assert_eq!(rle, "x = 4, y = 5\no3$3bo!\n");
+        assert_eq!(parse_rle(&rle).unwrap(), arr);
+    }
+
+    #[test]
+    fn test_multistate_with_bool() {
+        let chars = StateChars::rle_multistate(3);
+        let arr: Array2D<bool> = parse_rle_with("x = 2, y = 1\n.A!", &chars).unwrap();
+        assert_eq!(live(&arr), [(1, 0)]);
+
+        let err = parse_rle_with::<bool>("x = 2, y = 1\nAB!", &chars).unwrap_err();
+        assert_eq!(err, RleError::UnsupportedState(2));
+    }
+
+    #[test]
+    fn test_cxrle_pos() {
+        let input = format!("#CXRLE Pos=-3,12 Gen=40\n{GLIDER}");
+        let pattern = parse_rle_pattern(&input).unwrap();
+        assert_eq!(pattern.pos, Some((-3, 12)));
+        assert_eq!(pattern.cells, parse_rle(GLIDER).unwrap());
+
+        assert_eq!(parse_rle_pattern(GLIDER).unwrap().pos, None);
+        assert_eq!(
+            parse_rle_pattern("#CXRLE Gen=40\nx = 1, y = 1\no!")
+                .unwrap()
+                .pos,
+            None
+        );
+        assert!(matches!(
+            parse_rle_pattern("#CXRLE Pos=3\nx = 1, y = 1\no!"),
+            Err(RleError::BadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_rle("#C nothing"), Err(RleError::MissingHeader));
+        assert_eq!(parse_rle("x = 2, y = 1\n3o!"), Err(RleError::OutOfBounds));
+        // Run counts that would overflow the position
+        let max = usize::MAX;
+        let overflow = format!("x = 2, y = 1\no{max}o!");
+        assert_eq!(parse_rle(&overflow), Err(RleError::OutOfBounds));
+        let overflow = format!("x = 2, y = 1\n2${max}$o!");
+        assert_eq!(parse_rle(&overflow), Err(RleError::OutOfBounds));
+        assert_eq!(
+            parse_rle("x = 2, y = 1\nz!"),
+            Err(RleError::UnknownToken('z'))
+        );
+    }
+}
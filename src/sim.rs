@@ -1,9 +1,54 @@
-use crate::array2d::Array2D;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{analysis::IsotropyReport, array2d::Array2D, error::Error, rle::RlePattern};
 
 /// Block data, whose size is known by the Kernel
-pub type Block = Array2D<bool>;
+pub use crate::block::Block;
+
+/// Largest supported kernel order, i.e. 128x128 blocks.
+///
+/// Memory and time grow by 4x per order: a block packs 4^order cells into 4^order / 8 bytes, a
+/// `LayeredKernel` step builds a 4 * 4^order byte window per block, and its counting loop touches
+/// (2^order + 1)^2 mask cells for each of the 4^order outputs.
+pub const MAX_ORDER: usize = 7;
+
+/// Orders above this are allowed, but slow enough to warn about
+const WARN_ORDER: usize = 5;
+
+/// A kernel's order exceeds `MAX_ORDER`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnsupportedOrder(pub usize);
+
+impl std::fmt::Display for UnsupportedOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Kernel order {} (2^{} wide blocks) exceeds the maximum of {}",
+            self.0, self.0, MAX_ORDER
+        )
+    }
+}
 
-pub trait Kernel {
+impl std::error::Error for UnsupportedOrder {}
+
+/// Check that a kernel order is practical to simulate, warning if it is merely slow
+pub fn check_order(order: usize) -> Result<(), UnsupportedOrder> {
+    if order > MAX_ORDER {
+        return Err(UnsupportedOrder(order));
+    }
+    if order > WARN_ORDER {
+        log::warn!(
+            "Kernel order {} uses {} bytes per block, stepping will be slow",
+            order,
+            (1 << (2 * order)) / 8
+        );
+    }
+    Ok(())
+}
+
+/// Kernels are shared immutably so that stepping may happen on other threads; any internal
+/// caching must use interior mutability.
+pub trait Kernel: Send + Sync {
     /// Power law size of the basic block. E.g. each block has a width of 2^n, where n = self.order()
     /// For HashLife/Conway life (radius 1), this would be 1, since input would be 4 2x2 blocks and output would be 1 2x2 block
     /// For an MNCA with radius 4, this would be 3, since the input would be 4 8x8 blocks and output would be 1 8x8 block
@@ -11,7 +56,51 @@ pub trait Kernel {
 
     /// Given a novel combination of 4 blocks, produce an output block advanced by one time step
     /// (each entry in the input and output blocks are either 0 or 1 indicating dead or live states respectively)
-    fn exec(&mut self, blocks: [Block; 4]) -> (Block, KernelResult);
+    ///
+    /// The blocks are `[top left, top right, bottom left, bottom right]`, i.e. block (i, j) of
+    /// the 2x2 arrangement is `blocks[i + 2 * j]`, row-major like the cells within each block.
+    /// Together they form a 2w x 2w window, and the output is the w x w square at its center.
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult);
+
+    /// Whether four empty blocks always produce an empty block, i.e. nothing is born from
+    /// nothing. Simulations may then skip empty regions without calling `step_block`, so this must
+    /// only be true if the guarantee holds for every input.
+    fn zero_stable(&self) -> bool {
+        false
+    }
+
+    /// Compute and remember the results for these inputs ahead of time, if the kernel memoizes
+    /// anything. Does nothing by default.
+    fn prewarm(&self, samples: &[[Block; 4]]) {
+        let _ = samples;
+    }
+
+    /// Check that this kernel treats all directions alike; see `analysis::check_isotropy`
+    fn check_isotropy(&self, samples: usize, seed: u64) -> IsotropyReport
+    where
+        Self: Sized,
+    {
+        crate::analysis::check_isotropy(self, samples, seed)
+    }
+}
+
+/// Shared kernels, so that e.g. a `KernelCache` can be inspected while a simulation owns it
+impl<K: Kernel + ?Sized> Kernel for std::sync::Arc<K> {
+    fn order(&self) -> usize {
+        (**self).order()
+    }
+
+    fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
+        (**self).step_block(blocks)
+    }
+
+    fn zero_stable(&self) -> bool {
+        (**self).zero_stable()
+    }
+
+    fn prewarm(&self, samples: &[[Block; 4]]) {
+        (**self).prewarm(samples)
+    }
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -22,48 +111,463 @@ pub enum KernelResult {
     Approximate,
 }
 
+/// Common interface over the simulation backends
+pub trait Simulation {
+    /// Advance the simulation by one time step
+    fn step(&mut self);
+
+    /// Returns (width, height) of the visible area in pixels
+    fn pixel_dims(&self) -> (usize, usize);
+
+    /// Order of the kernel being simulated; blocks are 2^order pixels wide
+    fn order(&self) -> usize;
+
+    /// May panic if `index` is outside `pixel_dims`
+    fn get_pixel(&self, index: (usize, usize)) -> bool;
+
+    /// May panic if `index` is outside `pixel_dims`
+    fn set_pixel(&mut self, index: (usize, usize), val: bool);
+
+    /// The pixel at `index`, or None if it is outside `pixel_dims`
+    fn try_get_pixel(&self, index: (usize, usize)) -> Option<bool> {
+        check_pixel(index, self.pixel_dims()).ok()?;
+        Some(self.get_pixel(index))
+    }
+
+    /// Set the pixel at `index`, failing if it is outside `pixel_dims`
+    fn try_set_pixel(&mut self, index: (usize, usize), val: bool) -> Result<(), Error> {
+        check_pixel(index, self.pixel_dims())?;
+        self.set_pixel(index, val);
+        Ok(())
+    }
+
+    /// Kill every cell
+    fn clear(&mut self);
+
+    /// Which of the two alternating block phases the grid is in. With zero borders (phase A),
+    /// blocks are aligned to the pixel grid; otherwise (phase B) they are offset by half a block.
+    /// Each step flips it.
+    ///
+    /// Precisely, with blocks w = 2^order pixels wide, block i along either axis covers pixels
+    /// i * w - o to (i + 1) * w - o, exclusive, where o is 0 in phase A and w / 2 in phase B. A
+    /// step passes the kernel each 2x2 group of blocks and writes its output over the w x w
+    /// square at the center of the group, which is a block of the other phase. This is the
+    /// Margolus neighborhood: the blocks of the two phases are the two partitions, and two steps
+    /// return to the same lattice. Kernels which read only the center square, like
+    /// `MargolusKernel`, therefore take two steps per generation. Kernels which read the whole
+    /// group, like `Life`, advance a generation every step, and the phase only decides where
+    /// block boundaries fall.
+    fn zero_borders(&self) -> bool;
+
+    /// Switch to the other block phase without stepping, keeping every visible cell in place
+    fn flip_phase(&mut self);
+
+    /// Whether opposite edges of `pixel_dims` are joined, making the grid a torus
+    fn wraps(&self) -> bool {
+        false
+    }
+
+    /// Visit every visible pixel in row-major order, allowing it to be changed
+    fn for_each_pixel_mut(&mut self, f: &mut dyn FnMut((usize, usize), &mut bool)) {
+        let (w, h) = self.pixel_dims();
+        for y in 0..h {
+            for x in 0..w {
+                let old = self.get_pixel((x, y));
+                let mut val = old;
+                f((x, y), &mut val);
+                if val != old {
+                    self.set_pixel((x, y), val);
+                }
+            }
+        }
+    }
+
+    /// Set the live cells of a pattern, placed at its own position (if it has one) relative to
+    /// `anchor`, which is where a pattern with position (0, 0) has its top-left. Dead cells of the
+    /// pattern leave the grid as it was. Returns the number of live cells which fell outside
+    /// `pixel_dims` and were dropped.
+    fn load_pattern(&mut self, pattern: &RlePattern<bool>, anchor: (i64, i64)) -> usize {
+        let (ox, oy) = pattern.pos.unwrap_or((0, 0));
+        let (w, h) = self.pixel_dims();
+        let cells = &pattern.cells;
+        let mut dropped = 0;
+        for y in 0..cells.height() {
+            for x in 0..cells.width() {
+                if !cells[(x, y)] {
+                    continue;
+                }
+                let px = anchor.0 + ox + x as i64;
+                let py = anchor.1 + oy + y as i64;
+                if (0..w as i64).contains(&px) && (0..h as i64).contains(&py) {
+                    self.set_pixel((px as usize, py as usize), true);
+                } else {
+                    dropped += 1;
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Iterate the coordinates of all live pixels
+    fn live_cells(&self) -> Box<dyn Iterator<Item = (i32, i32)> + '_>;
+
+    /// Number of live pixels, counting the same cells as `live_cells`
+    fn population(&self) -> usize {
+        self.live_cells().count()
+    }
+
+    /// Copy the `size` pixels starting at `min` into `out`, which must be exactly `size`. Fails
+    /// if the region extends past `pixel_dims`.
+    fn copy_region(
+        &self,
+        min: (usize, usize),
+        size: (usize, usize),
+        out: &mut Array2D<bool>,
+    ) -> Result<(), Error> {
+        check_region(min, size, self.pixel_dims(), out)?;
+        for y in 0..size.1 {
+            for x in 0..size.0 {
+                out[(x, y)] = self.get_pixel((min.0 + x, min.1 + y));
+            }
+        }
+        Ok(())
+    }
+
+    /// Which visible pixels were produced by a `KernelResult::Approximate` block in the last
+    /// step, indexed like `get_pixel`. None if the backend doesn't track it.
+    fn approximate_cells(&self) -> Option<Array2D<bool>> {
+        None
+    }
+
+    /// Forget what earlier steps imply about later ones, so that the next steps evaluate every
+    /// block. Call this when the kernel's rule changes underneath the simulation, e.g. a
+    /// `Param` being adjusted. Does nothing for backends which remember nothing.
+    fn invalidate(&mut self) {}
+
+    /// Copy the visible area out into a grid indexed like `get_pixel`
+    fn snapshot(&self) -> Array2D<bool> {
+        let (w, h) = self.pixel_dims();
+        let mut out = Array2D::new(w, h);
+        self.copy_region((0, 0), (w, h), &mut out)
+            .expect("The visible area is in bounds");
+        out
+    }
+
+    /// Step the given number of generations, then snapshot the result
+    fn run_and_capture(&mut self, generations: usize) -> Array2D<bool> {
+        for _ in 0..generations {
+            self.step();
+        }
+        self.snapshot()
+    }
+
+    /// Like `run_and_capture`, but stopping early once `cancel` is set, which is checked before
+    /// each generation. Returns the snapshot and the number of generations actually run.
+    fn run_and_capture_until(
+        &mut self,
+        generations: usize,
+        cancel: &AtomicBool,
+    ) -> (Array2D<bool>, usize) {
+        let mut run = 0;
+        while run < generations && !cancel.load(Ordering::Relaxed) {
+            self.step();
+            run += 1;
+        }
+        (self.snapshot(), run)
+    }
+
+    /// Step repeatedly, yielding a snapshot after each step (the current state is not included).
+    /// The iterator is endless and borrows the simulation mutably; use `take` to bound it.
+    fn frames(&mut self) -> Box<dyn Iterator<Item = Array2D<bool>> + '_> {
+        Box::new(std::iter::from_fn(move || {
+            self.step();
+            Some(self.snapshot())
+        }))
+    }
+}
+
+/// How `Dense` treats the cells past the edges of its visible area
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoundaryMode {
+    /// Always dead
+    #[default]
+    Zero,
+    /// Opposite edges are joined, making the grid a torus
+    Toroidal,
+    /// The grid is reflected across each edge, so a cell just outside reads as the cell just
+    /// inside
+    Mirror,
+}
+
+/// The cells and phase of a `Dense` grid, without its kernel; see `Dense::save_state`
+#[derive(Clone, PartialEq, Eq)]
+pub struct DenseState {
+    blocks: Array2D<Block>,
+    zero_borders: bool,
+}
+
 pub struct Dense {
     back: Array2D<Block>,
     front: Array2D<Block>,
     kernel: Box<dyn Kernel>,
     zero_borders: bool,
+    /// Shared all-dead block used outside the grid
+    zero_block: Block,
+    /// Whether each block of `front` differs from the block at the same index two steps earlier,
+    /// when the grid was last in the same phase
+    dirty: Array2D<bool>,
+    /// Number of upcoming steps which must evaluate every block, because the grid was edited
+    /// and `back` can no longer be trusted to hold the output for an unchanged input
+    recompute: u8,
+    skip_unchanged: bool,
+    boundary: BoundaryMode,
+    /// Whether each block of `front` came from a `KernelResult::Approximate`
+    approximate: Array2D<bool>,
+    /// Same, for `back`
+    back_approximate: Array2D<bool>,
+    /// Number of threads `step` divides the blocks between
+    threads: usize,
+    /// Steps taken since the grid was created
+    generation: u64,
+}
+
+/// The result of stepping one block, to be written into `back`
+struct StepOutput {
+    /// None if the output is all dead
+    block: Option<Block>,
+    approximate: bool,
+    /// Whether it differs from what `back` holds
+    dirty: bool,
 }
 
 impl Dense {
+    /// Create a grid of width x height blocks.
+    /// Panics if the kernel's order is unsupported; see `try_new`.
     pub fn new(kernel: Box<dyn Kernel>, width: usize, height: usize) -> Self {
+        Self::try_new(kernel, width, height).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a grid covering at least px_width x px_height pixels.
+    /// Dimensions are rounded up to a whole number of blocks, so `pixel_dims` may be larger than
+    /// requested by up to one block width less one.
+    pub fn with_pixel_dims(kernel: Box<dyn Kernel>, px_width: usize, px_height: usize) -> Self {
+        let (width, height) = blocks_for_pixels(&*kernel, px_width, px_height);
+        Self::new(kernel, width, height)
+    }
+
+    /// Create a grid of width x height blocks, failing if the kernel's order exceeds `MAX_ORDER`
+    pub fn try_new(kernel: Box<dyn Kernel>, width: usize, height: usize) -> Result<Self, Error> {
+        Self::try_with_boundary(kernel, width, height, BoundaryMode::Zero)
+    }
+
+    /// Like `new`, with the given boundary instead of dead cells
+    pub fn with_boundary(
+        kernel: Box<dyn Kernel>,
+        width: usize,
+        height: usize,
+        boundary: BoundaryMode,
+    ) -> Self {
+        Self::try_with_boundary(kernel, width, height, boundary).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like `try_new`, with the given boundary instead of dead cells
+    pub fn try_with_boundary(
+        kernel: Box<dyn Kernel>,
+        width: usize,
+        height: usize,
+        boundary: BoundaryMode,
+    ) -> Result<Self, Error> {
+        check_order(kernel.order())?;
+
         // To account for difference in size between frames, we add 1 to width and height
-        let zero_block = Array2D::new(1 << kernel.order(), 1 << kernel.order());
-        let zeros = vec![zero_block; (width + 1) * (height + 1)];
+        let zero_block = Block::new(1 << kernel.order(), 1 << kernel.order());
+        let zeros = vec![zero_block.clone(); (width + 1) * (height + 1)];
+
+        // Empty space is what most of a grid starts out as
+        kernel.prewarm(&[[(); 4].map(|_| zero_block.clone())]);
 
-        Self {
+        Ok(Self {
             front: Array2D::from_array(width + 1, zeros.clone()),
             back: Array2D::from_array(width + 1, zeros),
             kernel,
             zero_borders: true,
-        }
+            zero_block,
+            dirty: Array2D::new(width + 1, height + 1),
+            recompute: 2,
+            skip_unchanged: true,
+            boundary,
+            approximate: Array2D::new(width + 1, height + 1),
+            back_approximate: Array2D::new(width + 1, height + 1),
+            threads: 1,
+            generation: 0,
+        })
     }
 
-    pub fn step(&mut self) {
-        for i in 0..self.front.width() as i32 {
-            for j in 0..self.front.height() as i32 {
-                let (x, y) = if self.zero_borders {
-                    (i - 1, j - 1)
-                } else {
-                    (i, j)
-                };
+    pub fn boundary(&self) -> BoundaryMode {
+        self.boundary
+    }
 
-                let in_blocks = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)];
+    pub fn set_boundary(&mut self, boundary: BoundaryMode) {
+        self.boundary = boundary;
+        self.recompute = 2;
+    }
+
+    /// Whether the grid is a torus; see `set_wrap`
+    pub fn wraps(&self) -> bool {
+        self.boundary == BoundaryMode::Toroidal
+    }
 
-                let in_blocks = in_blocks.map(|uv| get_block_zero_borders(&self.front, uv));
+    /// Join the opposite edges of the visible area, making the grid a torus, or otherwise
+    /// surround it with dead cells
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.set_boundary(match wrap {
+            true => BoundaryMode::Toroidal,
+            false => BoundaryMode::Zero,
+        });
+    }
 
-                let (out_block, _) = self.kernel.exec(in_blocks);
+    /// Evaluate every block for the next two steps, one in each phase. Skipping unchanged
+    /// blocks assumes the kernel is a fixed function of its inputs, which stops being true
+    /// when its parameters are adjusted.
+    pub fn invalidate(&mut self) {
+        self.recompute = 2;
+    }
+
+    /// Whether to skip blocks whose inputs are unchanged since the last step in the same phase.
+    /// On by default; the output is identical either way, so long as the kernel doesn't change
+    /// without a call to `invalidate`.
+    pub fn set_skip_unchanged(&mut self, skip: bool) {
+        self.skip_unchanged = skip;
+    }
 
-                self.back[(i as usize, j as usize)] = out_block;
+    /// Number of threads `step` uses; see `set_threads`
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Divide each step's blocks between this many threads (at least 1), each taking a band of
+    /// rows. The output is identical for any count. Defaults to 1, since spawning threads every
+    /// step costs more than it saves on small grids or cheap kernels.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn step(&mut self) {
+        // When a step is skipped, `back` still holds the output of two steps ago, which was
+        // computed in the same phase. If none of the inputs changed since then, it's still correct.
+        let full = !self.skip_unchanged || self.recompute > 0;
+        self.recompute = self.recompute.saturating_sub(1);
+        if self.boundary == BoundaryMode::Mirror && !self.zero_borders {
+            self.reflect_overhang();
+        }
+
+        // Every output depends only on `front` and the previous outputs, so the blocks are
+        // computed from a shared borrow and only written afterwards
+        let (width, height) = (self.front.width(), self.front.height());
+        let outputs: Vec<Option<StepOutput>> = {
+            let this = &*self;
+            let zero_stable = this.kernel.zero_stable();
+            let step_rows = move |rows: std::ops::Range<usize>| {
+                rows.flat_map(move |j| {
+                    (0..width).map(move |i| this.step_one((i, j), full, zero_stable))
+                })
+                .collect::<Vec<_>>()
+            };
+            let threads = self.threads.min(height);
+            if threads <= 1 {
+                step_rows(0..height)
+            } else {
+                let rows = height.div_ceil(threads);
+                std::thread::scope(|s| {
+                    let handles: Vec<_> = (0..height)
+                        .step_by(rows)
+                        .map(|start| s.spawn(move || step_rows(start..(start + rows).min(height))))
+                        .collect();
+                    handles
+                        .into_iter()
+                        .flat_map(|handle| handle.join().expect("Step worker panicked"))
+                        .collect()
+                })
+            }
+        };
+
+        // Outputs are in row-major order, like the grids' data
+        let mut dirty = Array2D::new(width, height);
+        let cells = self
+            .back
+            .data_mut()
+            .iter_mut()
+            .zip(self.back_approximate.data_mut())
+            .zip(dirty.data_mut());
+        for (((back, back_approximate), dirty), output) in cells.zip(outputs) {
+            let Some(output) = output else {
+                continue;
+            };
+            *back_approximate = output.approximate;
+            *dirty = output.dirty;
+            match output.block {
+                Some(block) => *back = block,
+                None if output.dirty => back.clone_from(&self.zero_block),
+                None => {}
             }
         }
 
         std::mem::swap(&mut self.back, &mut self.front);
+        std::mem::swap(&mut self.back_approximate, &mut self.approximate);
+        self.dirty = dirty;
         self.zero_borders = !self.zero_borders;
+        self.generation += 1;
+    }
+
+    /// Number of steps taken since the grid was created. Edits, `clear` and `restore` don't
+    /// change it.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Step the block at `idx` of `back`, or None if it can be skipped
+    fn step_one(&self, idx: (usize, usize), full: bool, zero_stable: bool) -> Option<StepOutput> {
+        let (i, j) = (idx.0 as i32, idx.1 as i32);
+        let (x, y) = if self.zero_borders {
+            (i - 1, j - 1)
+        } else {
+            (i, j)
+        };
+
+        let in_blocks = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)];
+
+        // Blocks outside the grid are always zero, so never dirty
+        let changed = full
+            || in_blocks.iter().any(|&uv| {
+                self.block_index(uv, self.boundary)
+                    .is_some_and(|(idx, _)| self.dirty[idx])
+            });
+        if !changed {
+            return None;
+        }
+
+        let empty = zero_stable
+            && in_blocks.iter().all(|&uv| {
+                self.block_index(uv, self.boundary)
+                    .is_none_or(|(idx, _)| self.front[idx].is_empty())
+            });
+        if empty {
+            return Some(StepOutput {
+                block: None,
+                approximate: false,
+                dirty: !self.back[idx].is_empty(),
+            });
+        }
+
+        let in_blocks = in_blocks.map(|uv| self.fetch_neighbor_block(uv, self.boundary));
+
+        let (out_block, result) = self.kernel.step_block(in_blocks);
+
+        Some(StepOutput {
+            approximate: matches!(result, KernelResult::Approximate),
+            dirty: out_block != self.back[idx],
+            block: Some(out_block),
+        })
     }
 
     /// Returns (width, height) in pixels
@@ -72,6 +576,86 @@ impl Dense {
         ((self.front.width() - 1) * w, (self.front.height() - 1) * w)
     }
 
+    pub fn order(&self) -> usize {
+        self.kernel.order()
+    }
+
+    /// Index into `front` of the block at `pos`, or None if it lies outside the grid and is
+    /// therefore dead. Also returns whether the block is seen mirrored in x and y.
+    ///
+    /// When wrapping, positions are taken modulo the visible width and height in blocks, so the
+    /// extra row and column (which only exist to hold the half-block overhang) are never read.
+    /// The half-block offset of alternate frames shifts which cells a block holds, but not the
+    /// period: in both phases blocks k and k + width cover the same cells of the torus. So a
+    /// neighbor across the seam is always one whole stored block, and never needs to be stitched
+    /// together from two.
+    ///
+    /// Mirroring works the same way, since reflecting a block about an edge falls on another
+    /// whole block; see `mirror_block`.
+    fn block_index(
+        &self,
+        (x, y): (i32, i32),
+        boundary: BoundaryMode,
+    ) -> Option<((usize, usize), (bool, bool))> {
+        let (width, height) = (self.front.width(), self.front.height());
+        match boundary {
+            BoundaryMode::Zero => {
+                let in_bounds = x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height;
+                in_bounds.then_some(((x as usize, y as usize), (false, false)))
+            }
+            BoundaryMode::Toroidal => {
+                let x = x.rem_euclid(width as i32 - 1);
+                let y = y.rem_euclid(height as i32 - 1);
+                Some(((x as usize, y as usize), (false, false)))
+            }
+            BoundaryMode::Mirror => {
+                let (x, flip_x) = mirror_block(x, width as i32 - 1, self.zero_borders);
+                let (y, flip_y) = mirror_block(y, height as i32 - 1, self.zero_borders);
+                Some(((x as usize, y as usize), (flip_x, flip_y)))
+            }
+        }
+    }
+
+    /// The block at `pos`, which may be outside the grid; see `block_index`
+    pub fn fetch_neighbor_block(&self, pos: (i32, i32), boundary: BoundaryMode) -> Block {
+        match self.block_index(pos, boundary) {
+            Some((idx, (false, false))) => self.front[idx].clone(),
+            Some((idx, (flip_x, flip_y))) => self.front[idx].flipped(flip_x, flip_y),
+            None => self.zero_block.clone(),
+        }
+    }
+
+    /// In the offset phase, the first and last blocks along each axis straddle the edge of the
+    /// visible area. When mirroring, overwrite their outer halves with the reflection of their
+    /// inner halves, since those cells are not stepped from anything the user can see or edit.
+    fn reflect_overhang(&mut self) {
+        let w = self.zero_block.width();
+        let (last_x, last_y) = (self.front.width() - 1, self.front.height() - 1);
+        // Cell p of an edge block, if outside, mirrors cell w - 1 - p of the same block
+        let reflect =
+            |p: usize, first: bool, last: bool| match (first && p < w / 2, last && p >= w / 2) {
+                (false, false) => p,
+                _ => w - 1 - p,
+            };
+        for by in 0..=last_y {
+            for bx in 0..=last_x {
+                let (first_x, first_y) = (bx == 0, by == 0);
+                let (end_x, end_y) = (bx == last_x, by == last_y);
+                if !(first_x || first_y || end_x || end_y) {
+                    continue;
+                }
+                let block = &self.front[(bx, by)];
+                let reflected = Block::from_fn(w, w, |px, py| {
+                    block[(reflect(px, first_x, end_x), reflect(py, first_y, end_y))]
+                });
+                if reflected != *block {
+                    self.front[(bx, by)] = reflected;
+                    self.dirty[(bx, by)] = true;
+                }
+            }
+        }
+    }
+
     fn index_block_pixel(&self, index: (usize, usize)) -> ((usize, usize), (usize, usize)) {
         let (mut x, mut y) = index;
 
@@ -84,37 +668,1183 @@ impl Dense {
             y += w / 2;
         }
 
-        ((x / w, y / w), (x % w, y % w))
+        let block_idx = (x / w, y / w);
+        let block_idx = match self.boundary {
+            // Pixels overhanging the right or bottom edge belong to the first column or row
+            BoundaryMode::Toroidal => (
+                block_idx.0 % (self.front.width() - 1),
+                block_idx.1 % (self.front.height() - 1),
+            ),
+            BoundaryMode::Zero | BoundaryMode::Mirror => block_idx,
+        };
+
+        (block_idx, (x % w, y % w))
     }
 
+    /// Panics if `index` is outside `pixel_dims`. Without this check, pixels past the edge would
+    /// land in the overhang blocks (or wrap into the first column when wrapping) and silently
+    /// alias other cells.
+    #[track_caller]
     pub fn get_pixel(&self, index: (usize, usize)) -> bool {
+        check_pixel(index, self.pixel_dims()).unwrap_or_else(|e| panic!("{e}"));
         let (block_idx, pixel_idx) = self.index_block_pixel(index);
         //dbg!(index, block_idx, pixel_idx);
         //dbg!();
         self.front[block_idx][pixel_idx]
     }
 
+    /// Copy a region of pixels into `out`; see `Simulation::copy_region`. Each row is copied a
+    /// block row at a time, rather than looking up every pixel's block.
+    pub fn copy_region(
+        &self,
+        min: (usize, usize),
+        size: (usize, usize),
+        out: &mut Array2D<bool>,
+    ) -> Result<(), Error> {
+        check_region(min, size, self.pixel_dims(), out)?;
+        let w = calc_block_width(&*self.kernel);
+        for y in 0..size.1 {
+            let row = &mut out.data_mut()[y * size.0..][..size.0];
+            let mut x = 0;
+            while x < size.0 {
+                let (block_idx, (px, py)) = self.index_block_pixel((min.0 + x, min.1 + y));
+                let run = (w - px).min(size.0 - x);
+                let block = &self.front[block_idx];
+                for (i, cell) in row[x..x + run].iter_mut().enumerate() {
+                    *cell = block.get((px + i, py));
+                }
+                x += run;
+            }
+        }
+        Ok(())
+    }
+
+    /// Panics if `index` is outside `pixel_dims`; see `get_pixel`
+    #[track_caller]
     pub fn set_pixel(&mut self, index: (usize, usize), val: bool) {
+        check_pixel(index, self.pixel_dims()).unwrap_or_else(|e| panic!("{e}"));
         let (block_idx, pixel_idx) = self.index_block_pixel(index);
-        self.front[block_idx][pixel_idx] = val;
+        if self.front[block_idx][pixel_idx] != val {
+            self.front[block_idx].set(pixel_idx, val);
+            self.recompute = 2;
+        }
+    }
+
+    /// Iterate the coordinates of all live pixels in row-major order, skipping empty blocks
+    pub fn live_cells(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        let (w, h) = self.pixel_dims();
+        let block_width = calc_block_width(&*self.kernel);
+
+        (0..h).flat_map(move |y| {
+            // Split the row into spans of pixels which share a block
+            let mut x = 0;
+            let spans = std::iter::from_fn(move || {
+                (x < w).then(|| {
+                    let (block_idx, (px, _)) = self.index_block_pixel((x, y));
+                    let end = (x + block_width - px).min(w);
+                    let span = x..end;
+                    x = end;
+                    (block_idx, span)
+                })
+            });
+
+            spans
+                .filter(move |(block_idx, _)| !self.front[*block_idx].is_empty())
+                .flat_map(move |(_, span)| span.filter(move |&x| self.get_pixel((x, y))))
+                .map(move |x| (x as i32, y as i32))
+        })
+    }
+
+    /// Number of live pixels within `pixel_dims`.
+    ///
+    /// The grid holds an extra row and column of blocks so that either phase covers the visible
+    /// area. Without wrapping, the half blocks hanging over the edges hold cells which have
+    /// spread past the visible area. The kernel still sees them, but they are padding, not part
+    /// of the pattern. When wrapping, the last row and column are stepped but never read back, and
+    /// every other cell is real.
+    ///
+    /// Blocks wholly inside the visible area are counted a word at a time, so this is cheap
+    /// enough to call every frame.
+    pub fn population(&self) -> usize {
+        let w = calc_block_width(&*self.kernel);
+        let (width, height) = self.pixel_dims();
+        let offset = if self.zero_borders { 0 } else { w / 2 };
+        let (blocks_x, blocks_y) = (self.front.width() - 1, self.front.height() - 1);
+        let wrap = self.wraps();
+
+        let mut population = 0;
+        for by in 0..self.front.height() {
+            for bx in 0..self.front.width() {
+                let block = &self.front[(bx, by)];
+                if block.is_empty() || (wrap && (bx == blocks_x || by == blocks_y)) {
+                    continue;
+                }
+                let (x0, y0) = (bx * w, by * w);
+                let inside = (x0 >= offset && x0 + w <= width + offset)
+                    && (y0 >= offset && y0 + w <= height + offset);
+                if wrap || inside {
+                    population += block.population();
+                    continue;
+                }
+                for py in 0..w {
+                    for px in 0..w {
+                        let x = (bx * w + px) as i64 - offset as i64;
+                        let y = (by * w + py) as i64 - offset as i64;
+                        let real =
+                            (0..width as i64).contains(&x) && (0..height as i64).contains(&y);
+                        if real && block[(px, py)] {
+                            population += 1;
+                        }
+                    }
+                }
+            }
+        }
+        population
+    }
+
+    pub fn clear(&mut self) {
+        for block in self.front.data_mut() {
+            block.clone_from(&self.zero_block);
+        }
+        self.clear_approximate();
+        self.recompute = 2;
+    }
+
+    /// Whether each block (indexed like `block`) was produced by a `KernelResult::Approximate`
+    /// in the last step. Blocks skipped because their inputs were unchanged keep the flag from
+    /// when they were computed. Cleared whenever the whole grid is replaced.
+    pub fn approximate_blocks(&self) -> &Array2D<bool> {
+        &self.approximate
+    }
+
+    /// `approximate_blocks` expanded to the visible pixels; see `Simulation::approximate_cells`
+    pub fn approximate_cells(&self) -> Array2D<bool> {
+        let (w, h) = self.pixel_dims();
+        let mut out = Array2D::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                let (block_idx, _) = self.index_block_pixel((x, y));
+                out[(x, y)] = self.approximate[block_idx];
+            }
+        }
+        out
+    }
+
+    fn clear_approximate(&mut self) {
+        self.approximate.data_mut().fill(false);
+        self.back_approximate.data_mut().fill(false);
+    }
+
+    /// Whether blocks are currently aligned to the pixel grid; see `Simulation::zero_borders`
+    pub fn zero_borders(&self) -> bool {
+        self.zero_borders
+    }
+
+    /// Capture the grid (including the overhanging blocks) and its phase, to `restore` later
+    pub fn save_state(&self) -> DenseState {
+        DenseState {
+            blocks: self.front.clone(),
+            zero_borders: self.zero_borders,
+        }
     }
 
+    /// Return to a saved state, which must come from a grid of the same size and kernel order
+    pub fn restore(&mut self, state: &DenseState) -> Result<(), Error> {
+        let dims = |blocks: &Array2D<Block>| (blocks.width(), blocks.height());
+        if dims(&state.blocks) != dims(&self.front) {
+            return Err(Error::ShapeMismatch {
+                expected: dims(&self.front),
+                found: dims(&state.blocks),
+            });
+        }
+        let w = self.zero_block.width();
+        let found = state.blocks.data()[0].width();
+        if found != w {
+            return Err(Error::ShapeMismatch {
+                expected: (w, w),
+                found: (found, found),
+            });
+        }
+
+        self.front.clone_from(&state.blocks);
+        self.zero_borders = state.zero_borders;
+        self.clear_approximate();
+        self.recompute = 2;
+        Ok(())
+    }
+
+    /// The visible cells as an image, each `scale` pixels wide, oriented as the app shows them;
+    /// see `capture::render_image`
+    pub fn to_image(&self, scale: usize) -> Array2D<[u8; 4]> {
+        crate::capture::render_image(&self.snapshot(), scale)
+    }
+
+    /// Copy `pattern` onto the grid with its top left at `at`, dead cells included, unlike
+    /// `Simulation::load_pattern`. Cells which fall outside `pixel_dims` are dropped.
+    pub fn stamp(&mut self, pattern: &Array2D<bool>, at: (i32, i32)) {
+        let (w, h) = self.pixel_dims();
+        let mut changed = false;
+        for y in 0..pattern.height() {
+            for x in 0..pattern.width() {
+                let (px, py) = (at.0 as i64 + x as i64, at.1 as i64 + y as i64);
+                if !(0..w as i64).contains(&px) || !(0..h as i64).contains(&py) {
+                    continue;
+                }
+                let (block_idx, pixel_idx) = self.index_block_pixel((px as usize, py as usize));
+                let val = pattern[(x, y)];
+                if self.front[block_idx][pixel_idx] != val {
+                    self.front[block_idx].set(pixel_idx, val);
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.recompute = 2;
+        }
+    }
+
+    /// Whether both grids show the same visible cells, even if they are in different phases
+    pub fn frames_equal(&self, other: &Dense) -> bool {
+        let (w, h) = self.pixel_dims();
+        self.pixel_dims() == other.pixel_dims()
+            && (0..h).all(|y| (0..w).all(|x| self.get_pixel((x, y)) == other.get_pixel((x, y))))
+    }
+
+    /// Re-block the visible area in the other phase. Without wrapping, cells which have spread
+    /// into the overhang past the visible area are dropped.
+    pub fn flip_phase(&mut self) {
+        let cells = self.snapshot();
+        self.clear();
+        self.zero_borders = !self.zero_borders;
+        self.for_each_pixel_mut(|pos, cell| *cell = cells[pos]);
+    }
+
+    /// The block at the given block index. Blocks cover (width + 1) x (height + 1) indices, and
+    /// on alternate steps are offset by half a block from the pixel grid, so prefer the pixel
+    /// accessors unless working with the kernel's view of the grid.
+    pub fn block(&self, index: (usize, usize)) -> &Block {
+        &self.front[index]
+    }
+
+    /// Mutable access to a block; see `block`
+    pub fn block_mut(&mut self, index: (usize, usize)) -> &mut Block {
+        self.recompute = 2;
+        &mut self.front[index]
+    }
+
+    /// Visit every visible pixel in row-major order, allowing it to be changed. Faster than
+    /// `set_pixel` for bulk edits such as seeding, since bounds are checked once.
+    pub fn for_each_pixel_mut(&mut self, mut f: impl FnMut((usize, usize), &mut bool)) {
+        let (w, h) = self.pixel_dims();
+        for y in 0..h {
+            for x in 0..w {
+                let (block_idx, pixel_idx) = self.index_block_pixel((x, y));
+                let block = &mut self.front[block_idx];
+                let mut cell = block.get(pixel_idx);
+                f((x, y), &mut cell);
+                block.set(pixel_idx, cell);
+            }
+        }
+        self.recompute = 2;
+    }
+
+    /// The raw block grid, including the overhanging row and column. Prefer `block_mut` or
+    /// `for_each_pixel_mut`, which don't depend on the layout.
     pub fn data_mut(&mut self) -> &mut Array2D<Block> {
+        self.recompute = 2;
         &mut self.front
     }
+
+    /// Build a grid with the same number of blocks for a kernel of possibly different order,
+    /// carrying the pattern over by nearest-neighbor sampling. The pixel grid scales with the
+    /// block size, so this is only exact when the orders match.
+    pub fn resample_into(&self, kernel: Box<dyn Kernel>) -> Result<Dense, Error> {
+        let mut out = Dense::try_new(kernel, self.front.width() - 1, self.front.height() - 1)?;
+        out.set_boundary(self.boundary);
+        out.set_threads(self.threads);
+
+        let (src_w, src_h) = self.pixel_dims();
+        let (dst_w, dst_h) = out.pixel_dims();
+        for y in 0..dst_h {
+            for x in 0..dst_w {
+                let src = (x * src_w / dst_w, y * src_h / dst_h);
+                if self.get_pixel(src) {
+                    out.set_pixel((x, y), true);
+                }
+            }
+        }
+
+        Ok(out)
+    }
 }
 
-fn get_block_zero_borders(arr: &Array2D<Block>, xy: (i32, i32)) -> Block {
-    let (x, y) = xy;
-    if x < 0 || y < 0 || x >= arr.width() as i32 || y >= arr.height() as i32 {
-        let mut out = arr[(0, 0)].clone();
-        out.data_mut().iter_mut().for_each(|x| *x = false);
-        out
+impl Simulation for Dense {
+    fn step(&mut self) {
+        Dense::step(self)
+    }
+
+    fn pixel_dims(&self) -> (usize, usize) {
+        Dense::pixel_dims(self)
+    }
+
+    fn order(&self) -> usize {
+        Dense::order(self)
+    }
+
+    fn get_pixel(&self, index: (usize, usize)) -> bool {
+        Dense::get_pixel(self, index)
+    }
+
+    fn set_pixel(&mut self, index: (usize, usize), val: bool) {
+        Dense::set_pixel(self, index, val)
+    }
+
+    fn clear(&mut self) {
+        Dense::clear(self)
+    }
+
+    fn zero_borders(&self) -> bool {
+        Dense::zero_borders(self)
+    }
+
+    fn flip_phase(&mut self) {
+        Dense::flip_phase(self)
+    }
+
+    fn wraps(&self) -> bool {
+        Dense::wraps(self)
+    }
+
+    fn for_each_pixel_mut(&mut self, f: &mut dyn FnMut((usize, usize), &mut bool)) {
+        Dense::for_each_pixel_mut(self, f)
+    }
+
+    fn copy_region(
+        &self,
+        min: (usize, usize),
+        size: (usize, usize),
+        out: &mut Array2D<bool>,
+    ) -> Result<(), Error> {
+        Dense::copy_region(self, min, size, out)
+    }
+
+    fn live_cells(&self) -> Box<dyn Iterator<Item = (i32, i32)> + '_> {
+        Box::new(Dense::live_cells(self))
+    }
+
+    fn population(&self) -> usize {
+        Dense::population(self)
+    }
+
+    fn approximate_cells(&self) -> Option<Array2D<bool>> {
+        Some(Dense::approximate_cells(self))
+    }
+
+    fn invalidate(&mut self) {
+        Dense::invalidate(self)
+    }
+}
+
+/// Check that a pixel lies within the given (width, height)
+fn check_pixel(pos: (usize, usize), dims: (usize, usize)) -> Result<(), Error> {
+    if pos.0 < dims.0 && pos.1 < dims.1 {
+        Ok(())
     } else {
-        arr[(x as usize, y as usize)].clone()
+        Err(Error::OutOfBounds { pos, dims })
     }
 }
 
+/// Check that a region of `size` pixels at `min` lies within `dims`, and that `out` fits it
+/// exactly
+fn check_region(
+    min: (usize, usize),
+    size: (usize, usize),
+    dims: (usize, usize),
+    out: &Array2D<bool>,
+) -> Result<(), Error> {
+    if (out.width(), out.height()) != size {
+        return Err(Error::ShapeMismatch {
+            expected: size,
+            found: (out.width(), out.height()),
+        });
+    }
+    let end = (min.0 + size.0, min.1 + size.1);
+    if size.0 > 0 && size.1 > 0 && (end.0 > dims.0 || end.1 > dims.1) {
+        return Err(Error::OutOfBounds {
+            pos: (end.0 - 1, end.1 - 1),
+            dims,
+        });
+    }
+    Ok(())
+}
+
+/// Number of blocks (width, height) needed to cover the given pixel dimensions, rounding up
+pub fn blocks_for_pixels(ker: &dyn Kernel, px_width: usize, px_height: usize) -> (usize, usize) {
+    let w = calc_block_width(ker);
+    (px_width.div_ceil(w), px_height.div_ceil(w))
+}
+
 pub fn calc_block_width(ker: &dyn Kernel) -> usize {
     1 << ker.order()
 }
+
+/// Wrap row-major cell data as a block for the given kernel, failing unless it holds exactly
+/// one block's worth of cells. For data from outside the crate, such as imported patterns.
+pub fn try_new_block(ker: &dyn Kernel, data: Vec<bool>) -> Result<Block, Error> {
+    let w = calc_block_width(ker);
+    if data.len() != w * w {
+        return Err(Error::BlockSize {
+            order: ker.order(),
+            expected: w * w,
+            found: data.len(),
+        });
+    }
+    Block::try_from_array(w, data)
+}
+
+/// Reflect block coordinate `i` back into the `n` visible blocks along one axis, returning it
+/// and whether it was reflected. Aligned blocks reflect about the boundary between two blocks,
+/// so block -1 mirrors block 0 and block n mirrors block n - 1. In the offset phase the edge
+/// runs through the middle of blocks 0 and n, so block -1 mirrors block 1 and block n + 1
+/// mirrors block n - 1.
+fn mirror_block(i: i32, n: i32, aligned: bool) -> (i32, bool) {
+    let (before, after) = match aligned {
+        true => (-1 - i, 2 * n - 1 - i),
+        false => (-i, 2 * n - i),
+    };
+    let last = if aligned { n - 1 } else { n };
+    if i < 0 {
+        (before, true)
+    } else if i > last {
+        (after, true)
+    } else {
+        (i, false)
+    }
+}
+
+/// Step one block in isolation, from four explicitly constructed input blocks arranged as for
+/// `Kernel::step_block`, without setting up a grid. For focused kernel tests and reproducing reported
+/// transitions. Panics if a block isn't the kernel's block size.
+#[track_caller]
+pub fn debug_step_blocks(ker: &dyn Kernel, blocks: [Block; 4]) -> Block {
+    let w = calc_block_width(ker);
+    for (i, block) in blocks.iter().enumerate() {
+        assert!(
+            (block.width(), block.height()) == (w, w),
+            "Input block {i} is {}x{}, but kernel order {} expects {w}x{w}:\n{block}",
+            block.width(),
+            block.height(),
+            ker.order(),
+        );
+    }
+    ker.step_block(blocks).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernels::{basic_mnca, tunable_mnca, KernelCache, LayeredKernel, Life};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::sync::Arc;
+
+    struct Huge;
+
+    /// Life, but claiming that every block with live cells was approximated
+    struct ApproximateLife;
+
+    impl Kernel for ApproximateLife {
+        fn order(&self) -> usize {
+            Life.order()
+        }
+
+        fn step_block(&self, blocks: [Block; 4]) -> (Block, KernelResult) {
+            let (out, _) = Life.step_block(blocks);
+            let result = match out.is_empty() {
+                true => KernelResult::NewBlock,
+                false => KernelResult::Approximate,
+            };
+            (out, result)
+        }
+    }
+
+    impl Kernel for Huge {
+        fn order(&self) -> usize {
+            10
+        }
+
+        fn step_block(&self, _blocks: [Block; 4]) -> (Block, KernelResult) {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_with_pixel_dims_rounds_up() {
+        let sim = Dense::with_pixel_dims(Box::new(Life), 7, 4);
+        assert_eq!(sim.pixel_dims(), (8, 4));
+    }
+
+    #[test]
+    fn test_load_pattern_pos() {
+        let glider = "#CXRLE Pos=-1,2\nx = 3, y = 3\nbo$2bo$3o!";
+        let pattern = crate::rle::parse_rle_pattern(glider).unwrap();
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+
+        let dropped = sim.load_pattern(&pattern, (4, 0));
+        assert_eq!(dropped, 0);
+        let mut cells: Vec<_> = sim.live_cells().collect();
+        cells.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(cells, [(4, 2), (5, 3), (3, 4), (4, 4), (5, 4)]);
+
+        // Hanging off the top-left corner
+        sim.clear();
+        assert_eq!(sim.load_pattern(&pattern, (0, -3)), 2);
+        assert_eq!(sim.population(), 3);
+    }
+
+    #[test]
+    fn test_birth_from_void() {
+        // A kernel which isn't zero stable must still be evaluated over empty regions
+        let void = LayeredKernel::new(|_, _| true, vec![Array2D::from_array(3, vec![true; 9])]);
+        let mut sim = Dense::new(Box::new(void), 4, 4);
+        sim.step();
+        assert!(sim.snapshot().is_full());
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let mut rng = StdRng::seed_from_u64(400);
+        let mut sim = Dense::new(Box::new(Life), 8, 8);
+        sim.for_each_pixel_mut(|_, cell| *cell = rng.gen_bool(0.4));
+        sim.step();
+
+        let saved = sim.save_state();
+        let mut expected = vec![];
+        for _ in 0..5 {
+            sim.step();
+            expected.push(sim.snapshot());
+        }
+
+        sim.restore(&saved).unwrap();
+        assert!(sim.save_state() == saved);
+        for frame in expected {
+            sim.step();
+            assert_eq!(sim.snapshot(), frame);
+        }
+
+        let mut other = Dense::new(Box::new(Life), 4, 8);
+        assert!(matches!(
+            other.restore(&saved),
+            Err(Error::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_frames_equal() {
+        let mut a = Dense::new(Box::new(Life), 4, 4);
+        let mut b = Dense::new(Box::new(Life), 4, 4);
+        for pos in [(1, 1), (2, 1), (3, 1)] {
+            a.set_pixel(pos, true);
+            b.set_pixel(pos, true);
+        }
+        b.flip_phase();
+        assert!(a.frames_equal(&b));
+
+        b.set_pixel((0, 0), true);
+        assert!(!a.frames_equal(&b));
+        assert!(!a.frames_equal(&Dense::new(Box::new(Life), 4, 5)));
+    }
+
+    #[test]
+    fn test_flip_phase() {
+        let glider = [(2, 1), (3, 2), (1, 3), (2, 3), (3, 3)];
+        let mut stepped = Dense::new(Box::new(Life), 8, 8);
+        for &pos in &glider {
+            stepped.set_pixel(pos, true);
+        }
+        let mut flipped = Dense::new(Box::new(Life), 8, 8);
+        for &pos in &glider {
+            flipped.set_pixel(pos, true);
+        }
+
+        flipped.flip_phase();
+        assert!(!flipped.zero_borders());
+        assert_eq!(flipped.snapshot(), stepped.snapshot());
+
+        // Life is exact, so the phase doesn't change the outcome
+        for _ in 0..6 {
+            stepped.step();
+            flipped.step();
+            assert_ne!(stepped.zero_borders(), flipped.zero_borders());
+            assert_eq!(flipped.snapshot(), stepped.snapshot());
+        }
+    }
+
+    #[test]
+    fn test_try_new_block() {
+        let block = try_new_block(&Life, vec![true, false, false, true]).unwrap();
+        assert_eq!((block.width(), block.height()), (2, 2));
+        assert!(block[(1, 1)]);
+
+        assert!(matches!(
+            try_new_block(&Life, vec![true; 8]),
+            Err(Error::BlockSize {
+                order: 1,
+                expected: 4,
+                found: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn test_debug_step_blocks() {
+        // A vertical blinker in the window's second column, whose horizontal phase crosses the
+        // output block, which is the middle of the window
+        let blocks = [
+            Block::from_rows(&["--", "-#"]),
+            Block::from_rows(&["--", "--"]),
+            Block::from_rows(&["-#", "-#"]),
+            Block::from_rows(&["--", "--"]),
+        ];
+        let out = debug_step_blocks(&Life, blocks);
+        assert_eq!(out, Block::from_rows(&["--", "##"]), "\n{out}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Input block 2 is 3x2")]
+    fn test_debug_step_blocks_wrong_size() {
+        let block = || Block::from_rows(&["--", "--"]);
+        debug_step_blocks(
+            &Life,
+            [block(), block(), Block::from_rows(&["---", "---"]), block()],
+        );
+    }
+
+    #[test]
+    fn test_unsupported_order() {
+        assert!(matches!(
+            Dense::try_new(Box::new(Huge), 1, 1),
+            Err(Error::UnsupportedOrder(UnsupportedOrder(10)))
+        ));
+    }
+
+    #[test]
+    fn test_dense_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Dense>();
+    }
+
+    #[test]
+    fn test_skip_unchanged_matches_naive() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut fast = Dense::new(Box::new(Life), 12, 12);
+        let mut naive = Dense::new(Box::new(Life), 12, 12);
+        naive.set_skip_unchanged(false);
+
+        // A block, a blinker and a glider, which leave most of the grid quiescent
+        let cells = [(2, 2), (3, 2), (2, 3), (3, 3), (10, 4), (10, 5), (10, 6)];
+        let glider = [(15, 20), (16, 20), (17, 20), (17, 21), (16, 22)];
+        for sim in [&mut fast, &mut naive] {
+            for &xy in cells.iter().chain(&glider) {
+                sim.set_pixel(xy, true);
+            }
+        }
+
+        for step in 0..40 {
+            // Edit mid-run, in both phases
+            if step % 13 == 5 {
+                let xy = (rng.gen_range(0..24), rng.gen_range(0..24));
+                fast.set_pixel(xy, true);
+                naive.set_pixel(xy, true);
+            }
+
+            fast.step();
+            naive.step();
+            assert_eq!(
+                fast.live_cells().collect::<Vec<_>>(),
+                naive.live_cells().collect::<Vec<_>>(),
+                "step {step}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_param_change_reaches_quiescent_blocks() {
+        let tuned = || {
+            let kernel = tunable_mnca();
+            let params = kernel.params().unwrap();
+            (Dense::new(Box::new(kernel), 3, 3), params)
+        };
+        let (mut fast, fast_params) = tuned();
+        let (mut naive, naive_params) = tuned();
+        naive.set_skip_unchanged(false);
+
+        // An empty grid stays empty, so after a couple of steps every block is skipped
+        for _ in 0..4 {
+            fast.step();
+            naive.step();
+        }
+
+        // Birth at an outer count of zero fills empty space
+        fast_params[0].set(0.0);
+        naive_params[0].set(0.0);
+        fast.invalidate();
+        for step in 0..4 {
+            fast.step();
+            naive.step();
+            assert_eq!(fast.snapshot(), naive.snapshot(), "step {step}");
+        }
+        assert!(fast.population() > 0);
+    }
+
+    #[test]
+    fn test_every_pixel_round_trips() {
+        // Check both frame phases, with and without wrapping
+        for (phase, wrap) in [(0, false), (1, false), (0, true), (1, true)] {
+            let mut sim = Dense::new(Box::new(Life), 3, 2);
+            sim.set_wrap(wrap);
+            for _ in 0..phase {
+                sim.step();
+            }
+
+            let (w, h) = sim.pixel_dims();
+            for y in 0..h {
+                for x in 0..w {
+                    sim.set_pixel((x, y), true);
+                    let cells: Vec<_> = sim.live_cells().collect();
+                    assert_eq!(cells, [(x as i32, y as i32)], "phase {phase} wrap {wrap}");
+                    sim.set_pixel((x, y), false);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_each_pixel_mut() {
+        let pattern = |(x, y): (usize, usize)| (3 * x + y) % 5 == 0;
+        for phase in 0..2 {
+            let mut sim = Dense::new(Box::new(Life), 3, 2);
+            for _ in 0..phase {
+                sim.step();
+            }
+
+            let mut visited = vec![];
+            sim.for_each_pixel_mut(|pos, cell| {
+                visited.push(pos);
+                *cell = pattern(pos);
+            });
+
+            let (w, h) = sim.pixel_dims();
+            let row_major: Vec<_> = (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).collect();
+            assert_eq!(visited, row_major);
+            for &pos in &row_major {
+                assert_eq!(sim.get_pixel(pos), pattern(pos), "phase {phase}");
+            }
+
+            // The provided implementation, which Sparse uses, agrees
+            let mut generic = crate::sparse::Sparse::new(Box::new(Life), 3, 2);
+            for _ in 0..phase {
+                generic.step();
+            }
+            generic.for_each_pixel_mut(&mut |pos, cell| *cell = pattern(pos));
+            assert_eq!(generic.snapshot(), sim.snapshot());
+        }
+    }
+
+    #[test]
+    fn test_pixel_out_of_bounds() {
+        let mut sim = Dense::new(Box::new(Life), 3, 2);
+        assert_eq!(sim.pixel_dims(), (6, 4));
+        assert_eq!(Simulation::try_get_pixel(&sim, (5, 3)), Some(false));
+        assert_eq!(Simulation::try_get_pixel(&sim, (6, 0)), None);
+        assert!(matches!(
+            Simulation::try_set_pixel(&mut sim, (0, 4), true),
+            Err(Error::OutOfBounds {
+                pos: (0, 4),
+                dims: (6, 4)
+            })
+        ));
+        assert_eq!(sim.live_cells().count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_pixel_out_of_bounds_panics() {
+        let mut sim = Dense::new(Box::new(Life), 3, 2);
+        // Would otherwise land in the overhang column
+        sim.set_pixel((6, 0), true);
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut sim = Dense::new(Box::new(Life), 2, 2);
+        for xy in [(1, 1), (1, 2), (1, 3)] {
+            sim.set_pixel(xy, true);
+        }
+        let start = sim.snapshot();
+        assert_eq!(start.width(), 4);
+
+        let frames: Vec<_> = Simulation::frames(&mut sim).take(2).collect();
+        assert_ne!(frames[0], start);
+        assert_eq!(frames[1], start);
+        assert!([(0, 2), (1, 2), (2, 2)].iter().all(|&xy| frames[0][xy]));
+    }
+
+    #[test]
+    fn test_run_and_capture() {
+        let mut sim = Dense::new(Box::new(Life), 2, 2);
+        for xy in [(1, 1), (1, 2), (1, 3)] {
+            sim.set_pixel(xy, true);
+        }
+        let start = sim.snapshot();
+
+        assert_eq!(sim.run_and_capture(0), start);
+        assert_ne!(sim.run_and_capture(1), start);
+        assert_eq!(sim.run_and_capture(3), start);
+    }
+
+    #[test]
+    fn test_run_and_capture_until() {
+        let mut sim = Dense::new(Box::new(Life), 2, 2);
+        for xy in [(1, 1), (1, 2), (1, 3)] {
+            sim.set_pixel(xy, true);
+        }
+        let start = sim.snapshot();
+
+        let cancel = AtomicBool::new(false);
+        assert_eq!(sim.run_and_capture_until(2, &cancel), (start.clone(), 2));
+
+        cancel.store(true, Ordering::Relaxed);
+        assert_eq!(sim.run_and_capture_until(1, &cancel), (start, 0));
+    }
+
+    #[test]
+    fn test_population_excludes_padding() {
+        for wrap in [false, true] {
+            // A glider heading for the bottom right corner, in a 16x16 grid
+            let mut sim = Dense::new(Box::new(Life), 8, 8);
+            sim.set_wrap(wrap);
+            for xy in [(12, 13), (13, 12), (11, 11), (12, 11), (13, 11)] {
+                sim.set_pixel(xy, true);
+            }
+            assert_eq!(sim.population(), 5);
+
+            // Check both phases as the glider crosses the edge (or seam)
+            let mut saw_padding = false;
+            for step in 0..24 {
+                assert_eq!(sim.generation(), step);
+                let visible = sim.snapshot().data().iter().filter(|&&c| c).count();
+                assert_eq!(sim.population(), visible, "step {step} wrap {wrap}");
+                if wrap {
+                    assert_eq!(sim.population(), 5, "step {step}");
+                }
+
+                let stored: usize = sim.front.data().iter().map(Block::population).sum();
+                saw_padding |= stored > sim.population();
+                sim.step();
+            }
+            // The overhang holds cells which spread past the edge, or when wrapping, stale copies
+            assert!(saw_padding, "wrap {wrap}");
+        }
+    }
+
+    fn sorted_cells(sim: &Dense) -> Vec<(i32, i32)> {
+        let mut cells: Vec<_> = sim.live_cells().collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn test_wrap_blinker_across_seam() {
+        // Check both frame phases
+        for phase in 0..2 {
+            let mut sim = Dense::new(Box::new(Life), 4, 4);
+            sim.set_wrap(true);
+            for _ in 0..phase {
+                sim.step();
+            }
+
+            // Horizontal blinker straddling the left/right edge
+            for xy in [(7, 3), (0, 3), (1, 3)] {
+                sim.set_pixel(xy, true);
+            }
+            sim.step();
+            assert_eq!(
+                sorted_cells(&sim),
+                [(0, 2), (0, 3), (0, 4)],
+                "phase {phase}"
+            );
+            sim.step();
+            assert_eq!(
+                sorted_cells(&sim),
+                [(0, 3), (1, 3), (7, 3)],
+                "phase {phase}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrap_glider_returns() {
+        let mut sim = Dense::with_boundary(Box::new(Life), 4, 4, BoundaryMode::Toroidal);
+        assert!(sim.wraps());
+        for xy in [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)] {
+            sim.set_pixel(xy, true);
+        }
+        let start = sorted_cells(&sim);
+
+        // A glider moves one cell diagonally every 4 generations, crossing both seams in both
+        // phases before coming back around the 8x8 torus
+        for step in 1..32 {
+            sim.step();
+            assert_eq!(sim.live_cells().count(), 5, "step {step}");
+        }
+        sim.step();
+        assert_eq!(sorted_cells(&sim), start);
+    }
+
+    #[test]
+    fn test_mirror_matches_doubled_torus() {
+        // Reflecting across every edge tiles the plane with period twice the grid, so a mirrored
+        // grid evolves like the top-left quarter of a torus holding four reflected copies
+        let mut rng = StdRng::seed_from_u64(504);
+        for phase in 0..2 {
+            let mut mirror = Dense::with_boundary(Box::new(Life), 3, 2, BoundaryMode::Mirror);
+            let mut torus = Dense::with_boundary(Box::new(Life), 6, 4, BoundaryMode::Toroidal);
+            for _ in 0..phase {
+                mirror.step();
+                torus.step();
+            }
+
+            let (w, h) = mirror.pixel_dims();
+            for y in 0..h {
+                for x in 0..w {
+                    let live = rng.gen_bool(0.4);
+                    mirror.set_pixel((x, y), live);
+                    for (tx, ty) in [(x, y), (2 * w - 1 - x, y), (x, 2 * h - 1 - y)] {
+                        torus.set_pixel((tx, ty), live);
+                    }
+                    torus.set_pixel((2 * w - 1 - x, 2 * h - 1 - y), live);
+                }
+            }
+
+            for step in 0..16 {
+                let quarter = Array2D::from_fn(w, h, |x, y| torus.get_pixel((x, y)));
+                assert_eq!(mirror.snapshot(), quarter, "phase {phase} step {step}");
+                mirror.step();
+                torus.step();
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_step_matches_serial() {
+        // Both grids share the cache, so it is also hit from several threads at once
+        let cache = Arc::new(KernelCache::new(Box::new(basic_mnca())));
+        let kernels: [Box<dyn Fn() -> Box<dyn Kernel>>; 2] = [
+            Box::new(|| Box::new(Life)),
+            Box::new(move || Box::new(cache.clone())),
+        ];
+        for kernel in kernels {
+            let mut rng = StdRng::seed_from_u64(510);
+            let mut serial = Dense::new(kernel(), 3, 4);
+            serial.for_each_pixel_mut(|_, cell| *cell = rng.gen_bool(0.3));
+            let mut parallel = Dense::new(kernel(), 3, 4);
+            parallel.restore(&serial.save_state()).unwrap();
+
+            // More threads than rows, an uneven split, and one band per row
+            for threads in [16, 2, 5] {
+                parallel.set_threads(threads);
+                for _ in 0..2 {
+                    serial.step();
+                    parallel.step();
+                    assert!(
+                        parallel.save_state() == serial.save_state(),
+                        "{threads} threads"
+                    );
+                    assert_eq!(parallel.approximate_blocks(), serial.approximate_blocks());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stamp_rle() {
+        let glider =
+            crate::rle::parse_rle("#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!").unwrap();
+        let blinker = crate::rle::parse_rle("x = 3, y = 1\n3o!").unwrap();
+
+        // In the offset phase, so the patterns straddle blocks
+        let mut sim = Dense::new(Box::new(Life), 4, 4);
+        sim.step();
+        sim.stamp(&glider, (1, 2));
+        assert_eq!(sorted_cells(&sim), [(1, 4), (2, 2), (2, 4), (3, 3), (3, 4)]);
+
+        // Dead cells of the pattern overwrite, and cells past the edge are dropped
+        sim.stamp(&blinker, (1, 4));
+        sim.stamp(&blinker, (6, 0));
+        assert_eq!(
+            sorted_cells(&sim),
+            [(1, 4), (2, 2), (2, 4), (3, 3), (3, 4), (6, 0), (7, 0)]
+        );
+        sim.stamp(&Array2D::new(3, 3), (1, 2));
+        assert_eq!(sorted_cells(&sim), [(6, 0), (7, 0)]);
+
+        // The clipped blinker dies, and the glider moves one cell diagonally, showing that the
+        // edit invalidated skipping
+        sim.stamp(&glider, (1, 2));
+        for _ in 0..4 {
+            sim.step();
+        }
+        assert_eq!(sorted_cells(&sim), [(2, 5), (3, 3), (3, 5), (4, 4), (4, 5)]);
+    }
+
+    #[test]
+    fn test_mirror_block() {
+        // Aligned, 3 visible blocks
+        assert_eq!(mirror_block(-1, 3, true), (0, true));
+        assert_eq!(mirror_block(2, 3, true), (2, false));
+        assert_eq!(mirror_block(3, 3, true), (2, true));
+        // Offset, where blocks 0 and 3 straddle the edges
+        assert_eq!(mirror_block(-1, 3, false), (1, true));
+        assert_eq!(mirror_block(3, 3, false), (3, false));
+        assert_eq!(mirror_block(4, 3, false), (2, true));
+    }
+
+    #[test]
+    fn test_resample_same_order_is_exact() {
+        let mut sim = Dense::new(Box::new(Life), 4, 3);
+        for xy in [(1, 1), (2, 1), (3, 1), (6, 4), (7, 5)] {
+            sim.set_pixel(xy, true);
+        }
+        // Leave the source in the offset phase
+        sim.step();
+
+        let copy = sim.resample_into(Box::new(Life)).unwrap();
+        assert_eq!(copy.pixel_dims(), sim.pixel_dims());
+        assert_eq!(
+            copy.live_cells().collect::<Vec<_>>(),
+            sim.live_cells().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_resample_scales_pattern() {
+        let mut sim = Dense::new(Box::new(Life), 2, 2);
+        sim.set_pixel((1, 2), true);
+
+        let bigger = sim
+            .resample_into(Box::new(crate::kernels::larger_than_life_layered_kernel()))
+            .unwrap();
+        assert_eq!(bigger.pixel_dims(), (32, 32));
+        let cells: Vec<_> = bigger.live_cells().collect();
+        assert_eq!(cells.len(), 8 * 8);
+        assert_eq!(cells[0], (8, 16));
+    }
+
+    #[test]
+    fn test_pixel_stays_put_without_stepping() {
+        // Odd coordinates fall in different blocks depending on the phase
+        for phase in 0..2 {
+            let mut sim = Dense::new(Box::new(basic_mnca()), 2, 2);
+            for _ in 0..phase {
+                sim.step();
+            }
+            sim.set_pixel((15, 17), true);
+            assert!(sim.get_pixel((15, 17)), "phase {phase}");
+            assert_eq!(sim.live_cells().collect::<Vec<_>>(), [(15, 17)]);
+            assert_eq!(sim.population(), 1);
+        }
+    }
+
+    #[test]
+    fn test_copy_region_matches_get_pixel() {
+        let mut rng = StdRng::seed_from_u64(403);
+        // Every phase, with regions starting and ending mid-block
+        for (phase, wrap) in [(0, false), (1, false), (0, true), (1, true)] {
+            let mut sim = Dense::new(Box::new(basic_mnca()), 3, 2);
+            sim.set_wrap(wrap);
+            for _ in 0..phase {
+                sim.step();
+            }
+            sim.for_each_pixel_mut(|_, cell| *cell = rng.gen_bool(0.5));
+
+            let (w, h) = sim.pixel_dims();
+            for (min, size) in [((0, 0), (w, h)), ((5, 9), (30, 17)), ((w - 1, 3), (1, 20))] {
+                let mut out = Array2D::new(size.0, size.1);
+                sim.copy_region(min, size, &mut out).unwrap();
+                for y in 0..size.1 {
+                    for x in 0..size.0 {
+                        let pos = (min.0 + x, min.1 + y);
+                        assert_eq!(out[(x, y)], sim.get_pixel(pos), "{pos:?} phase {phase}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_copy_region_errors() {
+        let sim = Dense::new(Box::new(Life), 2, 2);
+        let mut out = Array2D::new(2, 2);
+        assert!(matches!(
+            sim.copy_region((3, 0), (2, 2), &mut out),
+            Err(Error::OutOfBounds { pos: (4, 1), .. })
+        ));
+        assert!(matches!(
+            sim.copy_region((0, 0), (3, 2), &mut out),
+            Err(Error::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_approximate_cells() {
+        let mut sim = Dense::new(Box::new(ApproximateLife), 8, 8);
+        for pos in [(3, 3), (4, 3), (3, 4), (4, 4)] {
+            sim.set_pixel(pos, true);
+        }
+        assert!(!sim.approximate_cells().data().contains(&true));
+
+        // The block is a still life, so later steps skip its blocks and must keep their flags
+        for _ in 0..4 {
+            sim.step();
+            let approximate = sim.approximate_cells();
+            for (x, y) in sim.live_cells() {
+                assert!(approximate[(x as usize, y as usize)]);
+            }
+            let (w, h) = sim.pixel_dims();
+            assert!(!approximate[(w - 1, h - 1)]);
+            let flags = sim.approximate_blocks();
+            for j in 0..flags.height() {
+                for i in 0..flags.width() {
+                    assert_eq!(flags[(i, j)], !sim.block((i, j)).is_empty());
+                }
+            }
+        }
+
+        sim.clear();
+        assert!(!sim.approximate_cells().data().contains(&true));
+
+        let mut exact = Dense::new(Box::new(Life), 8, 8);
+        exact.set_pixel((3, 3), true);
+        exact.step();
+        assert!(!exact.approximate_cells().data().contains(&true));
+    }
+
+    #[test]
+    fn test_live_cells_row_major() {
+        let cells = [(5, 1), (0, 3), (2, 3), (7, 3), (6, 7)];
+
+        // Check both frame phases
+        for phase in 0..2 {
+            let mut sim = Dense::new(Box::new(Life), 4, 4);
+            for _ in 0..phase {
+                sim.step();
+            }
+
+            for &(x, y) in cells.iter().rev() {
+                sim.set_pixel((x as usize, y as usize), true);
+            }
+
+            assert_eq!(sim.live_cells().collect::<Vec<_>>(), cells);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use ahash::HashMap;
+
+use crate::{
+    error::Error,
+    sim::{blocks_for_pixels, calc_block_width, check_order, Block, Kernel, Simulation},
+};
+
+/// Unbounded simulation storing only the blocks which contain live cells.
+///
+/// Uses the same alternating half-block offset as `Dense`, but only evaluates blocks whose input
+/// neighborhood contains a live block. This assumes the kernel maps four empty blocks to an empty
+/// block; rules which produce life from nothing will behave differently than under `Dense`.
+pub struct Sparse {
+    blocks: HashMap<(i32, i32), Block>,
+    kernel: Box<dyn Kernel>,
+    zero_borders: bool,
+    zero_block: Block,
+    /// Size of the visible area in blocks
+    width: usize,
+    height: usize,
+}
+
+impl Sparse {
+    /// Create an empty simulation, with a visible area of width x height blocks.
+    /// Panics if the kernel's order exceeds `MAX_ORDER`; see `try_new`.
+    pub fn new(kernel: Box<dyn Kernel>, width: usize, height: usize) -> Self {
+        Self::try_new(kernel, width, height).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create an empty simulation, failing if the kernel's order exceeds `MAX_ORDER`
+    pub fn try_new(kernel: Box<dyn Kernel>, width: usize, height: usize) -> Result<Self, Error> {
+        check_order(kernel.order())?;
+        if !kernel.zero_stable() {
+            log::warn!("Kernel may give birth in empty space, which Sparse doesn't simulate");
+        }
+        let zero_block = Block::new(1 << kernel.order(), 1 << kernel.order());
+
+        Ok(Self {
+            blocks: Default::default(),
+            kernel,
+            zero_borders: true,
+            zero_block,
+            width,
+            height,
+        })
+    }
+
+    /// Create an empty simulation, whose visible area covers at least px_width x px_height pixels
+    /// (rounded up to whole blocks)
+    pub fn with_pixel_dims(kernel: Box<dyn Kernel>, px_width: usize, px_height: usize) -> Self {
+        let (width, height) = blocks_for_pixels(&*kernel, px_width, px_height);
+        Self::new(kernel, width, height)
+    }
+
+    pub fn step(&mut self) {
+        // Outputs read blocks (x - 1, y - 1)..=(x, y) with zero borders, otherwise (x, y)..=(x + 1, y + 1)
+        let offset = if self.zero_borders { 1 } else { -1 };
+
+        let mut candidates: Vec<(i32, i32)> = self
+            .blocks
+            .keys()
+            .flat_map(|&(x, y)| {
+                [
+                    (x, y),
+                    (x + offset, y),
+                    (x, y + offset),
+                    (x + offset, y + offset),
+                ]
+            })
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut next = HashMap::default();
+
+        for (i, j) in candidates {
+            let (x, y) = if self.zero_borders {
+                (i - 1, j - 1)
+            } else {
+                (i, j)
+            };
+
+            let in_blocks = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)];
+            let in_blocks = in_blocks.map(|xy| self.get_block(xy));
+
+            let (out_block, _) = self.kernel.step_block(in_blocks);
+
+            if !out_block.is_empty() {
+                next.insert((i, j), out_block);
+            }
+        }
+
+        self.blocks = next;
+        self.zero_borders = !self.zero_borders;
+    }
+
+    /// Returns (width, height) of the visible area in pixels
+    pub fn pixel_dims(&self) -> (usize, usize) {
+        let w = calc_block_width(&*self.kernel);
+        (self.width * w, self.height * w)
+    }
+
+    pub fn order(&self) -> usize {
+        self.kernel.order()
+    }
+
+    fn get_block(&self, xy: (i32, i32)) -> Block {
+        self.blocks.get(&xy).unwrap_or(&self.zero_block).clone()
+    }
+
+    fn index_block_pixel(&self, index: (i32, i32)) -> ((i32, i32), (usize, usize)) {
+        let (mut x, mut y) = index;
+
+        let w = calc_block_width(&*self.kernel) as i32;
+
+        // See Dense::index_block_pixel
+        if !self.zero_borders {
+            x += w / 2;
+            y += w / 2;
+        }
+
+        (
+            (x.div_euclid(w), y.div_euclid(w)),
+            (x.rem_euclid(w) as usize, y.rem_euclid(w) as usize),
+        )
+    }
+
+    pub fn get_pixel(&self, index: (i32, i32)) -> bool {
+        let (block_idx, pixel_idx) = self.index_block_pixel(index);
+        self.blocks
+            .get(&block_idx)
+            .map(|block| block[pixel_idx])
+            .unwrap_or(false)
+    }
+
+    pub fn set_pixel(&mut self, index: (i32, i32), val: bool) {
+        let (block_idx, pixel_idx) = self.index_block_pixel(index);
+        if let Some(block) = self.blocks.get_mut(&block_idx) {
+            block.set(pixel_idx, val);
+            if block.is_empty() {
+                self.blocks.remove(&block_idx);
+            }
+        } else if val {
+            let mut block = self.zero_block.clone();
+            block.set(pixel_idx, val);
+            self.blocks.insert(block_idx, block);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Whether blocks are currently aligned to the pixel grid; see `Simulation::zero_borders`
+    pub fn zero_borders(&self) -> bool {
+        self.zero_borders
+    }
+
+    /// Re-block every live cell, including those outside the visible area, in the other phase
+    pub fn flip_phase(&mut self) {
+        let cells: Vec<(i32, i32)> = self.live_cells().collect();
+        self.clear();
+        self.zero_borders = !self.zero_borders;
+        for pos in cells {
+            self.set_pixel(pos, true);
+        }
+    }
+
+    /// Iterate the coordinates of all live pixels, including those outside the visible area
+    pub fn live_cells(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        let w = calc_block_width(&*self.kernel) as i32;
+        let offset = if self.zero_borders { 0 } else { w / 2 };
+
+        let mut keys: Vec<(i32, i32)> = self.blocks.keys().copied().collect();
+        keys.sort_unstable_by_key(|&(x, y)| (y, x));
+
+        keys.into_iter().flat_map(move |(bx, by)| {
+            let block = &self.blocks[&(bx, by)];
+            (0..w).flat_map(move |py| {
+                (0..w)
+                    .filter(move |&px| block[(px as usize, py as usize)])
+                    .map(move |px| (bx * w + px - offset, by * w + py - offset))
+            })
+        })
+    }
+}
+
+impl Simulation for Sparse {
+    fn step(&mut self) {
+        Sparse::step(self)
+    }
+
+    fn pixel_dims(&self) -> (usize, usize) {
+        Sparse::pixel_dims(self)
+    }
+
+    fn order(&self) -> usize {
+        Sparse::order(self)
+    }
+
+    fn get_pixel(&self, (x, y): (usize, usize)) -> bool {
+        Sparse::get_pixel(self, (x as i32, y as i32))
+    }
+
+    fn set_pixel(&mut self, (x, y): (usize, usize), val: bool) {
+        Sparse::set_pixel(self, (x as i32, y as i32), val)
+    }
+
+    fn clear(&mut self) {
+        Sparse::clear(self)
+    }
+
+    fn zero_borders(&self) -> bool {
+        Sparse::zero_borders(self)
+    }
+
+    fn flip_phase(&mut self) {
+        Sparse::flip_phase(self)
+    }
+
+    fn live_cells(&self) -> Box<dyn Iterator<Item = (i32, i32)> + '_> {
+        Box::new(Sparse::live_cells(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kernels::Life, sim::Dense};
+
+    #[test]
+    fn test_sparse_matches_dense() {
+        // A glider well within the grid, so that Dense's borders don't interfere
+        let glider = [(3, 2), (4, 3), (2, 4), (3, 4), (4, 4)];
+
+        let mut dense = Dense::new(Box::new(Life), 8, 8);
+        let mut sparse = Sparse::new(Box::new(Life), 8, 8);
+        for &xy in &glider {
+            dense.set_pixel(xy, true);
+            Simulation::set_pixel(&mut sparse, xy, true);
+        }
+
+        for _ in 0..12 {
+            dense.step();
+            sparse.step();
+            let mut dense_cells: Vec<_> = dense.live_cells().collect();
+            let mut sparse_cells: Vec<_> = sparse.live_cells().collect();
+            dense_cells.sort();
+            sparse_cells.sort();
+            assert_eq!(dense_cells, sparse_cells);
+        }
+    }
+
+    #[test]
+    fn test_flip_phase_keeps_offscreen_cells() {
+        let mut sparse = Sparse::new(Box::new(Life), 2, 2);
+        let blinker = [(-10, -10), (-9, -10), (-8, -10)];
+        for &xy in &blinker {
+            sparse.set_pixel(xy, true);
+        }
+
+        sparse.flip_phase();
+        assert!(!sparse.zero_borders());
+        let mut cells: Vec<_> = sparse.live_cells().collect();
+        cells.sort();
+        assert_eq!(cells, blinker);
+    }
+
+    #[test]
+    fn test_sparse_unbounded() {
+        let mut sparse = Sparse::new(Box::new(Life), 2, 2);
+        let blinker = [(-10, -10), (-9, -10), (-8, -10)];
+        for &xy in &blinker {
+            sparse.set_pixel(xy, true);
+        }
+
+        sparse.step();
+        let mut cells: Vec<_> = sparse.live_cells().collect();
+        cells.sort();
+        assert_eq!(cells, [(-9, -11), (-9, -10), (-9, -9)]);
+
+        sparse.step();
+        let mut cells: Vec<_> = sparse.live_cells().collect();
+        cells.sort();
+        assert_eq!(cells, blinker);
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    analysis::{count_components, Fate, FateDetector},
+    array2d::Array2D,
+    error::Error,
+    events::Event,
+    kernels::{LayeredKernel, RuleBands},
+    sim::Dense,
+};
+
+/// Which bound of a band a sweep parameter controls
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Lo,
+    Hi,
+}
+
+/// A threshold to vary, and the values it takes
+#[derive(Clone, Debug)]
+pub struct SweepParam {
+    /// Index into `RuleBands::bands`
+    pub band: usize,
+    pub bound: Bound,
+    pub values: Vec<f32>,
+}
+
+/// Runs every combination of the parameters from the same seed
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub base: RuleBands,
+    pub layers: Vec<Array2D<bool>>,
+    pub params: Vec<SweepParam>,
+    pub seed: u64,
+    pub generations: usize,
+    /// Grid size in blocks
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Outcome of a single run
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepResult {
+    /// Parameter values, in the same order as `Sweep::params`
+    pub values: Vec<f32>,
+    pub population: usize,
+    pub components: usize,
+    pub fate: Fate,
+}
+
+impl Sweep {
+    /// Every combination of parameter values
+    pub fn combinations(&self) -> Vec<Vec<f32>> {
+        self.params.iter().fold(vec![vec![]], |acc, param| {
+            acc.iter()
+                .flat_map(|prefix| {
+                    param.values.iter().map(move |&value| {
+                        let mut combo = prefix.clone();
+                        combo.push(value);
+                        combo
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Bands with the given parameter values substituted
+    pub fn bands_for(&self, values: &[f32]) -> RuleBands {
+        let mut bands = self.base.clone();
+        for (param, &value) in self.params.iter().zip(values) {
+            let band = &mut bands.bands[param.band];
+            match param.bound {
+                Bound::Lo => band.lo = value,
+                Bound::Hi => band.hi = value,
+            }
+        }
+        bands
+    }
+
+    /// Run a single parameter set headlessly
+    pub fn run_one(&self, values: &[f32]) -> Result<SweepResult, Error> {
+        let result = self.run_one_until(values, &AtomicBool::new(false))?;
+        Ok(result.expect("Never cancelled"))
+    }
+
+    /// Run a single parameter set, returning None if `cancel` was set before it finished. The
+    /// flag is checked every generation.
+    pub fn run_one_until(
+        &self,
+        values: &[f32],
+        cancel: &AtomicBool,
+    ) -> Result<Option<SweepResult>, Error> {
+        let kernel = LayeredKernel::with_bands(self.bands_for(values), self.layers.clone())?;
+        let mut sim = Dense::try_new(Box::new(kernel), self.width, self.height)?;
+        Event::Seed(self.seed).apply(&mut sim);
+
+        let mut detector = FateDetector::new(64);
+        let mut fate = Fate::Evolving;
+        for _ in 0..self.generations {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            sim.step();
+            fate = detector.observe(&sim);
+            if fate == Fate::Extinct {
+                break;
+            }
+        }
+
+        Ok(Some(SweepResult {
+            values: values.to_vec(),
+            population: sim.population(),
+            components: count_components(&sim),
+            fate,
+        }))
+    }
+
+    /// Run every combination, spread across all cores. Results are in `combinations()` order.
+    pub fn run(&self) -> Result<Vec<SweepResult>, Error> {
+        self.run_until(&AtomicBool::new(false))
+    }
+
+    /// Like `run`, but stopping once `cancel` is set, e.g. from a Ctrl-C handler. Returns the
+    /// results of the runs which finished, in `combinations()` order; runs which were cut short
+    /// are left out, so results may be missing from the middle as well as the end.
+    pub fn run_until(&self, cancel: &AtomicBool) -> Result<Vec<SweepResult>, Error> {
+        let combos = self.combinations();
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let chunk_size = combos.len().div_ceil(threads).max(1);
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = combos
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|values| self.run_one_until(values, cancel))
+                            .filter_map(Result::transpose)
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                })
+                .collect();
+
+            let mut results = vec![];
+            for handle in handles {
+                results.extend(handle.join().expect("Sweep worker panicked")?);
+            }
+            Ok(results)
+        })
+    }
+
+    /// Write results as CSV, with one column per parameter
+    pub fn write_csv(&self, results: &[SweepResult], mut writer: impl Write) -> io::Result<()> {
+        for param in &self.params {
+            let bound = match param.bound {
+                Bound::Lo => "lo",
+                Bound::Hi => "hi",
+            };
+            write!(writer, "band{}_{},", param.band, bound)?;
+        }
+        writeln!(writer, "population,components,fate,spaceship")?;
+
+        for result in results {
+            for value in &result.values {
+                write!(writer, "{},", value)?;
+            }
+            let fate = match result.fate {
+                Fate::Evolving => "evolving".to_string(),
+                Fate::Extinct => "extinct".to_string(),
+                Fate::Static => "static".to_string(),
+                Fate::Periodic(n) => format!("period{}", n),
+                Fate::Translating { period, .. } => format!("translating{}", period),
+            };
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                result.population,
+                result.components,
+                fate,
+                u8::from(result.fate.is_spaceship())
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernels::{basic_mnca_bands, basic_mnca_layers};
+
+    fn example() -> Sweep {
+        Sweep {
+            base: basic_mnca_bands(),
+            layers: basic_mnca_layers(),
+            params: vec![
+                SweepParam {
+                    band: 0,
+                    bound: Bound::Lo,
+                    values: vec![0.2, 0.21],
+                },
+                SweepParam {
+                    band: 4,
+                    bound: Bound::Hi,
+                    values: vec![0.5, 0.55, 0.6],
+                },
+            ],
+            seed: 42,
+            generations: 3,
+            width: 3,
+            height: 3,
+        }
+    }
+
+    #[test]
+    fn test_combinations() {
+        let combos = example().combinations();
+        assert_eq!(combos.len(), 6);
+        assert_eq!(combos[0], [0.2, 0.5]);
+        assert_eq!(combos[5], [0.21, 0.6]);
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let sweep = example();
+        let serial: Vec<_> = sweep
+            .combinations()
+            .iter()
+            .map(|values| sweep.run_one(values).unwrap())
+            .collect();
+        assert_eq!(sweep.run().unwrap(), serial);
+
+        let mut csv = vec![];
+        sweep.write_csv(&serial, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 7);
+        assert!(csv.starts_with("band0_lo,band4_hi,population,components,fate,spaceship\n"));
+    }
+
+    #[test]
+    fn test_cancelled_sweep() {
+        let sweep = example();
+        let cancel = AtomicBool::new(true);
+        assert_eq!(sweep.run_until(&cancel).unwrap(), []);
+        assert_eq!(sweep.run_one_until(&[0.2, 0.5], &cancel).unwrap(), None);
+    }
+}
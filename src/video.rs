@@ -0,0 +1,204 @@
+//! Recording straight to a video file, by piping raw RGBA frames to an `ffmpeg` child process.
+//! Unlike numbered PNGs (see `capture`), nothing accumulates on disk but the video itself, so
+//! runs can be arbitrarily long.
+
+use std::{
+    ffi::OsString,
+    io::{ErrorKind, Read, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Sender},
+    thread::JoinHandle,
+};
+
+use crate::{array2d::Array2D, capture::render_to_rgba, error::Error, sim::Simulation};
+
+/// How the video is encoded. The container and codec follow from the file extension, as
+/// ffmpeg chooses them, e.g. `.mp4` or `.webm`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VideoSettings {
+    pub path: PathBuf,
+    pub fps: u32,
+    /// Each cell becomes `scale` x `scale` pixels. 0 is treated as 1.
+    pub scale: u32,
+    /// Program to run, `ffmpeg` on the PATH by default
+    pub ffmpeg: PathBuf,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            path: "capture.mp4".into(),
+            fps: 30,
+            scale: 1,
+            ffmpeg: "ffmpeg".into(),
+        }
+    }
+}
+
+impl VideoSettings {
+    /// Arguments reading `dims` sized RGBA frames from stdin. Output dimensions are padded to
+    /// even numbers, which yuv420p requires.
+    pub fn ffmpeg_args(&self, (width, height): (usize, usize)) -> Vec<OsString> {
+        let scale = self.scale.max(1);
+        let filter =
+            format!("scale=iw*{scale}:ih*{scale}:flags=neighbor,pad=ceil(iw/2)*2:ceil(ih/2)*2");
+        let mut args: Vec<OsString> = [
+            "-hide_banner",
+            "-loglevel",
+            "error",
+            "-y",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+            "-s",
+            &format!("{width}x{height}"),
+            "-r",
+            &self.fps.max(1).to_string(),
+            "-i",
+            "-",
+            "-vf",
+            &filter,
+            "-pix_fmt",
+            "yuv420p",
+        ]
+        .into_iter()
+        .map(Into::into)
+        .collect();
+        args.push(self.path.clone().into());
+        args
+    }
+}
+
+/// Streams frames to ffmpeg from a background thread, so that a slow encoder doesn't hold up
+/// the simulation. Every frame must have the dimensions the writer was started with.
+pub struct VideoWriter {
+    dims: (usize, usize),
+    sender: Sender<Array2D<bool>>,
+    writer: JoinHandle<Result<usize, Error>>,
+    captured: usize,
+}
+
+impl VideoWriter {
+    /// Start ffmpeg for frames of the given (width, height) in cells. Fails if it can't be run.
+    pub fn start(dims: (usize, usize), settings: &VideoSettings) -> Result<Self, Error> {
+        let mut child = Command::new(&settings.ffmpeg)
+            .args(settings.ffmpeg_args(dims))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => Error::Video(format!(
+                    "{} not found; install ffmpeg or put it on the PATH",
+                    settings.ffmpeg.display()
+                )),
+                _ => Error::Video(format!("couldn't start {}: {e}", settings.ffmpeg.display())),
+            })?;
+
+        let (sender, receiver) = mpsc::channel::<Array2D<bool>>();
+        let writer = std::thread::Builder::new()
+            .name("video writer".into())
+            .spawn(move || {
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                let mut written = 0;
+                for grid in receiver {
+                    if stdin.write_all(&render_to_rgba(&grid)).is_err() {
+                        // ffmpeg exited early; its error output says why
+                        break;
+                    }
+                    written += 1;
+                }
+                drop(stdin);
+                wait(child).map(|()| written)
+            })?;
+
+        Ok(Self {
+            dims,
+            sender,
+            writer,
+            captured: 0,
+        })
+    }
+
+    /// Queue the simulation's current state as the next frame. Fails if the grid has been
+    /// resized since the writer started.
+    pub fn record(&mut self, sim: &dyn Simulation) -> Result<(), Error> {
+        if sim.pixel_dims() != self.dims {
+            return Err(Error::ShapeMismatch {
+                expected: self.dims,
+                found: sim.pixel_dims(),
+            });
+        }
+        // A send only fails once the writer has stopped, which `finish` reports
+        let _ = self.sender.send(sim.snapshot());
+        self.captured += 1;
+        Ok(())
+    }
+
+    /// Number of frames queued so far
+    pub fn captured(&self) -> usize {
+        self.captured
+    }
+
+    /// Close the stream and wait for ffmpeg to finish the file, returning how many frames it
+    /// was sent
+    pub fn finish(self) -> Result<usize, Error> {
+        drop(self.sender);
+        self.writer
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+/// Wait for ffmpeg to exit, turning a failure into an error carrying its output
+fn wait(mut child: Child) -> Result<(), Error> {
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        // Best effort: the exit status still reports the failure if this doesn't
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Video(format!("ffmpeg {status}: {}", stderr.trim())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffmpeg_args() {
+        let settings = VideoSettings {
+            path: "out.webm".into(),
+            fps: 24,
+            scale: 3,
+            ..Default::default()
+        };
+        let args = settings.ffmpeg_args((64, 48));
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert!(args.windows(2).any(|w| w == ["-s", "64x48"]));
+        assert!(args.windows(2).any(|w| w == ["-r", "24"]));
+        assert!(args
+            .iter()
+            .any(|a| a.starts_with("scale=iw*3:ih*3:flags=neighbor")));
+        assert_eq!(args.last(), Some(&"out.webm"));
+    }
+
+    #[test]
+    fn test_missing_ffmpeg() {
+        let settings = VideoSettings {
+            ffmpeg: "mnca-no-such-ffmpeg".into(),
+            ..Default::default()
+        };
+        match VideoWriter::start((4, 4), &settings) {
+            Err(Error::Video(msg)) => assert!(msg.contains("not found"), "{msg}"),
+            Err(e) => panic!("unexpected error {e}"),
+            Ok(_) => panic!("started a missing program"),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Golden-frame regression tests: each built-in kernel steps a seeded grid, and the result must
+//! match a committed RLE file exactly. Run with `MNCA_UPDATE_GOLDEN=1` to rewrite the files after
+//! an intentional change in behavior.
+
+use std::path::PathBuf;
+
+use mnca_hashlife::{
+    basic_mnca, events::Event, larger_than_life_layered_kernel, life_layered_kernel, rle, Array2D,
+    Dense, Kernel, Life, Simulation,
+};
+
+const SEED: u64 = 0x5eed;
+
+/// Seed a grid of the given size in blocks, step it, and compare against `tests/golden/{name}.rle`
+fn check_golden(name: &str, kernel: Box<dyn Kernel>, blocks: usize, generations: usize) {
+    let mut sim = Dense::new(kernel, blocks, blocks);
+    Event::Seed(SEED).apply(&mut sim);
+    let frame = sim.run_and_capture(generations);
+
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("rle");
+    if std::env::var_os("MNCA_UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, rle::write_rle(&frame)).unwrap();
+        return;
+    }
+
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {e}; set MNCA_UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    let golden = rle::parse_rle(&text).unwrap();
+    assert_eq!(
+        (frame.width(), frame.height()),
+        (golden.width(), golden.height())
+    );
+    let differing = frame.diff(&golden).len();
+    assert_eq!(
+        differing, 0,
+        "{name}: {differing} cells differ from the golden frame"
+    );
+}
+
+#[test]
+fn golden_life() {
+    check_golden("life", Box::new(Life), 16, 32);
+}
+
+#[test]
+fn golden_life_layered() {
+    // Same rule as `Life`, so the golden frame is shared
+    check_golden("life", Box::new(life_layered_kernel()), 16, 32);
+}
+
+#[test]
+fn golden_larger_than_life() {
+    check_golden(
+        "larger_than_life",
+        Box::new(larger_than_life_layered_kernel()),
+        4,
+        8,
+    );
+}
+
+#[test]
+fn golden_basic_mnca() {
+    check_golden("basic_mnca", Box::new(basic_mnca()), 4, 8);
+}
+
+#[test]
+fn life_blinker_has_period_2() {
+    let mut sim = Dense::new(Box::new(Life), 4, 4);
+    for y in 3..6 {
+        sim.set_pixel((4, y), true);
+    }
+    let vertical = sim.snapshot();
+
+    let horizontal = sim.run_and_capture(1);
+    let mut expected = Array2D::new(8, 8);
+    for x in 3..6 {
+        expected[(x, 4)] = true;
+    }
+    assert_eq!(horizontal, expected);
+
+    assert_eq!(sim.run_and_capture(1), vertical);
+}